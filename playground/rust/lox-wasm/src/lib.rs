@@ -2,7 +2,7 @@ use std::fmt::{self, Display, Formatter};
 use std::io::{self, Write};
 
 use locks::diagnose::{Diagnoser, Diagnosis};
-use locks::error::report_error;
+use locks::error::{report_error, report_vm_error};
 use locks::syntax::parse;
 use locks::vm::{Compiler, Disassembler, Gc, VM};
 use serde::Serialize;
@@ -26,12 +26,13 @@ pub fn loxRun(source: &str) {
     console_error_panic_hook::set_once();
 
     let output = &mut Output::new();
-    match VM::default().run(source, output) {
+    let mut vm = VM::default();
+    match vm.run(source, output) {
         Ok(()) => postMessage(&Message::ExitSuccess.to_string()),
         Err(errors) => {
             let mut writer = HtmlWriter::new(output);
             for e in errors.iter() {
-                report_error(&mut writer, source, e);
+                report_vm_error(&mut writer, &vm, "<script>", e);
             }
             postMessage(&Message::ExitFailure.to_string());
         }
@@ -103,6 +103,11 @@ const Docs: React.FC = () => (
               String Concatentation
             </Link>
           </li>
+          <li className="nav-item">
+            <Link className="nav-link" to="#string-methods">
+              String Methods
+            </Link>
+          </li>
           <li className="nav-item">
             <Link className="nav-link" to="#functions">
               Functions
@@ -186,6 +191,11 @@ const Docs: React.FC = () => (
               instanceof
             </Link>
           </li>
+          <li className="nav-item">
+            <Link className="nav-link" to="#assert">
+              assert
+            </Link>
+          </li>
         </ul>
         <DocCard
           title="Example"
@@ -350,6 +360,31 @@ const Docs: React.FC = () => (
           Strings can be concatenated together using the <code>+</code>{' '}
           operator.
         </DocCard>
+        <DocCard
+          title="String Methods"
+          anchor="string-methods"
+          code={[
+            'let greeting = "  Hello World  ".trim();',
+            '',
+            'println(greeting[0]); // out: H',
+            'println(greeting.substring(0, 5)); // out: Hello',
+            'println(greeting.index_of("World")); // out: 6',
+            'println(greeting.upper()); // out: HELLO WORLD',
+            'println(greeting.replace("World", "Locks")); // out: Hello Locks',
+            'println(greeting.starts_with("Hello")); // out: true',
+            'println("-".join(greeting.split(" "))); // out: Hello-World',
+            'println("42".to_number() + 1); // out: 43',
+          ]}
+          height="200px"
+        >
+          Strings can be indexed to get a character and have the methods{' '}
+          <code>substring</code>, <code>index_of</code>, <code>split</code>,{' '}
+          <code>join</code>, <code>trim</code>, <code>upper</code>,{' '}
+          <code>lower</code>, <code>replace</code>, <code>starts_with</code>,{' '}
+          <code>ends_with</code> and <code>to_number</code>. Indexes count
+          characters, and <code>to_number</code> returns <code>nil</code> if
+          the string isn't a number.
+        </DocCard>
         <DocCard
           title="Functions"
           anchor="functions"
@@ -676,6 +711,27 @@ const Docs: React.FC = () => (
           class/super class.
         </DocCard>
 
+        <DocCard
+          title="assert"
+          anchor="assert"
+          code={[
+            'assert(1 < 2);',
+            'assert_eq(1 + 1, 2);',
+            'assert_ne("a", "b");',
+            '',
+            'try {',
+            '  assert_eq("a", "b");',
+            '} catch (e) {',
+            '  println(e.message); // out: expected "b" but got "a"',
+            '}',
+          ]}
+          height="250px"
+        >
+          Raise an <code>AssertionError</code> if a value is falsy, or if two
+          values are not equal (<code>assert_eq</code>) or are equal (
+          <code>assert_ne</code>).
+        </DocCard>
+
         <div className="shadow rounded p-3 vstack gap-3">
           <h2 id="errors">
             Errors{' '}
@@ -698,6 +754,11 @@ const Docs: React.FC = () => (
                 IoError
               </Link>
             </li>
+            <li className="nav-item">
+              <Link className="nav-link" to="#errors--memory-error">
+                MemoryError
+              </Link>
+            </li>
             <li className="nav-item">
               <Link className="nav-link" to="#errors--name-error">
                 NameError
@@ -780,6 +841,32 @@ const Docs: React.FC = () => (
             />
           </div>
 
+          <div className="vstack gap-3">
+            <h3 id="errors--memory-error">
+              MemoryError{' '}
+              <Link to="#errors--memory-error">
+                <span
+                  className="me-1 bi bi-link-45deg link-secondary align-text-bottom"
+                  role="img"
+                  aria-hidden="true"
+                />
+              </Link>
+            </h3>
+
+            <ErrorDoc
+              errorName="HeapLimitExceeded"
+              signature="(limit: usize)"
+              description={
+                <p>
+                  The heap grew past the VM&apos;s <code>max_heap_bytes</code>{' '}
+                  even after collecting garbage. It can be caught with{' '}
+                  <code>try</code>.
+                </p>
+              }
+              id="errors--memory-error--heap-limit-exceeded"
+            />
+          </div>
+
           <div className="vstack gap-3">
             <h3 id="errors--name-error">
               NameError{' '}
@@ -1,27 +1,118 @@
-use std::fs;
 use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::{env, fs, iter};
 
 use anyhow::{bail, Context, Result};
 use clap::Parser;
 
 use crate::error::ErrorS;
-use crate::vm::{Compiler, Disassembler, Gc, VM};
+use crate::lint::{self, Rule, Rules};
+use crate::vm::{bytecode, Compiler, Disassembler, Gc, GcStats, VM};
+use crate::{snapshot, test_runner};
 
 #[derive(Debug, Parser)]
 #[command(about, author, disable_help_subcommand = true, propagate_version = true, version)]
 pub enum Cmd {
+    /// Serve the Debug Adapter Protocol over stdio
+    Dap,
     Lsp,
     Repl,
-    Run { path: String },
-    Exec { source: Option<String> },
-    Parse { path: String },
-    Disassemble { path: String },
+    Run {
+        path: String,
+        /// Print what the garbage collector did to stderr when the program
+        /// exits
+        #[arg(long)]
+        gc_stats: bool,
+        /// Write a heap snapshot here when the program exits, or each time it
+        /// calls `heap_snapshot()` with a number before the extension (e.g.
+        /// `out.1.json`). Files ending in `.heapsnapshot` can be loaded by
+        /// Chrome DevTools; anything else is written as JSON.
+        #[arg(long, value_name = "PATH")]
+        heap_snapshot: Option<PathBuf>,
+    },
+    /// Compile a file to bytecode that `run` can load without parsing it
+    Compile {
+        path: String,
+        /// Where to write the bytecode. Defaults to the path with a `.lockc`
+        /// extension.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    Exec {
+        source: Option<String>,
+    },
+    /// Report errors and lint warnings in files, or the `.locks` files in
+    /// directories, without running them
+    Check {
+        /// Turn off a lint rule (e.g. `--allow unused-variable`)
+        #[arg(short = 'A', long, value_name = "RULE")]
+        allow: Vec<Rule>,
+        /// Fail if there are any warnings
+        #[arg(long)]
+        deny_warnings: bool,
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Format files, or the `.locks` files in directories, in place
+    Fmt {
+        /// List the files that aren't formatted instead of formatting them
+        #[arg(long)]
+        check: bool,
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Run the `test_*` functions in files, or the `.locks` files in
+    /// directories, each in a new VM
+    Test {
+        /// Also write the results as JUnit XML to this file
+        #[arg(long, value_name = "PATH")]
+        junit: Option<String>,
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Run scripts and compare what they print to their `// out:` and
+    /// `// err:` comments
+    Snapshot {
+        /// Rewrite the comments to match what the scripts print
+        #[arg(long)]
+        update: bool,
+        /// Files to run (e.g. `tests/**/*.locks`). Directories are searched
+        /// for `.locks` files.
+        #[arg(required = true)]
+        patterns: Vec<String>,
+    },
+    /// Run a file in an interactive debugger that stops on the first line
+    Debug {
+        path: String,
+    },
+    /// Run a file and report the time spent in each Locks function and line
+    Profile {
+        /// Also write the time spent in each call stack to this file, in the
+        /// folded format that flamegraph tools read
+        #[arg(long, value_name = "PATH")]
+        folded: Option<String>,
+        /// How many functions & lines to report
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        path: String,
+    },
+    Parse {
+        path: String,
+    },
+    Disassemble {
+        path: String,
+    },
 }
 
 impl Cmd {
     pub fn run(&self) -> Result<()> {
         #[allow(unused_variables)]
         match self {
+            #[cfg(feature = "dap")]
+            Cmd::Dap => crate::dap::serve(),
+            #[cfg(not(feature = "dap"))]
+            Cmd::Dap => bail!("locks was not compiled with the dap feature"),
+
             #[cfg(feature = "lsp")]
             Cmd::Lsp => crate::lsp::serve(),
             #[cfg(not(feature = "lsp"))]
@@ -32,25 +123,81 @@ impl Cmd {
             #[cfg(not(feature = "repl"))]
             Cmd::Repl => bail!("locks was not compiled with the repl feature"),
 
-            Cmd::Run { path } => {
-                let source = fs::read_to_string(path)
-                    .with_context(|| format!("could not read file: {path}"))?;
+            Cmd::Run { path, gc_stats, heap_snapshot } => {
                 let stdout = &mut io::stdout().lock();
                 let mut vm = VM::new();
-                if let Err(e) = vm.run(&source, stdout) {
-                    report_err(&source, e);
+                vm.add_package_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+                add_env_package_paths(&mut vm);
+                if let Some(heap_snapshot) = heap_snapshot {
+                    vm.set_heap_snapshot_path(heap_snapshot);
+                }
+
+                let result = if is_bytecode(path) {
+                    let bytes =
+                        fs::read(path).with_context(|| format!("could not read file: {path}"))?;
+                    vm.run_bytecode(&bytes, stdout)
+                } else {
+                    let source = fs::read_to_string(path)
+                        .with_context(|| format!("could not read file: {path}"))?;
+                    vm.run(&source, stdout)
+                };
+
+                if *gc_stats {
+                    report_gc_stats(&vm.gc_stats());
+                }
+                if let Err(e) = &result {
+                    crate::error::report_vm_errors(&mut io::stderr().lock(), &vm, path, e);
+                }
+                if let Some(heap_snapshot) = heap_snapshot {
+                    if vm.heap_snapshots_written() == 0 {
+                        vm.heap_snapshot().save(heap_snapshot).with_context(|| {
+                            format!("could not write heap snapshot: {}", heap_snapshot.display())
+                        })?;
+                    }
+                }
+                if result.is_err() {
                     bail!("program exited with errors");
                 }
                 Ok(())
             }
 
+            Cmd::Compile { path, output } => {
+                let source = fs::read_to_string(path)
+                    .with_context(|| format!("could not read file: {path}"))?;
+
+                let mut gc = Gc::default();
+                let bytes = match bytecode::compile(&source, &mut gc) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        report_err(path, &source, e);
+                        bail!("program has errors");
+                    }
+                };
+
+                let output = match output {
+                    Some(output) => PathBuf::from(output),
+                    None => Path::new(path).with_extension("lockc"),
+                };
+                fs::write(&output, bytes)
+                    .with_context(|| format!("could not write file: {}", output.display()))?;
+
+                Ok(())
+            }
+
             Cmd::Exec { source } => match source {
                 Some(source) => {
                     let stdout = &mut io::stdout().lock();
                     let mut vm = VM::new();
+                    vm.add_package_path(".");
+                    add_env_package_paths(&mut vm);
 
                     if let Err(e) = vm.run(source, stdout) {
-                        report_err(source, e);
+                        crate::error::report_vm_errors(
+                            &mut io::stderr().lock(),
+                            &vm,
+                            "<script>",
+                            &e,
+                        );
                         bail!("program exited with errors");
                     }
                     Ok(())
@@ -63,9 +210,16 @@ impl Cmd {
 
                     let stdout = &mut io::stdout().lock();
                     let mut vm = VM::new();
+                    vm.add_package_path(".");
+                    add_env_package_paths(&mut vm);
 
                     if let Err(e) = vm.run(&source, stdout) {
-                        report_err(&source, e);
+                        crate::error::report_vm_errors(
+                            &mut io::stderr().lock(),
+                            &vm,
+                            "<script>",
+                            &e,
+                        );
                         bail!("program exited with errors");
                     }
 
@@ -73,6 +227,244 @@ impl Cmd {
                 }
             },
 
+            Cmd::Check { allow, deny_warnings, paths } => {
+                let mut rules = Rules::default();
+                for rule in allow {
+                    rules.allow(*rule);
+                }
+
+                let mut files = Vec::new();
+                for path in paths {
+                    locks_files(Path::new(path), &mut files)
+                        .with_context(|| format!("could not read path: {path}"))?;
+                }
+
+                let mut error_count = 0;
+                let mut warning_count = 0;
+                for file in files {
+                    let source = fs::read_to_string(&file)
+                        .with_context(|| format!("could not read file: {}", file.display()))?;
+
+                    let (errors, lints) = match crate::syntax::parse(&source, 0) {
+                        Ok(program) => {
+                            let errors = Compiler::compile(&program, &mut Gc::default())
+                                .err()
+                                .unwrap_or_default();
+                            (errors, lint::lint(&source, &program, &rules))
+                        }
+                        Err(errors) => (errors, Vec::new()),
+                    };
+                    if errors.is_empty() && lints.is_empty() {
+                        continue;
+                    }
+
+                    error_count += errors.len();
+                    warning_count += lints.len();
+
+                    eprintln!("{}", file.display());
+                    report_err(&file.display().to_string(), &source, errors);
+                    let mut buffer = termcolor::Buffer::ansi();
+                    for lint in &lints {
+                        lint::report_lint(&mut buffer, &source, lint);
+                    }
+                    io::stderr().write_all(buffer.as_slice()).expect("failed to write to stderr");
+                }
+
+                if error_count > 0 {
+                    bail!("found {error_count} error(s) and {warning_count} warning(s)");
+                }
+                if *deny_warnings && warning_count > 0 {
+                    bail!("found {warning_count} warning(s)");
+                }
+                Ok(())
+            }
+
+            Cmd::Fmt { check, paths } => {
+                let mut files = Vec::new();
+                for path in paths {
+                    locks_files(Path::new(path), &mut files)
+                        .with_context(|| format!("could not read path: {path}"))?;
+                }
+
+                let mut has_errors = false;
+                let mut unformatted = Vec::new();
+                for file in files {
+                    let source = fs::read_to_string(&file)
+                        .with_context(|| format!("could not read file: {}", file.display()))?;
+                    let formatted = match crate::syntax::format::format(&source) {
+                        Ok(formatted) => formatted,
+                        Err(e) => {
+                            eprintln!("could not format file: {}", file.display());
+                            report_err(&file.display().to_string(), &source, e);
+                            has_errors = true;
+                            continue;
+                        }
+                    };
+                    if formatted == source {
+                        continue;
+                    }
+                    if *check {
+                        println!("{}", file.display());
+                        unformatted.push(file);
+                    } else {
+                        fs::write(&file, formatted)
+                            .with_context(|| format!("could not write file: {}", file.display()))?;
+                    }
+                }
+
+                if has_errors {
+                    bail!("some files could not be formatted");
+                }
+                if !unformatted.is_empty() {
+                    bail!("{} file(s) are not formatted", unformatted.len());
+                }
+                Ok(())
+            }
+
+            Cmd::Test { junit, paths } => {
+                let mut files = Vec::new();
+                for path in paths {
+                    locks_files(Path::new(path), &mut files)
+                        .with_context(|| format!("could not read path: {path}"))?;
+                }
+
+                let env_paths = env_package_paths();
+
+                let stdout = &mut io::stdout().lock();
+                let mut reports = Vec::new();
+                for file in files {
+                    let source = fs::read_to_string(&file)
+                        .with_context(|| format!("could not read file: {}", file.display()))?;
+                    let dir = file.parent().unwrap_or(Path::new(".")).to_path_buf();
+                    let package_paths =
+                        iter::once(dir).chain(env_paths.clone()).collect::<Vec<_>>();
+
+                    let report = test_runner::run_file(&file, &source, &package_paths);
+                    if !report.errors.is_empty() {
+                        writeln!(stdout, "{} ... ERROR", file.display())?;
+                    }
+                    for test in &report.tests {
+                        let result = if test.failure.is_none() { "ok" } else { "FAILED" };
+                        writeln!(stdout, "test {}::{} ... {result}", file.display(), test.name)?;
+                    }
+                    reports.push(report);
+                }
+
+                let mut buffer = termcolor::Buffer::ansi();
+                for report in &reports {
+                    let path = report.path.display().to_string();
+                    for error in &report.errors {
+                        writeln!(buffer, "\n---- {path} ----")?;
+                        crate::error::report_error_in(&mut buffer, &path, &report.source, error);
+                    }
+                    for test in &report.tests {
+                        let Some(failure) = &test.failure else {
+                            continue;
+                        };
+                        writeln!(buffer, "\n---- {path}::{} ----", test.name)?;
+                        buffer.write_all(test.output.as_bytes())?;
+                        failure.report(&mut buffer, &report.path);
+                    }
+                }
+                stdout.write_all(buffer.as_slice())?;
+
+                if let Some(junit) = junit {
+                    fs::write(junit, test_runner::junit(&reports))
+                        .with_context(|| format!("could not write file: {junit}"))?;
+                }
+
+                let passed = reports.iter().map(|report| report.passed()).sum::<usize>();
+                let failed = reports.iter().map(|report| report.failed()).sum::<usize>();
+                let errors = reports.iter().filter(|report| !report.errors.is_empty()).count();
+                let result = if failed + errors == 0 { "ok" } else { "FAILED" };
+                writeln!(stdout, "\ntest result: {result}. {passed} passed; {failed} failed")?;
+
+                if failed + errors > 0 {
+                    bail!("{failed} test(s) failed and {errors} file(s) could not be loaded");
+                }
+                Ok(())
+            }
+
+            Cmd::Snapshot { update, patterns } => {
+                let mut files = Vec::new();
+                for pattern in patterns {
+                    let paths = glob::glob(pattern)
+                        .with_context(|| format!("invalid pattern: {pattern}"))?
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if paths.is_empty() {
+                        bail!("no files match: {pattern}");
+                    }
+                    for path in paths {
+                        locks_files(&path, &mut files)
+                            .with_context(|| format!("could not read path: {}", path.display()))?;
+                    }
+                }
+
+                let env_paths = env_package_paths();
+
+                let stdout = &mut io::stdout().lock();
+                let mut failed = Vec::new();
+                for file in &files {
+                    let source = fs::read_to_string(file)
+                        .with_context(|| format!("could not read file: {}", file.display()))?;
+                    let dir = file.parent().unwrap_or(Path::new(".")).to_path_buf();
+                    let package_paths =
+                        iter::once(dir).chain(env_paths.clone()).collect::<Vec<_>>();
+
+                    let expected = snapshot::Output::expected(&source);
+                    let actual = snapshot::run(&source, &package_paths);
+                    let result = if actual.matches(&expected) {
+                        "ok"
+                    } else if *update {
+                        fs::write(file, snapshot::update(&source, &actual))
+                            .with_context(|| format!("could not write file: {}", file.display()))?;
+                        "updated"
+                    } else {
+                        failed.push((file, expected, actual));
+                        "FAILED"
+                    };
+                    writeln!(stdout, "snapshot {} ... {result}", file.display())?;
+                }
+
+                for (file, expected, actual) in &failed {
+                    writeln!(stdout, "\n---- {} ----", file.display())?;
+                    if expected.stdout != actual.stdout {
+                        writeln!(stdout, "expected stdout:\n{}", expected.stdout)?;
+                        writeln!(stdout, "actual stdout:\n{}", actual.stdout)?;
+                    }
+                    if expected.stderr != actual.stderr {
+                        writeln!(stdout, "expected stderr:\n{}", expected.stderr)?;
+                        writeln!(stdout, "actual stderr:\n{}", actual.stderr)?;
+                    }
+                }
+
+                if !failed.is_empty() {
+                    bail!("{} of {} snapshot(s) failed", failed.len(), files.len());
+                }
+                Ok(())
+            }
+
+            Cmd::Debug { path } => crate::debugger::run(path),
+
+            Cmd::Profile { folded, limit, path } => {
+                crate::profiler::run(path, folded.as_deref(), *limit)
+            }
+
+            Cmd::Disassemble { path } if is_bytecode(path) => {
+                let bytes =
+                    fs::read(path).with_context(|| format!("could not read file: {path}"))?;
+
+                let mut gc = Gc::default();
+                let function = bytecode::deserialize(&bytes, 0)
+                    .and_then(|script| script.function(&mut gc))
+                    .with_context(|| format!("could not load bytecode: {path}"))?;
+
+                let chunk = unsafe { &(*function).chunk };
+                println!("{}", Disassembler::new(chunk).disassemble(None));
+
+                Ok(())
+            }
+
             Cmd::Disassemble { path } => {
                 let source = fs::read_to_string(path)
                     .with_context(|| format!("could not read file: {path}"))?;
@@ -122,10 +514,66 @@ impl Cmd {
     }
 }
 
-fn report_err(source: &str, errors: Vec<ErrorS>) {
+/// Compiled bytecode files are recognized by their `.lockc` extension
+fn is_bytecode(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension == "lockc")
+}
+
+/// Collect `path` if it's a file, or the `.locks` files under it if it's a
+/// directory
+fn locks_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries =
+        fs::read_dir(path)?.map(|entry| Ok(entry?.path())).collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            locks_files(&entry, files)?;
+        } else if entry.extension().is_some_and(|extension| extension == "locks") {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// The directories listed in the `LOCKS_PATH` environment variable, which
+/// are searched for packages after the importing file's directory
+pub(crate) fn env_package_paths() -> Vec<PathBuf> {
+    env::var_os("LOCKS_PATH").map(|paths| env::split_paths(&paths).collect()).unwrap_or_default()
+}
+
+/// Add the directories listed in the `LOCKS_PATH` environment variable as package paths
+pub(crate) fn add_env_package_paths(vm: &mut VM) {
+    for path in env_package_paths() {
+        vm.add_package_path(path);
+    }
+}
+
+fn report_gc_stats(stats: &GcStats) {
+    let pauses = &stats.pauses;
+    eprintln!("gc collections: {}", stats.collections);
+    eprintln!(
+        "gc pauses: {} totalling {:?} (max {:?}, mean {:?})",
+        pauses.count,
+        pauses.total,
+        pauses.max,
+        pauses.mean()
+    );
+    eprintln!("gc freed: {} objects, {} bytes", stats.objects_freed, stats.bytes_freed);
+    eprintln!("gc heap: {} bytes", stats.allocated_bytes);
+    for (type_, count) in &stats.objects {
+        eprintln!("  {:<12} {count}", format!("{type_:?}"));
+    }
+}
+
+fn report_err(name: &str, source: &str, errors: Vec<ErrorS>) {
     let mut buffer = termcolor::Buffer::ansi();
     for err in errors {
-        crate::error::report_error(&mut buffer, source, &err);
+        crate::error::report_error_in(&mut buffer, name, source, &err);
     }
     io::stderr().write_all(buffer.as_slice()).expect("failed to write to stderr");
 }
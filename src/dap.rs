@@ -0,0 +1,434 @@
+#![cfg(feature = "dap")]
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Read, StdinLock, Stdout, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::rc::Rc;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value as Json};
+
+use crate::vm::debug::{Breakpoints, DebugHook, Debugger, Location, SourceName, Step, StopReason};
+use crate::vm::VM;
+
+/// The VM only runs one thread
+const THREAD_ID: i64 = 1;
+
+/// Variables reference of the globals scope. Each frame's locals scope is
+/// its index plus this.
+const GLOBALS_REFERENCE: i64 = 1;
+
+/// Serve the Debug Adapter Protocol over stdio
+pub fn serve() -> Result<()> {
+    let conn = Rc::new(RefCell::new(Connection {
+        input: io::stdin().lock(),
+        output: io::stdout(),
+        seq: 0,
+    }));
+
+    let mut program = None;
+    let mut stop_on_entry = false;
+    let mut pending = HashMap::new();
+    let mut is_configured = false;
+
+    // Breakpoints can be set before the program is launched, so they're
+    // kept by path until the program is known
+    while program.is_none() || !is_configured {
+        let Some(request) = conn.borrow_mut().read()? else {
+            return Ok(());
+        };
+
+        let mut conn = conn.borrow_mut();
+        match request.command.as_str() {
+            "initialize" => {
+                conn.respond(&request, json!({ "supportsConfigurationDoneRequest": true }))?;
+                conn.event("initialized", json!({}))?;
+            }
+            "launch" => {
+                let Some(path) = request.arguments["program"].as_str() else {
+                    conn.fail(&request, "launch requires a program")?;
+                    continue;
+                };
+                program = Some(PathBuf::from(path));
+                stop_on_entry = request.arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                conn.respond(&request, json!({}))?;
+            }
+            "setBreakpoints" => {
+                let (path, lines) = breakpoint_args(&request);
+                conn.respond(&request, breakpoints_body(&lines))?;
+                pending.insert(path, lines);
+            }
+            "configurationDone" => {
+                is_configured = true;
+                conn.respond(&request, json!({}))?;
+            }
+            "threads" => conn.respond(&request, threads_body())?,
+            "disconnect" => {
+                conn.respond(&request, json!({}))?;
+                return Ok(());
+            }
+            command => conn.fail(&request, &format!("unsupported request: {command}"))?,
+        }
+    }
+
+    let program = program.expect("program is set before configuration is done");
+    let source = fs::read_to_string(&program)
+        .with_context(|| format!("could not read file: {}", program.display()))?;
+
+    let adapter = Adapter { conn: Rc::clone(&conn), program: program.clone(), is_entry: true };
+    let mut debugger =
+        Debugger::new(adapter, if stop_on_entry { Step::In } else { Step::Continue });
+    for (path, lines) in pending {
+        let source = source_name(&program, &path);
+        for line in lines {
+            debugger.breakpoints.add(source.clone(), line);
+        }
+    }
+
+    let mut vm = VM::new();
+    vm.add_package_path(program.parent().unwrap_or(Path::new(".")));
+    crate::cmd::add_env_package_paths(&mut vm);
+    vm.set_debugger(Some(debugger));
+
+    let exit_code = match vm.run(&source, &mut Output(Rc::clone(&conn))) {
+        Ok(()) => 0,
+        Err(errors) => {
+            let mut buffer = Vec::new();
+            crate::error::report_vm_errors(
+                &mut buffer,
+                &vm,
+                &program.display().to_string(),
+                &errors,
+            );
+            let output = String::from_utf8_lossy(&buffer);
+            conn.borrow_mut().event("output", json!({ "category": "stderr", "output": output }))?;
+            1
+        }
+    };
+
+    let mut conn = conn.borrow_mut();
+    conn.event("exited", json!({ "exitCode": exit_code }))?;
+    conn.event("terminated", json!({}))?;
+
+    while let Some(request) = conn.read()? {
+        match request.command.as_str() {
+            "threads" => conn.respond(&request, threads_body())?,
+            "disconnect" => {
+                conn.respond(&request, json!({}))?;
+                break;
+            }
+            _ => conn.fail(&request, "the program has exited")?,
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    seq: i64,
+    command: String,
+    #[serde(default)]
+    arguments: Json,
+}
+
+/// Reads requests and writes responses and events, framed by
+/// `Content-Length` headers
+struct Connection {
+    input: StdinLock<'static>,
+    output: Stdout,
+    seq: i64,
+}
+
+impl Connection {
+    /// Read the next request. Returns [`None`] when the client closes stdin.
+    fn read(&mut self) -> Result<Option<Request>> {
+        let mut len = None;
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                len = Some(value.trim().parse::<usize>().context("invalid Content-Length")?);
+            }
+        }
+
+        let len = len.context("message is missing a Content-Length header")?;
+        let mut body = vec![0; len];
+        self.input.read_exact(&mut body)?;
+
+        Ok(Some(serde_json::from_slice(&body).context("invalid request")?))
+    }
+
+    fn send(&mut self, mut message: Json) -> Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.output.flush()?;
+
+        Ok(())
+    }
+
+    fn respond(&mut self, request: &Request, body: Json) -> Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "success": true,
+            "command": request.command,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Request, message: &str) -> Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "success": false,
+            "command": request.command,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+}
+
+/// Sends the program's output to the client as output events
+struct Output(Rc<RefCell<Connection>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let output = String::from_utf8_lossy(buf);
+        self.0
+            .borrow_mut()
+            .event("output", json!({ "category": "stdout", "output": output }))
+            .map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Answers the client's requests while the program is stopped
+struct Adapter {
+    conn: Rc<RefCell<Connection>>,
+    program: PathBuf,
+    /// Whether the program hasn't stopped yet
+    is_entry: bool,
+}
+
+impl Adapter {
+    fn path<'a>(&'a self, source: &'a SourceName) -> &'a Path {
+        match source {
+            SourceName::Package(path) => path,
+            SourceName::Lib | SourceName::Script => &self.program,
+        }
+    }
+
+    fn stack_trace(&self, vm: &VM) -> Json {
+        let frames = vm
+            .debug_frames()
+            .iter()
+            .enumerate()
+            .map(|(idx, frame)| {
+                let name = if frame.name.is_empty() { "<script>" } else { &frame.name };
+                let mut json = json!({ "id": idx, "name": name, "line": 0, "column": 0 });
+                if let Some(location) = &frame.location {
+                    let path = self.path(&location.source);
+                    json["source"] = json!({
+                        "name": path.file_name().map(|name| name.to_string_lossy()),
+                        "path": path.display().to_string(),
+                    });
+                    json["line"] = location.line.into();
+                    json["column"] = location.column.into();
+                }
+                json
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn scopes(frame: i64) -> Json {
+        json!({
+            "scopes": [
+                {
+                    "name": "Locals",
+                    "presentationHint": "locals",
+                    "variablesReference": frame + GLOBALS_REFERENCE + 1,
+                    "expensive": false,
+                },
+                {
+                    "name": "Globals",
+                    "variablesReference": GLOBALS_REFERENCE,
+                    "expensive": false,
+                },
+            ]
+        })
+    }
+
+    fn variables(vm: &VM, reference: i64) -> Json {
+        let variables = if reference == GLOBALS_REFERENCE {
+            vm.debug_globals()
+        } else {
+            let idx = (reference - GLOBALS_REFERENCE - 1) as usize;
+            vm.debug_frames()
+                .into_iter()
+                .nth(idx)
+                .map(|frame| frame.locals.into_iter().chain(frame.upvalues).collect())
+                .unwrap_or_default()
+        };
+
+        let variables = variables
+            .into_iter()
+            .map(|(name, value)| {
+                json!({
+                    "name": name,
+                    "value": value.repr(),
+                    "type": value.type_().to_string(),
+                    "variablesReference": 0,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "variables": variables })
+    }
+}
+
+impl DebugHook for Adapter {
+    fn stopped(
+        &mut self,
+        vm: &VM,
+        breakpoints: &mut Breakpoints,
+        _: &Location,
+        reason: StopReason,
+    ) -> Step {
+        let reason = match reason {
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step if self.is_entry => "entry",
+            StopReason::Step => "step",
+        };
+        self.is_entry = false;
+
+        let conn = Rc::clone(&self.conn);
+        let mut conn = conn.borrow_mut();
+        let result = conn.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+
+        let step = result.and_then(|()| loop {
+            let Some(request) = conn.read()? else {
+                exit(0);
+            };
+
+            let step = match request.command.as_str() {
+                "continue" => Step::Continue,
+                "next" => Step::Over,
+                "stepIn" => Step::In,
+                "stepOut" => Step::Out,
+                "threads" => {
+                    conn.respond(&request, threads_body())?;
+                    continue;
+                }
+                "stackTrace" => {
+                    conn.respond(&request, self.stack_trace(vm))?;
+                    continue;
+                }
+                "scopes" => {
+                    let frame = request.arguments["frameId"].as_i64().unwrap_or(0);
+                    conn.respond(&request, Self::scopes(frame))?;
+                    continue;
+                }
+                "variables" => {
+                    let reference = request.arguments["variablesReference"].as_i64().unwrap_or(0);
+                    conn.respond(&request, Self::variables(vm, reference))?;
+                    continue;
+                }
+                "setBreakpoints" => {
+                    let (path, lines) = breakpoint_args(&request);
+                    let source = source_name(&self.program, &path);
+                    breakpoints.clear(&source);
+                    for &line in &lines {
+                        breakpoints.add(source.clone(), line);
+                    }
+                    conn.respond(&request, breakpoints_body(&lines))?;
+                    continue;
+                }
+                "disconnect" => {
+                    conn.respond(&request, json!({}))?;
+                    exit(0);
+                }
+                command => {
+                    conn.fail(&request, &format!("unsupported request: {command}"))?;
+                    continue;
+                }
+            };
+
+            let body = if step == Step::Continue {
+                json!({ "allThreadsContinued": true })
+            } else {
+                json!({})
+            };
+            conn.respond(&request, body)?;
+            break Ok(step);
+        });
+
+        step.unwrap_or_else(|e: anyhow::Error| {
+            eprintln!("debug adapter failed: {e:#}");
+            exit(1);
+        })
+    }
+}
+
+/// Which of the [`VM`]'s source files a client's path refers to
+fn source_name(program: &Path, path: &Path) -> SourceName {
+    let is_program = match (program.canonicalize(), path.canonicalize()) {
+        (Ok(program), Ok(path)) => program == path,
+        _ => program == path,
+    };
+
+    if is_program {
+        SourceName::Script
+    } else {
+        SourceName::Package(path.to_path_buf())
+    }
+}
+
+/// The path and lines of a setBreakpoints request
+fn breakpoint_args(request: &Request) -> (PathBuf, Vec<usize>) {
+    let path = request.arguments["source"]["path"].as_str().unwrap_or_default();
+    let lines = request.arguments["breakpoints"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|breakpoint| breakpoint["line"].as_u64())
+        .map(|line| line as usize)
+        .collect();
+    (PathBuf::from(path), lines)
+}
+
+fn breakpoints_body(lines: &[usize]) -> Json {
+    let breakpoints =
+        lines.iter().map(|line| json!({ "verified": true, "line": line })).collect::<Vec<_>>();
+    json!({ "breakpoints": breakpoints })
+}
+
+fn threads_body() -> Json {
+    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })
+}
@@ -0,0 +1,178 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use anyhow::{bail, Context, Result};
+
+use crate::vm::debug::{Breakpoints, DebugHook, Debugger, Location, SourceName, Step, StopReason};
+use crate::vm::VM;
+
+const HELP: &str = "\
+b, break [file:]line   add a breakpoint
+d, delete [file:]line  remove a breakpoint
+c, continue            run until the next breakpoint
+s, step                stop at the next line, stepping into calls
+n, next                stop at the next line, stepping over calls
+o, out                 stop after the current function returns
+bt, backtrace          show the call stack
+v, vars [frame]        show the variables in a frame (0 is the innermost)
+l, list                show the source around the current line
+q, quit                stop the program";
+
+/// Run a file with an interactive prompt that stops on the first line
+pub fn run(path: &str) -> Result<()> {
+    let source =
+        fs::read_to_string(path).with_context(|| format!("could not read file: {path}"))?;
+
+    let mut vm = VM::new();
+    vm.add_package_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+    crate::cmd::add_env_package_paths(&mut vm);
+
+    let prompt = Prompt { path: PathBuf::from(path), source: source.clone() };
+    vm.set_debugger(Some(Debugger::new(prompt, Step::In)));
+
+    let stdout = &mut io::stdout();
+    if let Err(errors) = vm.run(&source, stdout) {
+        crate::error::report_vm_errors(&mut io::stderr().lock(), &vm, path, &errors);
+        bail!("program exited with errors");
+    }
+
+    Ok(())
+}
+
+/// Reads debugger commands from stdin
+struct Prompt {
+    /// The file being debugged
+    path: PathBuf,
+    source: String,
+}
+
+impl Prompt {
+    fn display_source<'a>(&'a self, source: &'a SourceName) -> &'a Path {
+        match source {
+            SourceName::Package(path) => path,
+            SourceName::Lib | SourceName::Script => &self.path,
+        }
+    }
+
+    /// Parse `[file:]line`. Lines without a file are in the file being
+    /// debugged.
+    fn parse_breakpoint(&self, arg: Option<&str>) -> Option<(SourceName, usize)> {
+        let arg = arg?;
+        let (source, line) = match arg.rsplit_once(':') {
+            Some((file, line)) if Path::new(file) != self.path => {
+                (SourceName::Package(PathBuf::from(file)), line)
+            }
+            Some((_, line)) => (SourceName::Script, line),
+            None => (SourceName::Script, arg),
+        };
+        Some((source, line.parse().ok()?))
+    }
+
+    fn list(&self, location: &Location) {
+        let source = match &location.source {
+            SourceName::Package(path) => fs::read_to_string(path).unwrap_or_default(),
+            SourceName::Lib | SourceName::Script => self.source.clone(),
+        };
+
+        let first = location.line.saturating_sub(3).max(1);
+        for (idx, line) in source.lines().enumerate().skip(first - 1).take(5) {
+            let marker = if idx + 1 == location.line { ">" } else { " " };
+            println!("{marker} {:>4} {line}", idx + 1);
+        }
+    }
+
+    fn backtrace(&self, vm: &VM) {
+        for (idx, frame) in vm.debug_frames().iter().enumerate() {
+            let name = if frame.name.is_empty() { "<script>" } else { &frame.name };
+            match &frame.location {
+                Some(location) => println!(
+                    "#{idx} {name} at {}:{}",
+                    self.display_source(&location.source).display(),
+                    location.line
+                ),
+                None => println!("#{idx} {name}"),
+            }
+        }
+    }
+
+    fn vars(&self, vm: &VM, frame: Option<&str>) {
+        let frames = vm.debug_frames();
+        let idx = frame.and_then(|frame| frame.parse().ok()).unwrap_or(0);
+        let Some(frame) = frames.get(idx) else {
+            println!("no frame #{idx}");
+            return;
+        };
+
+        for (name, value) in &frame.locals {
+            println!("{name} = {}", value.repr());
+        }
+        for (name, value) in &frame.upvalues {
+            println!("{name} = {} (captured)", value.repr());
+        }
+        if idx == frames.len() - 1 {
+            for (name, value) in vm.debug_globals() {
+                println!("{name} = {} (global)", value.repr());
+            }
+        }
+    }
+}
+
+impl DebugHook for Prompt {
+    fn stopped(
+        &mut self,
+        vm: &VM,
+        breakpoints: &mut Breakpoints,
+        location: &Location,
+        reason: StopReason,
+    ) -> Step {
+        let file = self.display_source(&location.source).display();
+        match reason {
+            StopReason::Breakpoint => println!("breakpoint at {file}:{}", location.line),
+            StopReason::Step => println!("stopped at {file}:{}", location.line),
+        }
+        self.list(location);
+
+        let mut stdin = io::stdin().lock();
+        loop {
+            print!("(debug) ");
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+                exit(0);
+            }
+
+            let mut args = line.split_whitespace();
+            match args.next() {
+                Some("b" | "break") => match self.parse_breakpoint(args.next()) {
+                    Some((source, line)) => {
+                        let file = self.display_source(&source).display().to_string();
+                        breakpoints.add(source, line);
+                        println!("breakpoint set at {file}:{line}");
+                    }
+                    None => println!("usage: break [file:]line"),
+                },
+                Some("d" | "delete") => match self.parse_breakpoint(args.next()) {
+                    Some((source, line)) if breakpoints.remove(&source, line) => {
+                        println!("breakpoint removed");
+                    }
+                    Some(_) => println!("no breakpoint there"),
+                    None => println!("usage: delete [file:]line"),
+                },
+                Some("c" | "continue") => return Step::Continue,
+                Some("s" | "step") => return Step::In,
+                Some("n" | "next") => return Step::Over,
+                Some("o" | "out") => return Step::Out,
+                Some("bt" | "backtrace") => self.backtrace(vm),
+                Some("v" | "vars") => self.vars(vm, args.next()),
+                Some("l" | "list") => self.list(location),
+                Some("q" | "quit") => exit(0),
+                Some("h" | "help") => println!("{HELP}"),
+                Some(command) => println!("unknown command: {command} (try help)"),
+                None => {}
+            }
+        }
+    }
+}
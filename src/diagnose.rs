@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+use crate::error::ErrorS;
+use crate::lint::{self, Rules};
+use crate::syntax::ast::Program;
 use crate::types::Span;
 use crate::vm::{Compiler, Gc};
 
@@ -8,34 +11,44 @@ pub struct Diagnoser {}
 
 impl Diagnoser {
     pub fn get_diagnostics(source: &str) -> Vec<Diagnosis> {
+        let (program, errors) = crate::syntax::parse_recover(source, 0);
+        Diagnoser::get_program_diagnostics(source, &program, &errors)
+    }
+
+    /// Diagnose a program that's already been parsed, along with the errors
+    /// from parsing it
+    pub fn get_program_diagnostics(
+        source: &str,
+        program: &Program,
+        parse_errors: &[ErrorS],
+    ) -> Vec<Diagnosis> {
+        if !parse_errors.is_empty() {
+            return parse_errors
+                .iter()
+                .map(|(err, span)| Diagnosis {
+                    range: Diagnoser::get_range(source, span),
+                    severity: Some(DiagnosisSeverity::ERROR),
+                    message: err.to_string(),
+                })
+                .collect();
+        }
+
         let mut gc = Gc::default();
+        let errors = Compiler::compile(program, &mut gc).err().unwrap_or_default();
+        let errors = errors.iter().map(|(err, span)| Diagnosis {
+            range: Diagnoser::get_range(source, span),
+            severity: Some(DiagnosisSeverity::ERROR),
+            message: err.to_string(),
+        });
 
-        let program = match crate::syntax::parse(source, 0) {
-            Ok(program) => program,
-            Err(errors) => {
-                return errors
-                    .iter()
-                    .map(|(err, span)| Diagnosis {
-                        range: Diagnoser::get_range(source, span),
-                        severity: Some(DiagnosisSeverity::ERROR),
-                        message: err.to_string(),
-                        ..Default::default()
-                    })
-                    .collect();
-            }
-        };
+        let lints = lint::lint(source, program, &Rules::default());
+        let warnings = lints.iter().map(|lint| Diagnosis {
+            range: Diagnoser::get_range(source, &lint.span),
+            severity: Some(DiagnosisSeverity::WARNING),
+            message: format!("{} ({})", lint.message, lint.rule),
+        });
 
-        Compiler::compile(&program, &mut gc)
-            .err()
-            .unwrap_or_default()
-            .iter()
-            .map(|(err, span)| Diagnosis {
-                range: Diagnoser::get_range(source, span),
-                severity: Some(DiagnosisSeverity::ERROR),
-                message: err.to_string(),
-                ..Default::default()
-            })
-            .collect()
+        errors.chain(warnings).collect()
     }
 
     pub fn get_range(source: &str, span: &Span) -> DiagnosisRange {
@@ -45,12 +58,37 @@ impl Diagnoser {
         }
     }
 
+    /// The position of an offset in `source`. Characters are counted in
+    /// UTF-16 code units, like editors do.
     pub fn get_position(source: &str, idx: usize) -> DiagnosisPosition {
         let before = &source[..idx];
-        let line = before.lines().count() - 1;
-        let character = before.lines().last().unwrap().len();
+        let line = before.matches('\n').count();
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        let character = before[line_start..].encode_utf16().count();
         DiagnosisPosition { line: line as _, character: character as _ }
     }
+
+    /// The offset of a position in `source`. Positions past the end of a line
+    /// or the source are moved back to the end, and positions inside a
+    /// character are moved back to its start.
+    pub fn get_offset(source: &str, position: DiagnosisPosition) -> usize {
+        let line_start = match position.line as usize {
+            0 => 0,
+            line => {
+                source.match_indices('\n').nth(line - 1).map_or(source.len(), |(idx, _)| idx + 1)
+            }
+        };
+        let line_end = source[line_start..].find('\n').map_or(source.len(), |idx| line_start + idx);
+
+        let mut units = 0;
+        for (idx, c) in source[line_start..line_end].char_indices() {
+            units += c.len_utf16();
+            if units > position.character as usize {
+                return line_start + idx;
+            }
+        }
+        line_end
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Default, Deserialize, Serialize)]
@@ -111,17 +149,14 @@ mod tests {
 
         let d = Diagnoser::get_diagnostics(&source);
 
-        let mut e = Vec::<Diagnosis>::new();
-
-        e.push(Diagnosis {
+        let e = vec![Diagnosis {
             range: DiagnosisRange {
                 start: DiagnosisPosition { line: 0, character: 4 },
                 end: DiagnosisPosition { line: 0, character: 5 },
             },
             severity: Some(DiagnosisSeverity::ERROR),
             message: String::from("SyntaxError: unexpected \"a\""),
-            ..Default::default()
-        });
+        }];
 
         assert_eq!(e, d);
     }
@@ -132,18 +167,59 @@ mod tests {
 
         let d = Diagnoser::get_diagnostics(&source);
 
-        let mut e = Vec::<Diagnosis>::new();
-
-        e.push(Diagnosis {
+        let e = vec![Diagnosis {
             range: DiagnosisRange {
                 start: DiagnosisPosition { line: 0, character: 11 },
                 end: DiagnosisPosition { line: 0, character: 11 },
             },
             severity: Some(DiagnosisSeverity::ERROR),
             message: String::from("SyntaxError: unexpected end of file"),
-            ..Default::default()
-        });
+        }];
 
         assert_eq!(e, d);
     }
+
+    #[test]
+    fn warnings() {
+        let source = "fn f(a) {}";
+
+        let d = Diagnoser::get_diagnostics(source);
+
+        let e = vec![Diagnosis {
+            range: DiagnosisRange {
+                start: DiagnosisPosition { line: 0, character: 5 },
+                end: DiagnosisPosition { line: 0, character: 6 },
+            },
+            severity: Some(DiagnosisSeverity::WARNING),
+            message: String::from("unused parameter: \"a\" (unused-variable)"),
+        }];
+
+        assert_eq!(e, d);
+    }
+
+    #[test]
+    fn positions() {
+        let source = "let a;\nprintln(a);\n";
+
+        assert_eq!(Diagnoser::get_position(source, 7), DiagnosisPosition::new(1, 0));
+        assert_eq!(Diagnoser::get_position(source, 15), DiagnosisPosition::new(1, 8));
+        assert_eq!(Diagnoser::get_position(source, 19), DiagnosisPosition::new(2, 0));
+
+        assert_eq!(Diagnoser::get_offset(source, DiagnosisPosition::new(0, 4)), 4);
+        assert_eq!(Diagnoser::get_offset(source, DiagnosisPosition::new(1, 8)), 15);
+        assert_eq!(Diagnoser::get_offset(source, DiagnosisPosition::new(1, 99)), 18);
+        assert_eq!(Diagnoser::get_offset(source, DiagnosisPosition::new(9, 0)), 19);
+    }
+
+    #[test]
+    fn positions_in_utf16() {
+        let source = "let s = \"é🔓\";";
+
+        assert_eq!(Diagnoser::get_position(source, 11), DiagnosisPosition::new(0, 10));
+        assert_eq!(Diagnoser::get_position(source, 15), DiagnosisPosition::new(0, 12));
+
+        assert_eq!(Diagnoser::get_offset(source, DiagnosisPosition::new(0, 10)), 11);
+        assert_eq!(Diagnoser::get_offset(source, DiagnosisPosition::new(0, 11)), 11);
+        assert_eq!(Diagnoser::get_offset(source, DiagnosisPosition::new(0, 12)), 15);
+    }
 }
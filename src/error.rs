@@ -7,6 +7,8 @@ use termcolor::WriteColor;
 use thiserror::Error;
 
 use crate::types::{Span, Spanned};
+use crate::vm::debug::SourceName;
+use crate::vm::VM;
 
 pub type Result<T, E = ErrorS> = std::result::Result<T, E>;
 pub type ErrorS = Spanned<Error>;
@@ -15,6 +17,8 @@ pub type ErrorS = Spanned<Error>;
 pub enum Error {
     #[error("AttributeError: {0}")]
     AttributeError(AttributeError),
+    #[error("BytecodeError: {0}")]
+    BytecodeError(BytecodeError),
     #[error("IOError: {0}")]
     IoError(IoError),
     #[error("NameError: {0}")]
@@ -27,18 +31,71 @@ pub enum Error {
     TypeError(TypeError),
     #[error("IndexError: {0}")]
     IndexError(IndexError),
+    #[error("ImportError: {0}")]
+    ImportError(ImportError),
+    #[error("AssertionError: {0}")]
+    AssertionError(AssertionError),
+    #[error("MemoryError: {0}")]
+    MemoryError(MemoryError),
+    #[error("{0}")]
+    UncaughtError(UncaughtError),
+}
+
+impl Error {
+    /// The name of the error's type (e.g. `TypeError`)
+    ///
+    /// Errors raised at runtime are instances of the Locks class with this name.
+    pub fn type_(&self) -> &str {
+        match self {
+            Error::AttributeError(_) => "AttributeError",
+            Error::BytecodeError(_) => "BytecodeError",
+            Error::IoError(_) => "IOError",
+            Error::NameError(_) => "NameError",
+            Error::OverflowError(_) => "OverflowError",
+            Error::SyntaxError(_) => "SyntaxError",
+            Error::TypeError(_) => "TypeError",
+            Error::IndexError(_) => "IndexError",
+            Error::ImportError(_) => "ImportError",
+            Error::AssertionError(_) => "AssertionError",
+            Error::MemoryError(_) => "MemoryError",
+            Error::UncaughtError(e) => &e.type_,
+        }
+    }
+
+    /// The error's message without its type
+    pub fn message(&self) -> String {
+        match self {
+            Error::AttributeError(e) => e.to_string(),
+            Error::BytecodeError(e) => e.to_string(),
+            Error::IoError(e) => e.to_string(),
+            Error::NameError(e) => e.to_string(),
+            Error::OverflowError(e) => e.to_string(),
+            Error::SyntaxError(e) => e.to_string(),
+            Error::TypeError(e) => e.to_string(),
+            Error::IndexError(e) => e.to_string(),
+            Error::ImportError(e) => e.to_string(),
+            Error::AssertionError(e) => e.to_string(),
+            Error::MemoryError(e) => e.to_string(),
+            Error::UncaughtError(e) => e.message.clone(),
+        }
+    }
 }
 
 impl AsDiagnostic for Error {
     fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
         match self {
             Error::AttributeError(e) => e.as_diagnostic(span),
+            Error::BytecodeError(e) => e.as_diagnostic(span),
             Error::IoError(e) => e.as_diagnostic(span),
             Error::NameError(e) => e.as_diagnostic(span),
             Error::OverflowError(e) => e.as_diagnostic(span),
             Error::SyntaxError(e) => e.as_diagnostic(span),
             Error::TypeError(e) => e.as_diagnostic(span),
             Error::IndexError(e) => e.as_diagnostic(span),
+            Error::ImportError(e) => e.as_diagnostic(span),
+            Error::AssertionError(e) => e.as_diagnostic(span),
+            Error::MemoryError(e) => e.as_diagnostic(span),
+            Error::UncaughtError(e) => e.as_diagnostic(span),
         }
     }
 }
@@ -55,12 +112,17 @@ macro_rules! impl_from_error {
 
 impl_from_error!(
     AttributeError,
+    BytecodeError,
     IoError,
     NameError,
     OverflowError,
     SyntaxError,
     TypeError,
-    IndexError
+    IndexError,
+    ImportError,
+    AssertionError,
+    MemoryError,
+    UncaughtError
 );
 
 #[derive(Debug, Error, Eq, PartialEq)]
@@ -80,6 +142,26 @@ impl AsDiagnostic for AttributeError {
     }
 }
 
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum BytecodeError {
+    #[error("not a compiled Locks file")]
+    InvalidHeader,
+    #[error("invalid {item} in compiled file")]
+    Invalid { item: String },
+    #[error("compiled file ended unexpectedly")]
+    UnexpectedEof,
+    #[error("compiled file has version {version} but version {expected} is supported")]
+    UnsupportedVersion { version: u16, expected: u16 },
+}
+
+impl AsDiagnostic for BytecodeError {
+    /// The error is about the compiled file rather than its source, so it
+    /// doesn't label a span
+    fn as_diagnostic(&self, _: &Span) -> Diagnostic<()> {
+        Diagnostic::error().with_code("BytecodeError").with_message(self.to_string())
+    }
+}
+
 #[derive(Debug, Error, Eq, PartialEq)]
 pub enum IoError {
     #[error("unable to write to file: {file:?}")]
@@ -120,6 +202,8 @@ impl AsDiagnostic for NameError {
 
 #[derive(Debug, Error, Eq, PartialEq)]
 pub enum OverflowError {
+    #[error("ran out of instruction budget")]
+    BudgetExhausted,
     #[error("jump body is too large")]
     JumpTooLarge,
     #[error("stack overflow")]
@@ -198,6 +282,14 @@ pub enum TypeError {
     NotCallable { type_: String },
     #[error("{type_:?} is not indexable")]
     NotIndexable { type_: String },
+    #[error("list indexes must be numbers, not {type_:?}")]
+    InvalidIndexType { type_: String },
+    #[error("list indexes must be non-negative integers, not {index}")]
+    InvalidIndex { index: String },
+    #[error("string indexes must be numbers, not {type_:?}")]
+    InvalidStringIndexType { type_: String },
+    #[error("string indexes must be non-negative integers, not {index}")]
+    InvalidStringIndex { index: String },
     #[error(r#"superclass should be of type "class", not {type_:?}"#)]
     SuperclassInvalidType { type_: String },
     #[error("unsupported operand type(s) for {op}: {lt_type:?} and {rt_type:?}")]
@@ -231,6 +323,8 @@ impl AsDiagnostic for TypeError {
 pub enum IndexError {
     #[error("the length is {length} but the index is {wanted_index} (out of bounds)")]
     OutOfBounds { wanted_index: usize, length: usize },
+    #[error("the key {key} is not in the map")]
+    KeyNotFound { key: String },
 }
 
 impl AsDiagnostic for IndexError {
@@ -242,6 +336,80 @@ impl AsDiagnostic for IndexError {
     }
 }
 
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum ImportError {
+    #[error("package {name:?} is imported circularly")]
+    Circular { name: String },
+    #[error("package {name:?} has not been imported")]
+    NotImported { name: String },
+    #[error("package {name:?} could not be found")]
+    NotFound { name: String },
+    #[error("expected {path:?} to declare package {name:?}")]
+    PackageMismatch { name: String, path: String },
+}
+
+impl AsDiagnostic for ImportError {
+    fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
+        Diagnostic::error()
+            .with_code("ImportError")
+            .with_message(self.to_string())
+            .with_labels(vec![Label::primary((), span.clone())])
+    }
+}
+
+/// Raised by the `assert` natives when a check fails
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum AssertionError {
+    #[error("assertion failed")]
+    Failed,
+    #[error("expected {expected} but got {actual}")]
+    NotEqual { actual: String, expected: String },
+    #[error("expected a value other than {unexpected}")]
+    Equal { unexpected: String },
+}
+
+impl AsDiagnostic for AssertionError {
+    fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
+        Diagnostic::error()
+            .with_code("AssertionError")
+            .with_message(self.to_string())
+            .with_labels(vec![Label::primary((), span.clone())])
+    }
+}
+
+/// Raised when a script's heap grows past the VM's `max_heap_bytes`
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum MemoryError {
+    #[error("heap limit of {limit} bytes exceeded")]
+    HeapLimitExceeded { limit: usize },
+}
+
+impl AsDiagnostic for MemoryError {
+    fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
+        Diagnostic::error()
+            .with_code("MemoryError")
+            .with_message(self.to_string())
+            .with_labels(vec![Label::primary((), span.clone())])
+    }
+}
+
+/// A value thrown by a script that wasn't caught
+#[derive(Debug, Error, Eq, PartialEq)]
+#[error("{type_}: {message}")]
+pub struct UncaughtError {
+    pub type_: String,
+    pub message: String,
+}
+
+impl AsDiagnostic for UncaughtError {
+    fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
+        Diagnostic::error()
+            .with_code(self.type_.clone())
+            .with_message(self.message.clone())
+            .with_labels(vec![Label::primary((), span.clone())])
+    }
+}
+
 trait AsDiagnostic {
     fn as_diagnostic(&self, span: &Span) -> Diagnostic<()>;
 }
@@ -271,8 +439,53 @@ pub fn report_errors(writer: &mut impl io::Write, source: &str, errors: &[ErrorS
     writer.write_all(buffer.as_slice()).expect("failed to write to output");
 }
 
-pub fn report_error(writer: &mut impl WriteColor, source: &str, (error, span): &ErrorS) {
-    let file = SimpleFile::new("<script>", source);
+pub fn report_error(writer: &mut impl WriteColor, source: &str, error: &ErrorS) {
+    report_error_in(writer, "<script>", source, error);
+}
+
+/// Report errors from running a [`VM`] in the files they happened in.
+/// `script` names the code passed to [`VM::run`] (e.g. its path).
+pub fn report_vm_errors(writer: &mut impl io::Write, vm: &VM, script: &str, errors: &[ErrorS]) {
+    let mut buffer = termcolor::Buffer::ansi();
+    for err in errors {
+        report_vm_error(&mut buffer, vm, script, err);
+    }
+    writer.write_all(buffer.as_slice()).expect("failed to write to output");
+}
+
+/// Report an error from running a [`VM`] in the file it happened in, with
+/// its span moved from the [`VM`]'s source into that file
+pub fn report_vm_error(
+    writer: &mut impl WriteColor,
+    vm: &VM,
+    script: &str,
+    (error, span): &ErrorS,
+) {
+    match vm.source_file(span.start) {
+        Some((start, source, name)) if span.end - start <= source.len() => {
+            let name = match name {
+                SourceName::Lib => "<lib>".to_string(),
+                SourceName::Script => script.to_string(),
+                SourceName::Package(path) => path.display().to_string(),
+            };
+            emit(writer, &name, source, error, &(span.start - start..span.end - start));
+        }
+        _ => emit(writer, script, &vm.source, error, span),
+    }
+}
+
+/// Report an error in a file with a name (e.g. its path)
+pub fn report_error_in(
+    writer: &mut impl WriteColor,
+    name: &str,
+    source: &str,
+    (error, span): &ErrorS,
+) {
+    emit(writer, name, source, error, span);
+}
+
+fn emit(writer: &mut impl WriteColor, name: &str, source: &str, error: &Error, span: &Span) {
+    let file = SimpleFile::new(name, source);
     let config = term::Config::default();
     let diagnostic = error.as_diagnostic(span);
     term::emit(writer, &config, &file, &diagnostic).expect("failed to write to output");
@@ -1,8 +1,15 @@
 pub mod cmd;
+pub mod dap;
+pub mod debugger;
 pub mod diagnose;
 pub mod error;
+pub mod lint;
 pub mod lsp;
+pub mod profiler;
 pub mod repl;
+pub mod snapshot;
+pub mod symbols;
 pub mod syntax;
+pub mod test_runner;
 pub mod types;
 pub mod vm;
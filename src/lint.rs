@@ -0,0 +1,481 @@
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use codespan_reporting::files::SimpleFile;
+use codespan_reporting::term;
+use termcolor::WriteColor;
+
+use crate::symbols::{SymbolIndex, SymbolKind, Target, NATIVES};
+use crate::syntax::ast::{Expr, ExprLiteral, ExprS, Program, Stmt, StmtBlock, StmtS};
+use crate::types::Span;
+
+/// A check run by [`lint`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Rule {
+    /// A local variable or parameter that's never used
+    UnusedVariable,
+    /// Statements after a `return` or `throw` in the same block
+    UnreachableCode,
+    /// A local variable with the same name as one in an enclosing scope
+    ShadowedVariable,
+    /// Assigning to a name that was never declared with `let`
+    UndeclaredAssignment,
+    /// Calling a known function with the wrong number of arguments
+    WrongArity,
+    /// A block without any statements or comments
+    EmptyBlock,
+}
+
+impl Rule {
+    pub const ALL: &'static [Rule] = &[
+        Rule::UnusedVariable,
+        Rule::UnreachableCode,
+        Rule::ShadowedVariable,
+        Rule::UndeclaredAssignment,
+        Rule::WrongArity,
+        Rule::EmptyBlock,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::ShadowedVariable => "shadowed-variable",
+            Rule::UndeclaredAssignment => "undeclared-assignment",
+            Rule::WrongArity => "wrong-arity",
+            Rule::EmptyBlock => "empty-block",
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Rule::ALL.iter().find(|rule| rule.name() == s).copied().ok_or_else(|| {
+            let names = Rule::ALL.iter().map(|rule| rule.name()).collect::<Vec<_>>();
+            format!("unknown lint rule {s:?}, expected one of: {}", names.join(", "))
+        })
+    }
+}
+
+/// The rules that [`lint`] checks. Every rule is enabled by default.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rules {
+    enabled: HashSet<Rule>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self { enabled: Rule::ALL.iter().copied().collect() }
+    }
+}
+
+impl Rules {
+    pub fn allow(&mut self, rule: Rule) {
+        self.enabled.remove(&rule);
+    }
+
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        self.enabled.contains(&rule)
+    }
+}
+
+/// A warning about code that runs but is likely a mistake
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub message: String,
+    pub span: Span,
+}
+
+/// Check a parsed program for likely mistakes, sorted by where they are
+pub fn lint(source: &str, program: &Program, rules: &Rules) -> Vec<Lint> {
+    let mut linter = Linter {
+        source,
+        index: SymbolIndex::from_program(source, program),
+        rules,
+        lints: Vec::new(),
+    };
+    linter.symbols();
+    for stmt in &program.stmts {
+        linter.stmt(stmt);
+    }
+
+    let mut lints = linter.lints;
+    lints.sort_by_key(|lint| (lint.span.start, lint.span.end));
+    lints
+}
+
+pub fn report_lint(writer: &mut impl WriteColor, source: &str, lint: &Lint) {
+    let file = SimpleFile::new("<script>", source);
+    let config = term::Config::default();
+    let diagnostic = Diagnostic::warning()
+        .with_code(lint.rule.name())
+        .with_message(&lint.message)
+        .with_labels(vec![Label::primary((), lint.span.clone())]);
+    term::emit(writer, &config, &file, &diagnostic).expect("failed to write to output");
+}
+
+struct Linter<'a> {
+    source: &'a str,
+    index: SymbolIndex,
+    rules: &'a Rules,
+    lints: Vec<Lint>,
+}
+
+impl Linter<'_> {
+    fn push(&mut self, rule: Rule, message: String, span: Span) {
+        if self.rules.is_enabled(rule) {
+            self.lints.push(Lint { rule, message, span });
+        }
+    }
+
+    /// Check declarations, which are already resolved by the [`SymbolIndex`]
+    fn symbols(&mut self) {
+        let mut lints = Vec::new();
+        for (idx, symbol) in self.index.symbols.iter().enumerate() {
+            if symbol.is_global || symbol.kind.is_member() {
+                continue;
+            }
+            let kind = match symbol.kind {
+                SymbolKind::Class => "class",
+                SymbolKind::Function => "function",
+                SymbolKind::Parameter => "parameter",
+                _ => "variable",
+            };
+
+            // Names starting with an underscore are unused on purpose
+            let is_used = symbol.name.starts_with('_')
+                || self
+                    .index
+                    .references
+                    .iter()
+                    .any(|reference| reference.target == Target::Symbol(idx));
+            if !is_used {
+                lints.push((
+                    Rule::UnusedVariable,
+                    format!("unused {kind}: {:?}", symbol.name),
+                    symbol.span.clone(),
+                ));
+            }
+
+            let is_shadowed = self.index.symbols.iter().any(|outer| {
+                outer.name == symbol.name
+                    && outer.span != symbol.span
+                    && outer.scope.as_ref().is_some_and(|scope| scope.contains(&symbol.span.start))
+            });
+            if is_shadowed {
+                lints.push((
+                    Rule::ShadowedVariable,
+                    format!("{kind} {:?} shadows a variable in an enclosing scope", symbol.name),
+                    symbol.span.clone(),
+                ));
+            }
+        }
+
+        for (rule, message, span) in lints {
+            self.push(rule, message, span);
+        }
+    }
+
+    fn stmt(&mut self, (stmt, span): &StmtS) {
+        match stmt {
+            Stmt::Block(block) => {
+                // Comments explain why a block is empty
+                let is_commented = self.source[span.clone()].contains("//");
+                if block.stmts.is_empty() && !is_commented {
+                    self.push(Rule::EmptyBlock, "empty block".to_string(), span.clone());
+                }
+                self.block(block);
+            }
+            Stmt::Class(class) => {
+                if let Some(super_) = &class.super_ {
+                    self.expr(super_);
+                }
+                for (field, _) in class.static_fields.iter().chain(&class.fields) {
+                    if let Some(value) = &field.value {
+                        self.expr(value);
+                    }
+                }
+                for (method, _) in class.static_methods.iter().chain(&class.methods) {
+                    self.block(&method.body);
+                }
+            }
+            Stmt::Expr(stmt) => self.expr(&stmt.value),
+            Stmt::For(for_) => {
+                if let Some(init) = &for_.init {
+                    self.stmt(init);
+                }
+                if let Some(cond) = &for_.cond {
+                    self.expr(cond);
+                }
+                if let Some(incr) = &for_.incr {
+                    self.expr(incr);
+                }
+                self.stmt(&for_.body);
+            }
+            Stmt::Fn(fn_) => self.block(&fn_.body),
+            Stmt::If(if_) => {
+                self.expr(&if_.cond);
+                self.stmt(&if_.then);
+                if let Some(else_) = &if_.else_ {
+                    self.stmt(else_);
+                }
+            }
+            Stmt::Return(return_) => {
+                if let Some(value) = &return_.value {
+                    self.expr(value);
+                }
+            }
+            Stmt::Assign(assign) => {
+                if let Some(value) = &assign.value {
+                    self.expr(value);
+                }
+            }
+            Stmt::While(while_) => {
+                self.expr(&while_.cond);
+                self.stmt(&while_.body);
+            }
+            Stmt::Throw(throw) => self.expr(&throw.value),
+            Stmt::Try(try_) => {
+                self.block(&try_.body);
+                if let Some(catch) = &try_.catch {
+                    self.block(&catch.body);
+                }
+                if let Some(finally) = &try_.finally {
+                    self.block(finally);
+                }
+            }
+            Stmt::Error => {}
+        }
+    }
+
+    fn block(&mut self, block: &StmtBlock) {
+        let exit = block
+            .stmts
+            .iter()
+            .position(|(stmt, _)| matches!(stmt, Stmt::Return(_) | Stmt::Throw(_)));
+        if let Some(unreachable) = exit.and_then(|exit| block.stmts.get(exit + 1..)) {
+            if let (Some((_, first)), Some((_, last))) = (unreachable.first(), unreachable.last()) {
+                self.push(
+                    Rule::UnreachableCode,
+                    "unreachable code".to_string(),
+                    first.start..last.end,
+                );
+            }
+        }
+
+        for stmt in &block.stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn expr(&mut self, (expr, span): &ExprS) {
+        match expr {
+            Expr::Assign(assign) => {
+                let identifier = &assign.identifier;
+                let is_declared = identifier.package.is_some()
+                    || self.index.resolve(&identifier.name, span.start).is_some()
+                    || NATIVES.iter().any(|(name, _)| *name == identifier.name);
+                if !is_declared {
+                    self.push(
+                        Rule::UndeclaredAssignment,
+                        format!("assignment to undeclared variable {:?}", identifier.name),
+                        span.start..span.start + identifier.name.len(),
+                    );
+                }
+                self.expr(&assign.value);
+            }
+            Expr::Call(call) => {
+                self.arity(&call.callee, call.args.len(), span);
+                self.expr(&call.callee);
+                for arg in &call.args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Get(get) => self.expr(&get.object),
+            Expr::GetIndex(get) => {
+                self.expr(&get.target);
+                self.expr(&get.index);
+            }
+            Expr::SetIndex(set) => {
+                self.expr(&set.target);
+                self.expr(&set.index);
+                self.expr(&set.value);
+            }
+            Expr::Infix(infix) => {
+                self.expr(&infix.lt);
+                self.expr(&infix.rt);
+            }
+            Expr::Literal(ExprLiteral::List(items)) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            Expr::Literal(ExprLiteral::Map(entries)) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Expr::Literal(_) => {}
+            Expr::Prefix(prefix) => self.expr(&prefix.rt),
+            Expr::Set(set) => {
+                self.expr(&set.object);
+                self.expr(&set.value);
+            }
+            Expr::Super(_) | Expr::Identifier(_) => {}
+        }
+    }
+
+    /// Check calls to functions, classes and natives that are called by name
+    fn arity(&mut self, (callee, callee_span): &ExprS, got_args: usize, span: &Span) {
+        let Expr::Identifier(callee) = callee else {
+            return;
+        };
+        if callee.identifier.package.is_some() {
+            return;
+        }
+        let name = &callee.identifier.name;
+
+        let params = match self.index.resolve(name, callee_span.start) {
+            Some(symbol) if symbol.kind == SymbolKind::Function => symbol.params.len(),
+            // Calling a class calls the nearest `init` in its class chain
+            Some(symbol) if symbol.kind == SymbolKind::Class => {
+                let init = self.index.class_chain(name).find_map(|class| {
+                    self.index.symbols.iter().find(|symbol| {
+                        symbol.kind == SymbolKind::Method
+                            && symbol.name == "init"
+                            && symbol.class.as_deref() == Some(class)
+                    })
+                });
+                init.map_or(0, |init| init.params.len())
+            }
+            Some(_) => return,
+            None => match NATIVES.iter().find(|(native, _)| native == name) {
+                Some((_, params)) => params.len(),
+                None => return,
+            },
+        };
+
+        if params != got_args {
+            self.push(
+                Rule::WrongArity,
+                format!("{name}() takes {params} arguments but {got_args} were given"),
+                span.clone(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// The rule and source text of each lint in a program
+    fn lints(source: &str, rules: &Rules) -> Vec<(Rule, String)> {
+        let program = crate::syntax::parse(source, 0).unwrap();
+        lint(source, &program, rules)
+            .into_iter()
+            .map(|lint| (lint.rule, source[lint.span].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn unused_and_shadowed() {
+        let source = "\
+fn f(a, b, _c) {
+  let x = a;
+  {
+    let x = 1;
+    println(x);
+  }
+  let unused;
+}
+";
+        assert_eq!(
+            lints(source, &Rules::default()),
+            vec![
+                (Rule::UnusedVariable, "b".to_string()),
+                (Rule::UnusedVariable, "x".to_string()),
+                (Rule::ShadowedVariable, "x".to_string()),
+                (Rule::UnusedVariable, "unused".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn statements() {
+        let source = "\
+fn f() {
+  return 1;
+  println(2);
+  println(3);
+}
+while (true) {}
+if (true) {
+  // nothing yet
+}
+undeclared = 1;
+let declared;
+declared = 2;
+";
+        assert_eq!(
+            lints(source, &Rules::default()),
+            vec![
+                (Rule::UnreachableCode, "println(2);\n  println(3);".to_string()),
+                (Rule::EmptyBlock, "{}".to_string()),
+                (Rule::UndeclaredAssignment, "undeclared".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn arity() {
+        let source = "\
+fn add(a, b) => a + b;
+class Base { fn init(x) {} }
+class Derived extends Base {}
+class Empty {}
+add(1);
+Derived(1, 2);
+Empty();
+println(1, 2);
+let f = add;
+f(1);
+";
+        assert_eq!(
+            lints(source, &Rules::default()),
+            vec![
+                (Rule::UnusedVariable, "x".to_string()),
+                (Rule::WrongArity, "add(1)".to_string()),
+                (Rule::WrongArity, "Derived(1, 2)".to_string()),
+                (Rule::WrongArity, "println(1, 2)".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn allowed_rules() {
+        let source = "fn f(a) { return; a; }";
+        let mut rules = Rules::default();
+        rules.allow(Rule::UnusedVariable);
+        assert_eq!(lints(source, &rules), vec![(Rule::UnreachableCode, "a;".to_string())]);
+
+        rules.allow(Rule::UnreachableCode);
+        assert_eq!(lints(source, &rules), vec![]);
+        assert_eq!("empty-block".parse(), Ok(Rule::EmptyBlock));
+        assert!("unknown".parse::<Rule>().is_err());
+    }
+}
@@ -1,23 +1,404 @@
 #![cfg(feature = "lsp")]
 
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fs, iter};
+
 use anyhow::{Context, Result};
 use tower_lsp::lsp_types::{
-    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
-    InitializeParams, InitializeResult, Position, Range, ServerCapabilities, ServerInfo,
-    TextDocumentSyncKind,
+    self, CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams,
+    CompletionResponse, Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DidSaveTextDocumentParams,
+    DocumentFormattingParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, InitializeResult, InsertTextFormat, Location,
+    MarkupContent, MarkupKind, OneOf, Position, PrepareRenameResponse, Range, ReferenceParams,
+    RenameOptions, RenameParams, SaveOptions, SemanticToken, SemanticTokenModifier,
+    SemanticTokenType, SemanticTokens, SemanticTokensFullOptions, SemanticTokensLegend,
+    SemanticTokensOptions, SemanticTokensParams, SemanticTokensRangeParams,
+    SemanticTokensRangeResult, SemanticTokensResult, ServerCapabilities, ServerInfo,
+    TextDocumentContentChangeEvent, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions, TextEdit, Url,
+    WorkspaceEdit,
 };
 use tower_lsp::{jsonrpc, Client, LanguageServer, LspService, Server};
 
 use crate::diagnose::{Diagnoser, Diagnosis, DiagnosisPosition, DiagnosisRange, DiagnosisSeverity};
+use crate::error::ErrorS;
+use crate::symbols::{native_detail, Completion, SymbolIndex, SymbolKind, Target, NATIVES};
+use crate::syntax::ast::{Program, Stmt};
+use crate::syntax::format::is_identifier;
+use crate::syntax::lexer::{Lexer, Token};
+use crate::types::Span;
+
+/// Documents larger than this (in bytes) wait for edits to pause before
+/// they're diagnosed
+const DEBOUNCE_SIZE: usize = 32 * 1024;
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(300);
+
+/// The source and analysis of a document, and a target in it with the span
+/// of its name
+type TargetAt = (String, Arc<Analysis>, Span, Target);
 
 #[derive(Debug)]
 struct Backend {
     client: Client,
+    documents: Documents,
 }
 
 impl Backend {
     pub fn new(client: Client) -> Self {
-        Self { client }
+        Self { client, documents: Documents::default() }
+    }
+
+    /// The source and analysis of a document, and the target at a position
+    /// in it
+    fn target_at(&self, uri: &Url, position: Position) -> Option<TargetAt> {
+        let (source, analysis) = self.documents.analysis(uri)?;
+        let offset = Diagnoser::get_offset(&source, position.into());
+        let (span, target) = analysis.index.target_at(offset)?;
+        Some((source, analysis, span, target))
+    }
+
+    /// Publish diagnostics for the current version of a document in the
+    /// background. Large documents are only diagnosed if they haven't
+    /// changed for [`DEBOUNCE_DELAY`].
+    fn diagnose(&self, uri: Url, is_debounced: bool) {
+        let client = self.client.clone();
+        let documents = self.documents.clone();
+        tokio::spawn(async move {
+            if is_debounced && documents.len(&uri).is_some_and(|len| len >= DEBOUNCE_SIZE) {
+                let version = documents.version(&uri);
+                tokio::time::sleep(DEBOUNCE_DELAY).await;
+                // A newer change publishes its own diagnostics
+                if documents.version(&uri) != version {
+                    return;
+                }
+            }
+
+            let Some((source, analysis)) = documents.analysis(&uri) else {
+                return;
+            };
+            let diagnostics =
+                Diagnoser::get_program_diagnostics(&source, &analysis.program, &analysis.errors);
+            client
+                .publish_diagnostics(
+                    uri,
+                    diagnostics.into_iter().map(Diagnostic::from).collect(),
+                    Some(analysis.version),
+                )
+                .await;
+        });
+    }
+}
+
+impl Backend {
+    /// The span of the name to rename at a position, and what it refers to.
+    /// Packages and natives can't be renamed.
+    fn rename_target(&self, uri: &Url, position: Position) -> jsonrpc::Result<Option<TargetAt>> {
+        let Some((source, analysis, span, target)) = self.target_at(uri, position) else {
+            return Ok(None);
+        };
+        let span = match &target {
+            Target::Symbol(_) => span,
+            // Only the name in `some::path:name` is renamed
+            Target::PackageGlobal { .. } => match identifiers(&source, &span).pop() {
+                Some(span) => span,
+                None => return Ok(None),
+            },
+            Target::Package(_) => {
+                return Err(jsonrpc::Error::invalid_params("packages can't be renamed"))
+            }
+            Target::Native(_) => {
+                return Err(jsonrpc::Error::invalid_params("natives can't be renamed"))
+            }
+        };
+        Ok(Some((source, analysis, span, target)))
+    }
+
+    /// Highlight the names in a document, or the part of it in a range, by
+    /// what they refer to
+    fn semantic_tokens(&self, uri: &Url, range: Option<Range>) -> Option<Vec<SemanticToken>> {
+        let (source, analysis) = self.documents.analysis(uri)?;
+        let index = &analysis.index;
+        let mut highlights = Vec::new();
+
+        for symbol in &index.symbols {
+            let (token_type, modifiers) = token_type(symbol.kind);
+            highlights.push((symbol.span.clone(), token_type, modifiers | MODIFIER_DECLARATION));
+        }
+
+        let mut packages = HashMap::new();
+        for reference in &index.references {
+            let span = reference.span.clone();
+            match &reference.target {
+                Target::Symbol(idx) => {
+                    let (token_type, modifiers) = token_type(index.symbols[*idx].kind);
+                    highlights.push((span, token_type, modifiers));
+                }
+                Target::Package(_) => {
+                    for name in identifiers(&source, &span) {
+                        highlights.push((name, TYPE_NAMESPACE, 0));
+                    }
+                }
+                // `some::path:name` is a package's path, then a global's name
+                Target::PackageGlobal { package, name } => {
+                    let mut names = identifiers(&source, &span);
+                    let Some(name_span) = names.pop() else {
+                        continue;
+                    };
+                    for path in names {
+                        highlights.push((path, TYPE_NAMESPACE, 0));
+                    }
+
+                    let package = packages
+                        .entry(package.clone())
+                        .or_insert_with(|| Package::load(uri, package));
+                    let kind = package
+                        .as_ref()
+                        .and_then(|package| package.index.global(name))
+                        .map(|symbol| symbol.kind);
+                    let (token_type, modifiers) = kind.map_or((TYPE_VARIABLE, 0), token_type);
+                    highlights.push((name_span, token_type, modifiers));
+                }
+                Target::Native(_) => {
+                    highlights.push((span, TYPE_FUNCTION, MODIFIER_DEFAULT_LIBRARY));
+                }
+            }
+        }
+
+        if let Some(range) = range {
+            let start = Diagnoser::get_offset(&source, range.start.into());
+            let end = Diagnoser::get_offset(&source, range.end.into());
+            highlights.retain(|(span, _, _)| span.start < end && start < span.end);
+        }
+        highlights.sort_by_key(|(span, _, _)| span.start);
+
+        // Each token's position is relative to the one before it
+        let mut previous = DiagnosisPosition::default();
+        let tokens = highlights
+            .into_iter()
+            .map(|(span, token_type, token_modifiers_bitset)| {
+                let position = Diagnoser::get_position(&source, span.start);
+                let delta_start = match position.line == previous.line {
+                    true => position.character - previous.character,
+                    false => position.character,
+                };
+                let token = SemanticToken {
+                    delta_line: position.line - previous.line,
+                    delta_start,
+                    length: source[span].encode_utf16().count() as u32,
+                    token_type,
+                    token_modifiers_bitset,
+                };
+                previous = position;
+                token
+            })
+            .collect();
+
+        Some(tokens)
+    }
+}
+
+/// Semantic token types, numbered by their position
+const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::CLASS,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PARAMETER,
+];
+const TYPE_NAMESPACE: u32 = 0;
+const TYPE_CLASS: u32 = 1;
+const TYPE_FUNCTION: u32 = 2;
+const TYPE_METHOD: u32 = 3;
+const TYPE_PROPERTY: u32 = 4;
+const TYPE_VARIABLE: u32 = 5;
+const TYPE_PARAMETER: u32 = 6;
+
+/// Semantic token modifiers, which are bits numbered by their position
+const TOKEN_MODIFIERS: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::STATIC,
+    SemanticTokenModifier::DEFAULT_LIBRARY,
+];
+const MODIFIER_DECLARATION: u32 = 1 << 0;
+const MODIFIER_STATIC: u32 = 1 << 1;
+const MODIFIER_DEFAULT_LIBRARY: u32 = 1 << 2;
+
+/// The semantic token type and modifiers of a symbol
+fn token_type(kind: SymbolKind) -> (u32, u32) {
+    match kind {
+        SymbolKind::Class => (TYPE_CLASS, 0),
+        SymbolKind::Function => (TYPE_FUNCTION, 0),
+        SymbolKind::Variable => (TYPE_VARIABLE, 0),
+        SymbolKind::Parameter => (TYPE_PARAMETER, 0),
+        SymbolKind::Field => (TYPE_PROPERTY, 0),
+        SymbolKind::Method => (TYPE_METHOD, 0),
+        SymbolKind::StaticField => (TYPE_PROPERTY, MODIFIER_STATIC),
+        SymbolKind::StaticMethod => (TYPE_METHOD, MODIFIER_STATIC),
+    }
+}
+
+/// Spans of the identifiers in part of a document
+fn identifiers(source: &str, span: &Span) -> Vec<Span> {
+    Lexer::new(&source[span.clone()])
+        .filter_map(Result::ok)
+        .filter(|(_, token, _)| matches!(token, Token::Identifier(_)))
+        .map(|(start, _, end)| span.start + start..span.start + end)
+        .collect()
+}
+
+/// Builds the outline of a document from its declarations
+struct Outline<'a> {
+    source: &'a str,
+    index: &'a SymbolIndex,
+}
+
+impl Outline<'_> {
+    /// The outline entry for the declaration of `name` at `span`
+    fn symbol(&self, name: &str, span: &Span) -> Option<DocumentSymbol> {
+        let symbol = self
+            .index
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == name && symbol.decl_span == *span)?;
+
+        #[allow(deprecated)]
+        Some(DocumentSymbol {
+            name: symbol.name.clone(),
+            detail: Some(symbol.detail.clone()),
+            kind: symbol.kind.into(),
+            tags: None,
+            deprecated: None,
+            range: range(self.source, span),
+            selection_range: range(self.source, &symbol.span),
+            children: None,
+        })
+    }
+}
+
+/// The open documents, by URI
+#[derive(Clone, Debug, Default)]
+struct Documents(Arc<Mutex<HashMap<Url, Document>>>);
+
+impl Documents {
+    fn open(&self, uri: Url, source: String, version: i32) {
+        self.0.lock().unwrap().insert(uri, Document { source, version, analysis: None });
+    }
+
+    fn close(&self, uri: &Url) {
+        self.0.lock().unwrap().remove(uri);
+    }
+
+    /// Apply edits to a document in order. Changes without a range replace
+    /// the whole document.
+    fn change(&self, uri: &Url, version: i32, changes: Vec<TextDocumentContentChangeEvent>) {
+        let mut documents = self.0.lock().unwrap();
+        let Some(document) = documents.get_mut(uri) else {
+            return;
+        };
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start = Diagnoser::get_offset(&document.source, range.start.into());
+                    let end = Diagnoser::get_offset(&document.source, range.end.into());
+                    document.source.replace_range(start..end.max(start), &change.text);
+                }
+                None => document.source = change.text,
+            }
+        }
+        document.version = version;
+        // Saving can change the source without changing the version
+        document.analysis = None;
+    }
+
+    fn source(&self, uri: &Url) -> Option<String> {
+        self.0.lock().unwrap().get(uri).map(|document| document.source.clone())
+    }
+
+    fn version(&self, uri: &Url) -> Option<i32> {
+        self.0.lock().unwrap().get(uri).map(|document| document.version)
+    }
+
+    fn len(&self, uri: &Url) -> Option<usize> {
+        self.0.lock().unwrap().get(uri).map(|document| document.source.len())
+    }
+
+    /// The source of a document and its analysis, which is only recomputed
+    /// when the document's version changes
+    fn analysis(&self, uri: &Url) -> Option<(String, Arc<Analysis>)> {
+        let mut documents = self.0.lock().unwrap();
+        let document = documents.get_mut(uri)?;
+        let analysis = match &document.analysis {
+            Some(analysis) if analysis.version == document.version => Arc::clone(analysis),
+            _ => {
+                let analysis = Arc::new(Analysis::new(&document.source, document.version));
+                document.analysis = Some(Arc::clone(&analysis));
+                analysis
+            }
+        };
+        Some((document.source.clone(), analysis))
+    }
+}
+
+#[derive(Debug)]
+struct Document {
+    source: String,
+    version: i32,
+    analysis: Option<Arc<Analysis>>,
+}
+
+/// A parsed and indexed version of a document
+#[derive(Debug)]
+struct Analysis {
+    version: i32,
+    program: Program,
+    /// Errors from parsing. The program has the statements that could be
+    /// parsed.
+    errors: Vec<ErrorS>,
+    index: SymbolIndex,
+}
+
+impl Analysis {
+    fn new(source: &str, version: i32) -> Self {
+        let (program, errors) = crate::syntax::parse_recover(source, 0);
+        let index = SymbolIndex::from_program(source, &program);
+        Self { version, program, errors, index }
+    }
+}
+
+/// A package that's imported by a document
+struct Package {
+    uri: Url,
+    source: String,
+    index: SymbolIndex,
+}
+
+impl Package {
+    /// Find and index a package's file the same way `locks run` does: next to
+    /// the document, then in `LOCKS_PATH`
+    fn load(document: &Url, name: &str) -> Option<Self> {
+        let relative_path = PathBuf::from(format!("{}.locks", name.replace("::", "/")));
+        let dir = document.to_file_path().ok()?.parent()?.to_path_buf();
+        let path = iter::once(dir)
+            .chain(crate::cmd::env_package_paths())
+            .map(|path| path.join(&relative_path))
+            .find(|path| path.is_file())?;
+        let source = fs::read_to_string(&path).ok()?;
+        let index = SymbolIndex::new(&source);
+        let uri = Url::from_file_path(&path).ok()?;
+
+        Some(Self { uri, source, index })
+    }
+
+    fn global(&self, name: &str) -> Option<Location> {
+        let symbol = self.index.global(name)?;
+        Some(Location::new(self.uri.clone(), range(&self.source, &symbol.span)))
     }
 }
 
@@ -26,7 +407,41 @@ impl LanguageServer for Backend {
     async fn initialize(&self, _: InitializeParams) -> jsonrpc::Result<InitializeResult> {
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncKind::FULL.into()),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                            include_text: Some(true),
+                        })),
+                        ..Default::default()
+                    },
+                )),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                })),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                semantic_tokens_provider: Some(
+                    SemanticTokensOptions {
+                        legend: SemanticTokensLegend {
+                            token_types: TOKEN_TYPES.to_vec(),
+                            token_modifiers: TOKEN_MODIFIERS.to_vec(),
+                        },
+                        range: Some(true),
+                        full: Some(SemanticTokensFullOptions::Bool(true)),
+                        ..Default::default()
+                    }
+                    .into(),
+                ),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec![".".to_string()]),
+                    ..Default::default()
+                }),
                 ..Default::default()
             },
             server_info: Some(ServerInfo {
@@ -41,31 +456,322 @@ impl LanguageServer for Backend {
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let source = &params.text_document.text;
-        let uri = params.text_document.uri;
-        let version = Some(params.text_document.version);
-        let diagnostics = Diagnoser::get_diagnostics(source);
-        self.client
-            .publish_diagnostics(
-                uri,
-                diagnostics.iter().map(|x| (*x).clone().into()).collect(),
-                version,
-            )
-            .await;
+        let document = params.text_document;
+        self.documents.open(document.uri.clone(), document.text, document.version);
+        self.diagnose(document.uri, false);
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        let source = &params.content_changes.first().unwrap().text;
         let uri = params.text_document.uri;
-        let version = Some(params.text_document.version);
-        let diagnostics = Diagnoser::get_diagnostics(source);
-        self.client
-            .publish_diagnostics(
-                uri,
-                diagnostics.iter().map(|x| (*x).clone().into()).collect(),
-                version,
-            )
-            .await;
+        self.documents.change(&uri, params.text_document.version, params.content_changes);
+        self.diagnose(uri, true);
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let uri = params.text_document.uri;
+        if let (Some(text), Some(version)) = (params.text, self.documents.version(&uri)) {
+            let change = TextDocumentContentChangeEvent { range: None, range_length: None, text };
+            self.documents.change(&uri, version, vec![change]);
+        }
+        self.diagnose(uri, false);
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.documents.close(&uri);
+        self.client.publish_diagnostics(uri, Vec::new(), None).await;
+    }
+
+    async fn hover(&self, params: HoverParams) -> jsonrpc::Result<Option<Hover>> {
+        let params = params.text_document_position_params;
+        let Some((source, analysis, span, target)) =
+            self.target_at(&params.text_document.uri, params.position)
+        else {
+            return Ok(None);
+        };
+
+        let value = match &target {
+            Target::Symbol(idx) => {
+                format!("```locks\n{}\n```", analysis.index.symbols[*idx].detail)
+            }
+            Target::Package(name) => format!("```locks\npackage {name}\n```"),
+            Target::PackageGlobal { package, name } => {
+                let detail = Package::load(&params.text_document.uri, package)
+                    .and_then(|package| {
+                        package.index.global(name).map(|symbol| symbol.detail.clone())
+                    })
+                    .unwrap_or_else(|| name.clone());
+                format!("```locks\n{detail}\n```\n\nFrom package `{package}`")
+            }
+            Target::Native(name) => {
+                let detail = NATIVES
+                    .iter()
+                    .find(|(native, _)| native == name)
+                    .map(|(name, params)| native_detail(name, params))
+                    .unwrap_or_else(|| name.clone());
+                format!("```locks\n{detail}\n```")
+            }
+        };
+
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+            range: Some(range(&source, &span)),
+        }))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> jsonrpc::Result<Option<GotoDefinitionResponse>> {
+        let params = params.text_document_position_params;
+        let uri = params.text_document.uri;
+        let Some((source, analysis, _, target)) = self.target_at(&uri, params.position) else {
+            return Ok(None);
+        };
+
+        let location = match target {
+            Target::Symbol(idx) => {
+                Some(Location::new(uri, range(&source, &analysis.index.symbols[idx].span)))
+            }
+            Target::Package(name) => Package::load(&uri, &name)
+                .map(|package| Location::new(package.uri, Range::default())),
+            Target::PackageGlobal { package, name } => {
+                Package::load(&uri, &package).and_then(|package| package.global(&name))
+            }
+            Target::Native(_) => None,
+        };
+
+        Ok(location.map(GotoDefinitionResponse::Scalar))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> jsonrpc::Result<Option<Vec<Location>>> {
+        let include_declaration = params.context.include_declaration;
+        let params = params.text_document_position;
+        let uri = params.text_document.uri;
+        let Some((source, analysis, _, target)) = self.target_at(&uri, params.position) else {
+            return Ok(None);
+        };
+
+        let declaration = match (&target, include_declaration) {
+            (_, false) => None,
+            (Target::Symbol(idx), true) => {
+                Some(Location::new(uri.clone(), range(&source, &analysis.index.symbols[*idx].span)))
+            }
+            (Target::Package(_) | Target::Native(_), true) => None,
+            (Target::PackageGlobal { package, name }, true) => {
+                Package::load(&uri, package).and_then(|package| package.global(name))
+            }
+        };
+
+        let references = analysis
+            .index
+            .references(&target)
+            .into_iter()
+            .map(|span| Location::new(uri.clone(), range(&source, &span)));
+
+        Ok(Some(declaration.into_iter().chain(references).collect()))
+    }
+
+    async fn completion(
+        &self,
+        params: CompletionParams,
+    ) -> jsonrpc::Result<Option<CompletionResponse>> {
+        let params = params.text_document_position;
+        let Some((source, analysis)) = self.documents.analysis(&params.text_document.uri) else {
+            return Ok(None);
+        };
+
+        let offset = Diagnoser::get_offset(&source, params.position.into());
+        let items =
+            analysis.index.completions(&source, offset).into_iter().map(CompletionItem::from);
+
+        Ok(Some(CompletionResponse::Array(items.collect())))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> jsonrpc::Result<Option<PrepareRenameResponse>> {
+        let target = self.rename_target(&params.text_document.uri, params.position)?;
+        Ok(target.map(|(source, _, span, _)| PrepareRenameResponse::Range(range(&source, &span))))
+    }
+
+    async fn rename(&self, params: RenameParams) -> jsonrpc::Result<Option<WorkspaceEdit>> {
+        let new_name = params.new_name;
+        if !is_identifier(&new_name) {
+            let message = format!("{new_name:?} is not a valid identifier");
+            return Err(jsonrpc::Error::invalid_params(message));
+        }
+        let params = params.text_document_position;
+        let uri = params.text_document.uri;
+        let Some((source, analysis, _, target)) = self.rename_target(&uri, params.position)? else {
+            return Ok(None);
+        };
+
+        let mut changes = HashMap::new();
+        let edit = |source: &str, span: &Span| TextEdit::new(range(source, span), new_name.clone());
+        let spans = match &target {
+            Target::Symbol(idx) => iter::once(analysis.index.symbols[*idx].span.clone()).collect(),
+            // The global is declared in the package, so it's renamed there
+            // too. Other documents that import the package aren't changed.
+            Target::PackageGlobal { package, name } => {
+                let Some(package) = Package::load(&uri, package) else {
+                    let message = format!("package not found: {package}");
+                    return Err(jsonrpc::Error::invalid_params(message));
+                };
+                if let Some((span, target)) = package
+                    .index
+                    .global(name)
+                    .and_then(|symbol| package.index.target_at(symbol.span.start))
+                {
+                    let edits = iter::once(span)
+                        .chain(package.index.references(&target))
+                        .map(|span| edit(&package.source, &span))
+                        .collect();
+                    changes.insert(package.uri, edits);
+                }
+                Vec::new()
+            }
+            Target::Package(_) | Target::Native(_) => Vec::new(),
+        };
+        let references =
+            analysis.index.references(&target).into_iter().filter_map(|span| match target {
+                Target::PackageGlobal { .. } => identifiers(&source, &span).pop(),
+                _ => Some(span),
+            });
+        let edits = spans.into_iter().chain(references).map(|span| edit(&source, &span));
+        changes.entry(uri).or_insert_with(Vec::new).extend(edits);
+
+        Ok(Some(WorkspaceEdit::new(changes)))
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> jsonrpc::Result<Option<DocumentSymbolResponse>> {
+        let Some((source, analysis)) = self.documents.analysis(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        let outline = Outline { source: &source, index: &analysis.index };
+
+        let symbols = analysis
+            .program
+            .stmts
+            .iter()
+            .filter_map(|(stmt, span)| match stmt {
+                Stmt::Class(class) => {
+                    let fields = class.fields.iter().chain(&class.static_fields);
+                    let methods = class.methods.iter().chain(&class.static_methods);
+                    let mut members = fields
+                        .map(|(field, span)| (&field.identifier.name, span))
+                        .chain(methods.map(|(method, span)| (&method.name, span)))
+                        .collect::<Vec<_>>();
+                    // Members are listed in the order they're written
+                    members.sort_by_key(|(_, span)| span.start);
+
+                    let mut symbol = outline.symbol(&class.name, span)?;
+                    symbol.children = Some(
+                        members
+                            .into_iter()
+                            .filter_map(|(name, span)| outline.symbol(name, span))
+                            .collect(),
+                    );
+                    Some(symbol)
+                }
+                Stmt::Fn(fn_) => outline.symbol(&fn_.name, span),
+                Stmt::Assign(assign) => outline.symbol(&assign.identifier.name, span),
+                _ => None,
+            })
+            .collect();
+
+        Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> jsonrpc::Result<Option<SemanticTokensResult>> {
+        let tokens = self.semantic_tokens(&params.text_document.uri, None);
+        Ok(tokens.map(|data| SemanticTokens { result_id: None, data }.into()))
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> jsonrpc::Result<Option<SemanticTokensRangeResult>> {
+        let tokens = self.semantic_tokens(&params.text_document.uri, Some(params.range));
+        Ok(tokens.map(|data| SemanticTokens { result_id: None, data }.into()))
+    }
+
+    async fn formatting(
+        &self,
+        params: DocumentFormattingParams,
+    ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let Some(source) = self.documents.source(&params.text_document.uri) else {
+            return Ok(None);
+        };
+
+        // Documents with syntax errors are left as they are
+        let Ok(formatted) = crate::syntax::format::format(&source) else {
+            return Ok(None);
+        };
+        if formatted == source {
+            return Ok(Some(Vec::new()));
+        }
+
+        Ok(Some(vec![TextEdit::new(range(&source, &(0..source.len())), formatted)]))
+    }
+}
+
+fn range(source: &str, span: &Span) -> Range {
+    Diagnoser::get_range(source, span).into()
+}
+
+impl From<Completion> for CompletionItem {
+    fn from(value: Completion) -> Self {
+        // Callables complete with a snippet that has a placeholder for each
+        // parameter
+        let insert_text = value.params.map(|params| {
+            let params = params
+                .iter()
+                .enumerate()
+                .map(|(idx, param)| format!("${{{}:{param}}}", idx + 1))
+                .collect::<Vec<_>>();
+            format!("{}({})", value.label, params.join(", "))
+        });
+
+        CompletionItem {
+            kind: Some(value.kind.into()),
+            detail: Some(value.detail),
+            insert_text_format: insert_text.as_ref().map(|_| InsertTextFormat::SNIPPET),
+            insert_text,
+            label: value.label,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<SymbolKind> for CompletionItemKind {
+    fn from(value: SymbolKind) -> Self {
+        match value {
+            SymbolKind::Class => CompletionItemKind::CLASS,
+            SymbolKind::Function => CompletionItemKind::FUNCTION,
+            SymbolKind::Variable | SymbolKind::Parameter => CompletionItemKind::VARIABLE,
+            SymbolKind::Field | SymbolKind::StaticField => CompletionItemKind::FIELD,
+            SymbolKind::Method | SymbolKind::StaticMethod => CompletionItemKind::METHOD,
+        }
+    }
+}
+
+impl From<SymbolKind> for lsp_types::SymbolKind {
+    fn from(value: SymbolKind) -> Self {
+        match value {
+            SymbolKind::Class => lsp_types::SymbolKind::CLASS,
+            SymbolKind::Function => lsp_types::SymbolKind::FUNCTION,
+            SymbolKind::Variable | SymbolKind::Parameter => lsp_types::SymbolKind::VARIABLE,
+            SymbolKind::Field | SymbolKind::StaticField => lsp_types::SymbolKind::FIELD,
+            SymbolKind::Method | SymbolKind::StaticMethod => lsp_types::SymbolKind::METHOD,
+        }
     }
 }
 
@@ -86,6 +792,12 @@ impl From<DiagnosisPosition> for Position {
     }
 }
 
+impl From<Position> for DiagnosisPosition {
+    fn from(value: Position) -> Self {
+        DiagnosisPosition { line: value.line, character: value.character }
+    }
+}
+
 impl From<DiagnosisRange> for Range {
     fn from(value: DiagnosisRange) -> Self {
         Range { start: value.start.into(), end: value.end.into() }
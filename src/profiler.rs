@@ -0,0 +1,137 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::vm::debug::{Location, SourceName};
+use crate::vm::profile::{Profile, Profiler};
+use crate::vm::VM;
+
+/// Run a file with the profiler, then print the functions & lines that took
+/// the longest to stderr
+pub fn run(path: &str, folded: Option<&str>, limit: usize) -> Result<()> {
+    let source =
+        fs::read_to_string(path).with_context(|| format!("could not read file: {path}"))?;
+
+    let mut vm = VM::new();
+    vm.add_package_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+    crate::cmd::add_env_package_paths(&mut vm);
+
+    vm.set_profiler(Some(Profiler::new()));
+    let result = vm.run(&source, &mut io::stdout());
+    let profile = vm.take_profiler().unwrap_or_default().finish(&vm);
+
+    let report = Report { path: Path::new(path), profile: &profile };
+    if let Some(folded) = folded {
+        let file = File::create(folded)
+            .with_context(|| format!("could not write folded stacks: {folded}"))?;
+        let writer = &mut BufWriter::new(file);
+        report.write_folded(writer).and_then(|()| writer.flush())?;
+    }
+    report.write_summary(&mut io::stderr().lock(), limit)?;
+
+    if let Err(errors) = result {
+        crate::error::report_vm_errors(&mut io::stderr().lock(), &vm, path, &errors);
+        bail!("program exited with errors");
+    }
+
+    Ok(())
+}
+
+/// A [`Profile`] of the file at `path`
+struct Report<'a> {
+    path: &'a Path,
+    profile: &'a Profile,
+}
+
+impl Report<'_> {
+    /// Write the time spent in each call stack in the folded format that
+    /// flamegraph tools read (e.g. `<script> (fib.locks:1);fib (fib.locks:2) 1234`),
+    /// in microseconds
+    fn write_folded(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut stacks = self
+            .profile
+            .stacks
+            .iter()
+            .map(|(stack, time)| {
+                let names = stack.iter().map(|&idx| self.function(idx)).collect::<Vec<_>>();
+                (names.join(";"), time.as_micros())
+            })
+            .filter(|&(_, micros)| micros > 0)
+            .collect::<Vec<_>>();
+        stacks.sort();
+
+        for (stack, micros) in stacks {
+            writeln!(writer, "{stack} {micros}")?;
+        }
+        Ok(())
+    }
+
+    /// Write the functions & lines with the most self time, up to `limit` of
+    /// each
+    fn write_summary(&self, writer: &mut impl Write, limit: usize) -> io::Result<()> {
+        let profile = self.profile;
+        writeln!(writer, "total time: {}", millis(profile.total_time))?;
+
+        let mut functions = profile.functions.iter().enumerate().collect::<Vec<_>>();
+        functions.sort_by_key(|(_, function)| Reverse(function.self_time));
+        writeln!(writer)?;
+        writeln!(writer, "{:>12} {:>12} {:>10}  function", "self", "total", "calls")?;
+        for (idx, function) in functions.into_iter().take(limit) {
+            writeln!(
+                writer,
+                "{:>12} {:>12} {:>10}  {}",
+                millis(function.self_time),
+                millis(function.total_time),
+                function.calls,
+                self.function(idx)
+            )?;
+        }
+
+        let mut lines = profile
+            .lines
+            .iter()
+            .filter_map(|line| Some((line, line.location.as_ref()?)))
+            .collect::<Vec<_>>();
+        lines.sort_by_key(|(line, _)| Reverse(line.self_time));
+        writeln!(writer)?;
+        writeln!(writer, "{:>12} {:>12} {:>10}  line", "self", "total", "calls")?;
+        for (line, location) in lines.into_iter().take(limit) {
+            writeln!(
+                writer,
+                "{:>12} {:>12} {:>10}  {}",
+                millis(line.self_time),
+                millis(line.total_time),
+                line.calls,
+                self.location(location)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// A function's name and where it is (e.g. `fib (fib.locks:2)`)
+    fn function(&self, idx: usize) -> String {
+        let function = &self.profile.functions[idx];
+        let name = if function.name.is_empty() { "<script>" } else { &function.name };
+        match &function.location {
+            Some(location) => format!("{name} ({})", self.location(location)),
+            None => name.to_string(),
+        }
+    }
+
+    fn location(&self, location: &Location) -> String {
+        match &location.source {
+            SourceName::Lib => format!("<lib>:{}", location.line),
+            SourceName::Script => format!("{}:{}", self.path.display(), location.line),
+            SourceName::Package(path) => format!("{}:{}", path.display(), location.line),
+        }
+    }
+}
+
+fn millis(time: Duration) -> String {
+    format!("{:.3}ms", time.as_secs_f64() * 1000.0)
+}
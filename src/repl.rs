@@ -17,6 +17,7 @@ use crate::vm::VM;
 
 pub fn run() -> Result<()> {
     let mut vm = VM::default();
+    vm.add_package_path(".");
     let mut editor = editor().context("could not start REPL")?;
     let stdout = &mut io::stdout().lock();
     let stderr = &mut io::stderr().lock();
@@ -28,7 +29,7 @@ pub fn run() -> Result<()> {
         match line {
             Ok(reedline::Signal::Success(line)) => {
                 if let Err(errors) = vm.run(&line, stdout) {
-                    crate::error::report_errors(stderr, &vm.source, &errors)
+                    crate::error::report_vm_errors(stderr, &vm, "<script>", &errors)
                 }
             }
             Ok(reedline::Signal::CtrlC) => exit(130),
@@ -184,23 +185,23 @@ impl reedline::Validator for Validator {
 pub struct Prompt;
 
 impl reedline::Prompt for Prompt {
-    fn render_prompt_left(&self) -> Cow<str> {
+    fn render_prompt_left(&self) -> Cow<'_, str> {
         Cow::Borrowed(">>> ")
     }
 
-    fn render_prompt_right(&self) -> Cow<str> {
+    fn render_prompt_right(&self) -> Cow<'_, str> {
         Cow::Borrowed("")
     }
 
-    fn render_prompt_indicator(&self, _: PromptEditMode) -> Cow<str> {
+    fn render_prompt_indicator(&self, _: PromptEditMode) -> Cow<'_, str> {
         Cow::Borrowed("")
     }
 
-    fn render_prompt_multiline_indicator(&self) -> Cow<str> {
+    fn render_prompt_multiline_indicator(&self) -> Cow<'_, str> {
         Cow::Borrowed("... ")
     }
 
-    fn render_prompt_history_search_indicator(&self, _: PromptHistorySearch) -> Cow<str> {
+    fn render_prompt_history_search_indicator(&self, _: PromptHistorySearch) -> Cow<'_, str> {
         Cow::Borrowed("")
     }
 }
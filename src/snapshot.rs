@@ -0,0 +1,191 @@
+//! Golden output tests for Locks scripts
+//!
+//! A script states what it should print with comments: `// out: ` for each
+//! line written to stdout and `// err: ` for the first uncaught error. A
+//! script with an `// err: ` comment is expected to exit with an error.
+//!
+//! Scripts without an `// err: ` comment can also expect the error in an
+//! `// out: ` comment after the rest of their output, as the examples in
+//! `res/examples` do.
+//!
+//! ```locks
+//! println("hello"); // out: hello
+//! println(1 + nil); // err: TypeError: unsupported operand type(s) for +: "number" and "nil"
+//! ```
+
+use std::path::PathBuf;
+
+use crate::error::ErrorS;
+use crate::vm::VM;
+
+pub const OUT_COMMENT: &str = "// out: ";
+pub const ERR_COMMENT: &str = "// err: ";
+
+/// What a script printed, or is expected to print
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Output {
+    pub stdout: String,
+    /// The first uncaught error, if there was one (e.g. `TypeError: ...`)
+    pub stderr: String,
+}
+
+impl Output {
+    /// The output of a finished run
+    pub fn new(stdout: &[u8], result: Result<(), Vec<ErrorS>>) -> Self {
+        let stdout = String::from_utf8_lossy(stdout).into_owned();
+        let stderr = match result {
+            Ok(()) => String::new(),
+            Err(errors) => {
+                errors.first().map_or_else(String::new, |(error, _)| format!("{error}\n"))
+            }
+        };
+        Self { stdout, stderr }
+    }
+
+    /// The output a script expects from its `// out: ` and `// err: `
+    /// comments
+    pub fn expected(source: &str) -> Self {
+        let mut output = Self::default();
+        for line in source.lines() {
+            if let Some((stream, text)) = expectation(line) {
+                let stream = match stream {
+                    Stream::Stdout => &mut output.stdout,
+                    Stream::Stderr => &mut output.stderr,
+                };
+                *stream += text;
+                *stream += "\n";
+            }
+        }
+        output
+    }
+
+    /// Whether this output is what `expected` states, including uncaught
+    /// errors expected by `// out: ` comments
+    pub fn matches(&self, expected: &Self) -> bool {
+        self == expected
+            || (expected.stderr.is_empty()
+                && !self.stderr.is_empty()
+                && expected.stdout.strip_prefix(self.stdout.as_str()) == Some(self.stderr.as_str()))
+    }
+
+    /// Whether the script exits without an error
+    pub fn is_success(&self) -> bool {
+        self.stderr.is_empty()
+    }
+}
+
+/// Run a script in a new [`VM`] that imports packages from `package_paths`
+pub fn run(source: &str, package_paths: &[PathBuf]) -> Output {
+    let mut vm = VM::new();
+    for path in package_paths {
+        vm.add_package_path(path);
+    }
+
+    let mut stdout = Vec::new();
+    let result = vm.run(source, &mut stdout);
+    Output::new(&stdout, result)
+}
+
+/// Rewrite a script's expectations to match `output`
+///
+/// Existing `// out: ` and `// err: ` comments are updated in order. Extra
+/// comments are removed, and lines without a comment to update are added at
+/// the end of the script.
+pub fn update(source: &str, output: &Output) -> String {
+    let mut stdout = output.stdout.lines();
+    let mut stderr = output.stderr.lines();
+
+    let mut updated = String::with_capacity(source.len());
+    for line in source.lines() {
+        let Some((stream, text)) = expectation(line) else {
+            updated += line;
+            updated += "\n";
+            continue;
+        };
+        let (comment, next) = match stream {
+            Stream::Stdout => (OUT_COMMENT, stdout.next()),
+            Stream::Stderr => (ERR_COMMENT, stderr.next()),
+        };
+
+        let code = &line[..line.len() - text.len() - comment.len()];
+        match next {
+            Some(next) => {
+                updated += code;
+                updated += comment;
+                updated += next;
+                updated += "\n";
+            }
+            // Lines that only had the comment are removed with it
+            None if code.trim().is_empty() => {}
+            None => {
+                updated += code.trim_end();
+                updated += "\n";
+            }
+        }
+    }
+
+    for line in stdout {
+        updated += OUT_COMMENT;
+        updated += line;
+        updated += "\n";
+    }
+    for line in stderr {
+        updated += ERR_COMMENT;
+        updated += line;
+        updated += "\n";
+    }
+    updated
+}
+
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// The stream and text of the expectation comment on a line
+fn expectation(line: &str) -> Option<(Stream, &str)> {
+    if let Some(idx) = line.find(OUT_COMMENT) {
+        Some((Stream::Stdout, &line[idx + OUT_COMMENT.len()..]))
+    } else {
+        line.find(ERR_COMMENT).map(|idx| (Stream::Stderr, &line[idx + ERR_COMMENT.len()..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn expected() {
+        let source = "println(1); // out: 1\n// out: 2\nprintln(nil + 1); // err: TypeError: bad\n";
+        let output = Output::expected(source);
+        assert_eq!(output.stdout, "1\n2\n");
+        assert_eq!(output.stderr, "TypeError: bad\n");
+        assert!(!output.is_success());
+    }
+
+    #[test]
+    fn errors_in_out_comments() {
+        let expected =
+            Output::expected("println(1); // out: 1\nprintln(nil + 1); // out: TypeError: bad\n");
+        let actual = Output { stdout: "1\n".to_string(), stderr: "TypeError: bad\n".to_string() };
+        assert!(actual.matches(&expected));
+        assert!(!Output { stderr: String::new(), ..actual.clone() }.matches(&expected));
+        assert!(!Output { stdout: String::new(), ..actual }.matches(&expected));
+    }
+
+    #[test]
+    fn update_in_order() {
+        let source = "println(1); // out: 2\n// out: 3\nprintln(4);\n// err: Error: old\n";
+        let output = Output { stdout: "1\n".to_string(), stderr: String::new() };
+        assert_eq!(update(source, &output), "println(1); // out: 1\nprintln(4);\n");
+
+        let output = Output { stdout: "1\n5\n6\n".to_string(), stderr: "Error: new\n".to_string() };
+        assert_eq!(
+            update(source, &output),
+            "println(1); // out: 1\n// out: 5\nprintln(4);\n// err: Error: new\n// out: 6\n"
+        );
+    }
+}
@@ -0,0 +1,981 @@
+use std::collections::{HashMap, HashSet};
+use std::iter;
+
+use crate::syntax::ast::{
+    Expr, ExprS, Program, Stmt, StmtAssign, StmtBlock, StmtClass, StmtFn, StmtS,
+};
+use crate::syntax::lexer::{Lexer, Token};
+use crate::types::Span;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SymbolKind {
+    Class,
+    Function,
+    Variable,
+    Parameter,
+    Field,
+    Method,
+    StaticField,
+    StaticMethod,
+}
+
+impl SymbolKind {
+    /// Whether the symbol belongs to a class instead of a scope
+    pub fn is_member(self) -> bool {
+        matches!(
+            self,
+            SymbolKind::Field
+                | SymbolKind::Method
+                | SymbolKind::StaticField
+                | SymbolKind::StaticMethod
+        )
+    }
+
+    pub fn is_static(self) -> bool {
+        matches!(self, SymbolKind::StaticField | SymbolKind::StaticMethod)
+    }
+}
+
+/// A named declaration in a Locks program
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Where the name is written in the declaration
+    pub span: Span,
+    /// The whole declaration (e.g. a function including its body)
+    pub decl_span: Span,
+    /// The class that a field or method belongs to
+    pub class: Option<String>,
+    /// Short description of the declaration (e.g. `fn add(a, b)`)
+    pub detail: String,
+    /// Whether the symbol is declared at the top level of the program
+    pub is_global: bool,
+    /// Where a local is visible, from its declaration to the end of its
+    /// scope. [`None`] for globals and members.
+    pub scope: Option<Span>,
+    /// Parameters of a function or method
+    pub params: Vec<String>,
+    /// The class a variable is an instance of, if it was declared by calling
+    /// the class (e.g. `let p = Point(1, 2);`)
+    pub instance_of: Option<String>,
+}
+
+/// What a name in the program refers to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Target {
+    /// A symbol declared in the program, by index
+    Symbol(usize),
+    /// An imported package (e.g. `import some::path;`)
+    Package(String),
+    /// A global declared in another package (e.g. `some::path:name`)
+    PackageGlobal { package: String, name: String },
+    /// A function built into the VM (e.g. `println`)
+    Native(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reference {
+    pub span: Span,
+    pub target: Target,
+}
+
+/// Declarations in a program and the names that refer to them
+///
+/// Globals are resolved by name like the [`VM`](crate::vm::VM) does, so they
+/// can be used before they're declared. Fields and methods are resolved by
+/// name too, preferring the class of `this`, `super` or a class name when
+/// it's known.
+#[derive(Debug, Default)]
+pub struct SymbolIndex {
+    pub symbols: Vec<Symbol>,
+    /// Every resolved use of a name, not including declarations
+    pub references: Vec<Reference>,
+    /// Superclass names by class name
+    supers: HashMap<String, Option<String>>,
+}
+
+impl SymbolIndex {
+    /// Index a program. Source that can't be parsed is skipped.
+    pub fn new(source: &str) -> Self {
+        let (program, _) = crate::syntax::parse_recover(source, 0);
+        Self::from_program(source, &program)
+    }
+
+    pub fn from_program(source: &str, program: &Program) -> Self {
+        let tokens = Lexer::new(source).filter_map(Result::ok).collect();
+        let mut indexer = Indexer {
+            tokens,
+            package: program.package.as_deref(),
+            index: SymbolIndex::default(),
+            scopes: Vec::new(),
+            globals: HashMap::new(),
+            classes: Vec::new(),
+            pending_globals: Vec::new(),
+            pending_members: Vec::new(),
+        };
+        indexer.program(program);
+        indexer.finish()
+    }
+
+    /// The declaration or reference at an offset, and the span of its name
+    pub fn target_at(&self, offset: usize) -> Option<(Span, Target)> {
+        let contains = |span: &Span| span.start <= offset && offset <= span.end;
+
+        let symbol = self
+            .symbols
+            .iter()
+            .enumerate()
+            .find(|(_, symbol)| contains(&symbol.span))
+            .map(|(idx, symbol)| (symbol.span.clone(), Target::Symbol(idx)));
+
+        symbol.or_else(|| {
+            self.references
+                .iter()
+                .find(|reference| contains(&reference.span))
+                .map(|reference| (reference.span.clone(), reference.target.clone()))
+        })
+    }
+
+    /// Spans of every use of a target, not including its declaration
+    pub fn references(&self, target: &Target) -> Vec<Span> {
+        self.references
+            .iter()
+            .filter(|reference| reference.target == *target)
+            .map(|reference| reference.span.clone())
+            .collect()
+    }
+
+    /// A global declared at the top level of the program
+    pub fn global(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.is_global && symbol.name == name)
+    }
+
+    /// Names that can be written at an offset, innermost scope first
+    ///
+    /// After a `.`, these are the members of the object before it when its
+    /// class is known (`this`, `super`, a class or a variable declared by
+    /// calling a class), or every instance member otherwise. Anywhere else,
+    /// these are the locals in scope, globals and natives.
+    pub fn completions(&self, source: &str, offset: usize) -> Vec<Completion> {
+        let before = &source[..offset];
+        let before = before.trim_end_matches(is_identifier_char);
+
+        let symbols = match before.strip_suffix('.') {
+            Some(before) => {
+                let object = &before[before.trim_end_matches(is_identifier_char).len()..];
+                self.members_of(object, offset)
+            }
+            // Other packages' globals aren't indexed
+            None if before.ends_with(':') => return Vec::new(),
+            None => {
+                let locals = self.symbols.iter().rev().filter(|symbol| {
+                    symbol.scope.as_ref().is_some_and(|scope| scope.contains(&offset))
+                });
+                let globals = self.symbols.iter().filter(|symbol| symbol.is_global);
+                locals.chain(globals).collect()
+            }
+        };
+
+        let mut names = HashSet::new();
+        let mut completions = symbols
+            .into_iter()
+            .filter(|symbol| names.insert(symbol.name.as_str()))
+            .map(Completion::from)
+            .collect::<Vec<_>>();
+
+        if !before.ends_with('.') {
+            let natives =
+                NATIVES.iter().filter(|(name, _)| names.insert(name)).map(|(name, params)| {
+                    Completion {
+                        label: name.to_string(),
+                        kind: SymbolKind::Function,
+                        detail: native_detail(name, params),
+                        params: Some(params.iter().map(|param| param.to_string()).collect()),
+                    }
+                });
+            completions.extend(natives);
+        }
+
+        completions
+    }
+
+    /// Members that can be accessed on an object, subclass members first
+    fn members_of(&self, object: &str, offset: usize) -> Vec<&Symbol> {
+        let class_at = || {
+            self.symbols
+                .iter()
+                .filter(|symbol| {
+                    symbol.kind == SymbolKind::Class && symbol.decl_span.contains(&offset)
+                })
+                .min_by_key(|symbol| symbol.decl_span.len())
+                .map(|symbol| symbol.name.as_str())
+        };
+
+        let (class, is_static) = match object {
+            "this" => (class_at(), false),
+            "super" => (class_at().and_then(|class| self.supers.get(class)?.as_deref()), false),
+            name => match self.resolve(name, offset) {
+                Some(symbol) if symbol.kind == SymbolKind::Class => (Some(name), true),
+                Some(symbol) => {
+                    let class = symbol.instance_of.as_deref();
+                    (class.filter(|class| self.supers.contains_key(*class)), false)
+                }
+                None => (None, false),
+            },
+        };
+
+        match class {
+            Some(class) => self
+                .class_chain(class)
+                .flat_map(|class| {
+                    self.symbols.iter().filter(move |symbol| symbol.class.as_deref() == Some(class))
+                })
+                .filter(|symbol| symbol.kind.is_static() == is_static)
+                .collect(),
+            None => self
+                .symbols
+                .iter()
+                .filter(|symbol| symbol.kind.is_member() && !symbol.kind.is_static())
+                .collect(),
+        }
+    }
+
+    /// The local or global a name refers to at an offset
+    pub(crate) fn resolve(&self, name: &str, offset: usize) -> Option<&Symbol> {
+        let local = self.symbols.iter().rev().find(|symbol| {
+            symbol.name == name
+                && symbol.scope.as_ref().is_some_and(|scope| scope.contains(&offset))
+        });
+        local.or_else(|| self.global(name))
+    }
+
+    /// A class and its superclasses, starting with the class itself
+    pub(crate) fn class_chain<'a>(&'a self, class: &'a str) -> impl Iterator<Item = &'a str> {
+        // Limited in case a broken program has a cycle
+        iter::successors(Some(class), |class| self.supers.get(*class)?.as_deref())
+            .take(self.supers.len() + 1)
+    }
+}
+
+/// Functions built into the [`VM`](crate::vm::VM), with their parameters
+pub(crate) const NATIVES: &[(&str, &[&str])] = &[
+    ("assert", &["value"]),
+    ("assert_eq", &["actual", "expected"]),
+    ("assert_ne", &["actual", "unexpected"]),
+    ("clock", &[]),
+    ("heap_snapshot", &[]),
+    ("instanceof", &["value", "class"]),
+    ("len", &["value"]),
+    ("print", &["value"]),
+    ("println", &["value"]),
+    ("typeof", &["value"]),
+];
+
+/// Short description of a native (e.g. `native fn len(value)`)
+pub(crate) fn native_detail(name: &str, params: &[&str]) -> String {
+    format!("native fn {name}({})", params.join(", "))
+}
+
+/// A name suggested by [`SymbolIndex::completions`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: SymbolKind,
+    pub detail: String,
+    /// Parameters of a function, method or class initializer
+    pub params: Option<Vec<String>>,
+}
+
+impl From<&Symbol> for Completion {
+    fn from(symbol: &Symbol) -> Self {
+        let is_callable = matches!(
+            symbol.kind,
+            SymbolKind::Class
+                | SymbolKind::Function
+                | SymbolKind::Method
+                | SymbolKind::StaticMethod
+        );
+        Completion {
+            label: symbol.name.clone(),
+            kind: symbol.kind,
+            detail: symbol.detail.clone(),
+            params: is_callable.then(|| symbol.params.clone()),
+        }
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// The class being called to create a value (e.g. `Point(1, 2)`), if the
+/// callee is a name. It may not actually be a class.
+fn called_class((value, _): &ExprS) -> Option<String> {
+    let Expr::Call(call) = value else {
+        return None;
+    };
+    match &call.callee.0 {
+        Expr::Identifier(callee) if callee.identifier.package.is_none() => {
+            Some(callee.identifier.name.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Local variables declared in a block or function
+struct Scope {
+    /// Offset where the scope ends
+    end: usize,
+    names: HashMap<String, usize>,
+}
+
+/// How the object of a field or method access was written
+enum MemberOf {
+    /// `this.name` inside a class
+    This(String),
+    /// `super.name` inside a class that extends another
+    Super(String),
+    /// `Name.name`, which is a static member if `Name` is a class
+    Name(String),
+    Unknown,
+}
+
+struct Indexer<'a> {
+    tokens: Vec<(usize, Token, usize)>,
+    package: Option<&'a str>,
+    index: SymbolIndex,
+    /// Local scopes, innermost last. Empty at the top level.
+    scopes: Vec<Scope>,
+    globals: HashMap<String, usize>,
+    /// Classes being indexed, innermost last
+    classes: Vec<String>,
+    /// Globals and members are resolved after every declaration is indexed
+    pending_globals: Vec<(Span, String)>,
+    pending_members: Vec<(Span, String, MemberOf)>,
+}
+
+impl Indexer<'_> {
+    fn program(&mut self, program: &Program) {
+        for (import, span) in &program.imports {
+            let span = self.tokens_in(span).filter(|(_, token, _)| *token != Token::Import).fold(
+                None,
+                |span: Option<Span>, (start, token, end)| match token {
+                    Token::Semicolon => span,
+                    _ => Some(span.map_or(*start, |span| span.start)..*end),
+                },
+            );
+            if let Some(span) = span {
+                self.reference(span, Target::Package(import.package.clone()));
+            }
+        }
+
+        for stmt in &program.stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn finish(mut self) -> SymbolIndex {
+        for (span, name) in std::mem::take(&mut self.pending_globals) {
+            if let Some(&idx) = self.globals.get(&name) {
+                self.reference(span, Target::Symbol(idx));
+            } else if NATIVES.iter().any(|(native, _)| *native == name) {
+                self.reference(span, Target::Native(name));
+            }
+        }
+
+        for (span, name, of) in std::mem::take(&mut self.pending_members) {
+            let idx = match of {
+                MemberOf::This(class) => self.member(&class, &name, false),
+                MemberOf::Super(class) => self.member(&class, &name, false),
+                MemberOf::Name(class) if self.index.supers.contains_key(&class) => {
+                    self.member(&class, &name, true)
+                }
+                MemberOf::Name(_) | MemberOf::Unknown => None,
+            };
+
+            // Without a known class, any member with the same name will do
+            let idx = idx.or_else(|| {
+                let members = || {
+                    self.index
+                        .symbols
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, symbol)| symbol.kind.is_member() && symbol.name == name)
+                };
+                members()
+                    .find(|(_, symbol)| !symbol.kind.is_static())
+                    .or_else(|| members().next())
+                    .map(|(idx, _)| idx)
+            });
+
+            if let Some(idx) = idx {
+                self.reference(span, Target::Symbol(idx));
+            }
+        }
+
+        self.index.references.sort_by_key(|reference| reference.span.start);
+        self.index
+    }
+
+    /// Find a member of a class or one of its superclasses
+    fn member(&self, class: &str, name: &str, is_static: bool) -> Option<usize> {
+        self.index.class_chain(class).find_map(|class| {
+            self.index.symbols.iter().position(|symbol| {
+                symbol.class.as_deref() == Some(class)
+                    && symbol.name == name
+                    && symbol.kind.is_static() == is_static
+            })
+        })
+    }
+
+    fn stmt(&mut self, (stmt, span): &StmtS) {
+        match stmt {
+            Stmt::Block(block) => self.scoped(span.end, |indexer| indexer.block(block)),
+            Stmt::Class(class) => self.class(class, span),
+            Stmt::Expr(stmt) => self.expr(&stmt.value),
+            Stmt::For(for_) => self.scoped(span.end, |indexer| {
+                if let Some(init) = &for_.init {
+                    indexer.stmt(init);
+                }
+                if let Some(cond) = &for_.cond {
+                    indexer.expr(cond);
+                }
+                if let Some(incr) = &for_.incr {
+                    indexer.expr(incr);
+                }
+                indexer.stmt(&for_.body);
+            }),
+            Stmt::Fn(fn_) => {
+                let detail = format!("fn {}({})", fn_.name, fn_.params.join(", "));
+                if let Some(symbol) =
+                    self.declare(&fn_.name, SymbolKind::Function, span, detail, None)
+                {
+                    symbol.params = fn_.params.clone();
+                }
+                self.function(fn_, span);
+            }
+            Stmt::If(if_) => {
+                self.expr(&if_.cond);
+                self.stmt(&if_.then);
+                if let Some(else_) = &if_.else_ {
+                    self.stmt(else_);
+                }
+            }
+            Stmt::Return(return_) => {
+                if let Some(value) = &return_.value {
+                    self.expr(value);
+                }
+            }
+            Stmt::Assign(assign) => {
+                if let Some(value) = &assign.value {
+                    self.expr(value);
+                }
+                let name = &assign.identifier.name;
+                let detail = format!("let {name}");
+                if let Some(symbol) = self.declare(name, SymbolKind::Variable, span, detail, None) {
+                    symbol.instance_of = assign.value.as_ref().and_then(called_class);
+                }
+            }
+            Stmt::While(while_) => {
+                self.expr(&while_.cond);
+                self.stmt(&while_.body);
+            }
+            Stmt::Throw(throw) => self.expr(&throw.value),
+            Stmt::Try(try_) => {
+                self.scoped(span.end, |indexer| indexer.block(&try_.body));
+                if let Some(catch) = &try_.catch {
+                    // The catch block doesn't have its own span, so the name
+                    // is the first identifier after `catch`
+                    let name_span = self
+                        .tokens_in(span)
+                        .skip_while(|(_, token, _)| *token != Token::Catch)
+                        .find(|(_, token, _)| *token == Token::Identifier(catch.name.clone()))
+                        .map(|(start, _, end)| *start..*end);
+
+                    self.scoped(span.end, |indexer| {
+                        if let Some(name_span) = name_span {
+                            let detail = format!("catch ({})", catch.name);
+                            indexer.push_symbol(Symbol {
+                                name: catch.name.clone(),
+                                kind: SymbolKind::Variable,
+                                span: name_span,
+                                decl_span: span.clone(),
+                                class: None,
+                                detail,
+                                is_global: false,
+                                scope: None,
+                                params: Vec::new(),
+                                instance_of: None,
+                            });
+                        }
+                        indexer.block(&catch.body);
+                    });
+                }
+                if let Some(finally) = &try_.finally {
+                    self.scoped(span.end, |indexer| indexer.block(finally));
+                }
+            }
+            Stmt::Error => {}
+        }
+    }
+
+    fn block(&mut self, block: &StmtBlock) {
+        for stmt in &block.stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn class(&mut self, class: &StmtClass, span: &Span) {
+        let super_ = match &class.super_ {
+            Some((Expr::Identifier(super_), _)) => Some(super_.identifier.name.clone()),
+            _ => None,
+        };
+        let detail = match &super_ {
+            Some(super_) => format!("class {} extends {super_}", class.name),
+            None => format!("class {}", class.name),
+        };
+        // Calling the class calls its initializer
+        let init = class.methods.iter().find(|(method, _)| method.name == "init");
+        if let Some(symbol) = self.declare(&class.name, SymbolKind::Class, span, detail, None) {
+            symbol.params = init.map(|(init, _)| init.params.clone()).unwrap_or_default();
+        }
+        if let Some(super_) = &class.super_ {
+            self.expr(super_);
+        }
+        self.index.supers.insert(class.name.clone(), super_);
+
+        self.classes.push(class.name.clone());
+        for (field, span) in &class.static_fields {
+            self.field(field, span, SymbolKind::StaticField, &class.name);
+        }
+        for (method, span) in &class.static_methods {
+            self.method(method, span, SymbolKind::StaticMethod, &class.name);
+        }
+        for (field, span) in &class.fields {
+            self.field(field, span, SymbolKind::Field, &class.name);
+        }
+        for (method, span) in &class.methods {
+            self.method(method, span, SymbolKind::Method, &class.name);
+        }
+        self.classes.pop();
+    }
+
+    fn field(&mut self, field: &StmtAssign, span: &Span, kind: SymbolKind, class: &str) {
+        if let Some(value) = &field.value {
+            self.expr(value);
+        }
+        let name = &field.identifier.name;
+        let detail = match kind {
+            SymbolKind::StaticField => format!("static let {class}.{name}"),
+            _ => format!("let {class}.{name}"),
+        };
+        self.declare(name, kind, span, detail, Some(class));
+    }
+
+    fn method(&mut self, method: &StmtFn, span: &Span, kind: SymbolKind, class: &str) {
+        let params = method.params.join(", ");
+        let detail = match kind {
+            SymbolKind::StaticMethod => format!("static fn {class}.{}({params})", method.name),
+            _ => format!("fn {class}.{}({params})", method.name),
+        };
+        if let Some(symbol) = self.declare(&method.name, kind, span, detail, Some(class)) {
+            symbol.params = method.params.clone();
+        }
+        self.function(method, span);
+    }
+
+    fn function(&mut self, fn_: &StmtFn, span: &Span) {
+        // Parameters are the identifiers between the first pair of parentheses
+        let params = self
+            .tokens_in(span)
+            .skip_while(|(_, token, _)| *token != Token::LtParen)
+            .take_while(|(_, token, _)| *token != Token::RtParen)
+            .filter(|(_, token, _)| matches!(token, Token::Identifier(_)))
+            .map(|(start, _, end)| *start..*end)
+            .collect::<Vec<_>>();
+
+        self.scoped(span.end, |indexer| {
+            for (param, param_span) in fn_.params.iter().zip(params) {
+                indexer.push_symbol(Symbol {
+                    name: param.clone(),
+                    kind: SymbolKind::Parameter,
+                    span: param_span,
+                    decl_span: span.clone(),
+                    class: None,
+                    detail: format!("(parameter) {param}"),
+                    is_global: false,
+                    scope: None,
+                    params: Vec::new(),
+                    instance_of: None,
+                });
+            }
+            indexer.block(&fn_.body);
+        });
+    }
+
+    fn expr(&mut self, (expr, span): &ExprS) {
+        match expr {
+            Expr::Assign(assign) => {
+                let name_span = self
+                    .last_identifier(&(span.start..assign.value.1.start), &assign.identifier.name);
+                if let Some(name_span) = name_span {
+                    self.identifier(
+                        name_span,
+                        &assign.identifier.name,
+                        assign.identifier.package.as_deref(),
+                    );
+                }
+                self.expr(&assign.value);
+            }
+            Expr::Call(call) => {
+                self.expr(&call.callee);
+                for arg in &call.args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Get(get) => {
+                self.expr(&get.object);
+                let of = self.member_of(&get.object);
+                if let Some(name_span) = self.last_identifier(span, &get.name) {
+                    self.pending_members.push((name_span, get.name.clone(), of));
+                }
+            }
+            Expr::GetIndex(get) => {
+                self.expr(&get.target);
+                self.expr(&get.index);
+            }
+            Expr::SetIndex(set) => {
+                self.expr(&set.target);
+                self.expr(&set.index);
+                self.expr(&set.value);
+            }
+            Expr::Infix(infix) => {
+                self.expr(&infix.lt);
+                self.expr(&infix.rt);
+            }
+            Expr::Literal(literal) => match literal {
+                crate::syntax::ast::ExprLiteral::List(items) => {
+                    for item in items {
+                        self.expr(item);
+                    }
+                }
+                crate::syntax::ast::ExprLiteral::Map(entries) => {
+                    for (key, value) in entries {
+                        self.expr(key);
+                        self.expr(value);
+                    }
+                }
+                _ => {}
+            },
+            Expr::Prefix(prefix) => self.expr(&prefix.rt),
+            Expr::Set(set) => {
+                self.expr(&set.object);
+                let of = self.member_of(&set.object);
+                let name_span = self.last_identifier(&(span.start..set.value.1.start), &set.name);
+                if let Some(name_span) = name_span {
+                    self.pending_members.push((name_span, set.name.clone(), of));
+                }
+                self.expr(&set.value);
+            }
+            Expr::Super(super_) => {
+                let class =
+                    self.classes.last().and_then(|class| self.index.supers.get(class)).cloned();
+                if let (Some(Some(class)), Some(name_span)) =
+                    (class, self.last_identifier(span, &super_.name))
+                {
+                    self.pending_members.push((
+                        name_span,
+                        super_.name.clone(),
+                        MemberOf::Super(class),
+                    ));
+                }
+            }
+            Expr::Identifier(identifier) => {
+                let identifier = &identifier.identifier;
+                self.identifier(span.clone(), &identifier.name, identifier.package.as_deref());
+            }
+        }
+    }
+
+    fn member_of(&self, (object, _): &ExprS) -> MemberOf {
+        let Expr::Identifier(identifier) = object else {
+            return MemberOf::Unknown;
+        };
+        match identifier.identifier.name.as_str() {
+            "this" => match self.classes.last() {
+                Some(class) => MemberOf::This(class.clone()),
+                None => MemberOf::Unknown,
+            },
+            name if identifier.identifier.package.is_none() => MemberOf::Name(name.to_string()),
+            _ => MemberOf::Unknown,
+        }
+    }
+
+    fn identifier(&mut self, span: Span, name: &str, package: Option<&str>) {
+        if matches!(name, "this" | "super" | "package") {
+            return;
+        }
+
+        match package {
+            Some(package) if Some(package) != self.package => {
+                let target =
+                    Target::PackageGlobal { package: package.to_string(), name: name.to_string() };
+                self.reference(span, target);
+            }
+            Some(_) => self.pending_globals.push((span, name.to_string())),
+            None => {
+                let local =
+                    self.scopes.iter().rev().find_map(|scope| scope.names.get(name)).copied();
+                match local {
+                    Some(idx) => self.reference(span, Target::Symbol(idx)),
+                    None => self.pending_globals.push((span, name.to_string())),
+                }
+            }
+        }
+    }
+
+    fn declare(
+        &mut self,
+        name: &str,
+        kind: SymbolKind,
+        span: &Span,
+        detail: String,
+        class: Option<&str>,
+    ) -> Option<&mut Symbol> {
+        let name_span = self.first_identifier(span, name)?;
+        Some(self.push_symbol(Symbol {
+            name: name.to_string(),
+            kind,
+            span: name_span,
+            decl_span: span.clone(),
+            class: class.map(str::to_string),
+            detail,
+            is_global: self.scopes.is_empty() && !kind.is_member(),
+            scope: None,
+            params: Vec::new(),
+            instance_of: None,
+        }))
+    }
+
+    fn push_symbol(&mut self, mut symbol: Symbol) -> &mut Symbol {
+        let idx = self.index.symbols.len();
+        if !symbol.kind.is_member() {
+            match self.scopes.last_mut() {
+                Some(scope) => {
+                    symbol.scope = Some(symbol.span.end..scope.end);
+                    scope.names.insert(symbol.name.clone(), idx);
+                }
+                None => {
+                    self.globals.entry(symbol.name.clone()).or_insert(idx);
+                }
+            }
+        }
+        self.index.symbols.push(symbol);
+        self.index.symbols.last_mut().expect("symbol was just pushed")
+    }
+
+    fn reference(&mut self, span: Span, target: Target) {
+        self.index.references.push(Reference { span, target });
+    }
+
+    fn scoped(&mut self, end: usize, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(Scope { end, names: HashMap::new() });
+        f(self);
+        self.scopes.pop();
+    }
+
+    /// Tokens that are inside a span
+    fn tokens_in<'t>(&'t self, span: &Span) -> impl Iterator<Item = &'t (usize, Token, usize)> {
+        let first = self.tokens.partition_point(|(start, _, _)| *start < span.start);
+        let end = span.end;
+        self.tokens[first..].iter().take_while(move |(_, _, token_end)| *token_end <= end)
+    }
+
+    fn first_identifier(&self, span: &Span, name: &str) -> Option<Span> {
+        let name = Token::Identifier(name.to_string());
+        self.tokens_in(span)
+            .find(|(_, token, _)| *token == name)
+            .map(|(start, _, end)| *start..*end)
+    }
+
+    fn last_identifier(&self, span: &Span, name: &str) -> Option<Span> {
+        let name = Token::Identifier(name.to_string());
+        self.tokens_in(span)
+            .filter(|(_, token, _)| *token == name)
+            .last()
+            .map(|(start, _, end)| *start..*end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// The source text of every reference to the symbol declared at `needle`
+    fn references(source: &str, needle: &str) -> Vec<(String, usize)> {
+        let index = SymbolIndex::new(source);
+        let (_, target) = index.target_at(source.find(needle).unwrap()).unwrap();
+        index
+            .references(&target)
+            .into_iter()
+            .map(|span| (source[span.clone()].to_string(), span.start))
+            .collect()
+    }
+
+    #[test]
+    fn globals_and_locals() {
+        let source =
+            "fn add(a, b) { let sum = a + b; return sum; }\nlet a = add(1, 2);\nprintln(a);";
+        let index = SymbolIndex::new(source);
+
+        let symbols = index
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind, symbol.is_global))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            symbols,
+            vec![
+                ("add", SymbolKind::Function, true),
+                ("a", SymbolKind::Parameter, false),
+                ("b", SymbolKind::Parameter, false),
+                ("sum", SymbolKind::Variable, false),
+                ("a", SymbolKind::Variable, true),
+            ]
+        );
+        assert_eq!(index.symbols[0].detail, "fn add(a, b)");
+
+        // The parameter `a` and the global `a` are different symbols
+        assert_eq!(references(source, "a, b"), vec![("a".to_string(), 25)]);
+        assert_eq!(references(source, "a = add"), vec![("a".to_string(), 73)]);
+        assert_eq!(references(source, "add"), vec![("add".to_string(), 54)]);
+
+        assert_eq!(
+            index.target_at(source.find("println").unwrap()),
+            Some((65..72, Target::Native("println".to_string())))
+        );
+    }
+
+    #[test]
+    fn globals_used_before_declaration() {
+        let source = "fn f() { return g(); }\nfn g() { return 1; }";
+        assert_eq!(references(source, "g() {"), vec![("g".to_string(), 16)]);
+    }
+
+    #[test]
+    fn members() {
+        let source =
+            "class A { static fn make() { return A(); } let x; fn get() { return this.x; } }
+class B extends A { fn get() { return super.get(); } }
+let b = B();
+b.x = A.make().get();";
+        let index = SymbolIndex::new(source);
+
+        let (_, target) = index.target_at(source.find("x;").unwrap()).unwrap();
+        let spans = index.references(&target);
+        assert_eq!(spans.len(), 2);
+        assert!(spans.iter().all(|span| &source[span.clone()] == "x"));
+
+        // `super.get` refers to A's method, not B's
+        let (_, target) = index.target_at(source.find("get() { return this").unwrap()).unwrap();
+        let refs = index.references(&target);
+        assert_eq!(
+            refs.iter().map(|span| span.start).collect::<Vec<_>>(),
+            vec![source.find("get(); }").unwrap(), source.rfind("get").unwrap()]
+        );
+
+        let (_, target) = index.target_at(source.find("make").unwrap()).unwrap();
+        assert_eq!(
+            index.references(&target),
+            vec![source.rfind("make").unwrap()..source.rfind("make").unwrap() + 4]
+        );
+        assert_eq!(index.symbols[1].detail, "static fn A.make()");
+    }
+
+    #[test]
+    fn packages() {
+        let source =
+            "package app;\nimport some::pkg;\nlet x = 1;\nprintln(some::pkg:value + app:x);";
+        let index = SymbolIndex::new(source);
+
+        assert_eq!(
+            index.target_at(source.find("some::pkg;").unwrap() + 6),
+            Some((20..29, Target::Package("some::pkg".to_string())))
+        );
+        assert_eq!(
+            index.target_at(source.find(":value").unwrap()),
+            Some((
+                source.find("some::pkg:value").unwrap()..source.find(" + ").unwrap(),
+                Target::PackageGlobal {
+                    package: "some::pkg".to_string(),
+                    name: "value".to_string()
+                }
+            ))
+        );
+        assert_eq!(references(source, "x = 1").len(), 1);
+    }
+
+    fn completions(source: &str) -> Vec<String> {
+        let offset = source.find('|').unwrap();
+        let source = source.replace('|', "");
+        let index = SymbolIndex::new(&source);
+        index.completions(&source, offset).into_iter().map(|completion| completion.label).collect()
+    }
+
+    #[test]
+    fn scope_completions() {
+        let source = "let g = 1;\nfn f(a) {\n  let b = 2;\n  pr|\n}\nlet h = 2;";
+        assert_eq!(
+            completions(source),
+            vec![
+                "b",
+                "a",
+                "g",
+                "f",
+                "h",
+                "assert",
+                "assert_eq",
+                "assert_ne",
+                "clock",
+                "heap_snapshot",
+                "instanceof",
+                "len",
+                "print",
+                "println",
+                "typeof"
+            ]
+        );
+
+        let source = "fn f(a) {\n  let b = 2;\n}\n|";
+        assert_eq!(completions(source)[..2], ["f", "assert"]);
+    }
+
+    #[test]
+    fn member_completions() {
+        let source = "class A { let x; fn get() { return this.x; } }
+class B extends A { static fn make() { return B(); } let y; fn init(y) { this. } fn get() {} }
+let b = B(1);
+";
+        let this = source.replace("this. ", "this.| ");
+        assert_eq!(completions(&this), vec!["y", "init", "get", "x"]);
+        assert_eq!(completions(&format!("{source}B.|")), vec!["make"]);
+        assert_eq!(completions(&format!("{source}b.|")), vec!["y", "init", "get", "x"]);
+        assert_eq!(completions(&format!("{source}unknown().g|")), vec!["x", "get", "y", "init"]);
+
+        let index = SymbolIndex::new(source);
+        let completion = index.completions(source, source.find("\nlet b").unwrap());
+        let class = completion.iter().find(|completion| completion.label == "B").unwrap();
+        assert_eq!(class.params, Some(vec!["y".to_string()]));
+    }
+
+    #[test]
+    fn partial_programs() {
+        let source = "fn add(a, b) { return a + b; }\nlet x = ;\nadd(1, 2);";
+        let index = SymbolIndex::new(source);
+        assert_eq!(index.global("add").map(|symbol| symbol.span.clone()), Some(3..6));
+        assert_eq!(references(source, "add").len(), 1);
+    }
+}
@@ -8,9 +8,17 @@ pub type ExprS = Spanned<Expr>;
 #[derive(Debug, Default)]
 pub struct Program {
     pub package: Option<String>,
+    pub imports: Vec<Spanned<StmtImport>>,
     pub stmts: Vec<StmtS>,
 }
 
+/// Statement that loads a package (e.g. `import some::path;`) so its globals
+/// can be accessed with qualified identifiers (e.g. `some::path:name`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct StmtImport {
+    pub package: String,
+}
+
 #[derive(Clone, PartialEq)]
 pub enum Stmt {
     Block(StmtBlock),
@@ -22,6 +30,8 @@ pub enum Stmt {
     Return(StmtReturn),
     Assign(StmtAssign),
     While(Box<StmtWhile>),
+    Throw(StmtThrow),
+    Try(Box<StmtTry>),
     Error,
 }
 
@@ -37,6 +47,8 @@ impl Debug for Stmt {
             Self::Return(arg0) => f.write_fmt(format_args!("{:#?}", arg0)),
             Self::Assign(arg0) => f.write_fmt(format_args!("{:#?}", arg0)),
             Self::While(arg0) => f.write_fmt(format_args!("{:#?}", arg0)),
+            Self::Throw(arg0) => f.write_fmt(format_args!("{:#?}", arg0)),
+            Self::Try(arg0) => f.write_fmt(format_args!("{:#?}", arg0)),
             Self::Error => write!(f, "Error"),
         }
     }
@@ -103,6 +115,30 @@ pub struct StmtWhile {
     pub body: StmtS,
 }
 
+/// Statement that raises `value` as an error (e.g. `throw Error("message");`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct StmtThrow {
+    pub value: ExprS,
+}
+
+/// Statement that runs `body` and handles errors raised by it
+///
+/// At least one of `catch` or `finally` is present.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StmtTry {
+    pub body: StmtBlock,
+    pub catch: Option<StmtCatch>,
+    pub finally: Option<StmtBlock>,
+}
+
+/// The `catch (name) { ... }` block of a [`StmtTry`]. The caught error is
+/// bound to `name`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StmtCatch {
+    pub name: String,
+    pub body: StmtBlock,
+}
+
 #[derive(Clone, PartialEq)]
 pub enum Expr {
     Assign(Box<ExprAssign>),
@@ -145,13 +181,13 @@ pub struct ExprAssign {
 #[derive(Clone, Debug, PartialEq)]
 pub struct ExprGetIndex {
     pub target: ExprS,
-    pub index: f64,
+    pub index: ExprS,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct ExprSetIndex {
     pub target: ExprS,
-    pub index: f64,
+    pub index: ExprS,
     pub value: ExprS,
 }
 
@@ -174,6 +210,8 @@ pub enum ExprLiteral {
     Number(f64),
     String(String),
     List(Vec<ExprS>),
+    /// Key value pairs in the order they were written
+    Map(Vec<(ExprS, ExprS)>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
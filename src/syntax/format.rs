@@ -0,0 +1,754 @@
+use crate::error::ErrorS;
+use crate::syntax::ast::{
+    Expr, ExprLiteral, ExprS, Identifier, OpInfix, OpPrefix, Program, Stmt, StmtAssign, StmtFn,
+    StmtS,
+};
+use crate::syntax::lexer::{self, Lexer, Token};
+use crate::types::Span;
+
+const INDENT: &str = "  ";
+
+/// Format a program in the canonical style, keeping its `//` comments.
+/// Programs that don't parse are returned as errors instead.
+pub fn format(source: &str) -> Result<String, Vec<ErrorS>> {
+    let program = crate::syntax::parse(source, 0)?;
+    let mut formatter = Formatter::new(source);
+    formatter.program(&program);
+    Ok(formatter.output)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    tokens: Vec<(Token, Span)>,
+    comments: Vec<Span>,
+    /// Index of the next comment that hasn't been written
+    comment: usize,
+    output: String,
+    indent: usize,
+    /// End of the last statement or comment that was written
+    last: usize,
+    /// Set at the start of a file or block, where blank lines are dropped
+    at_start: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str) -> Self {
+        let tokens = Lexer::new(source)
+            .filter_map(Result::ok)
+            .map(|(start, token, end)| (token, start..end))
+            .collect();
+        Self {
+            source,
+            tokens,
+            comments: lexer::comments(source),
+            comment: 0,
+            output: String::new(),
+            indent: 0,
+            last: 0,
+            at_start: true,
+        }
+    }
+
+    fn program(&mut self, program: &Program) {
+        if let Some(package) = &program.package {
+            let start = self.position(0, |token| *token == Token::Package);
+            let end = self.position(start, |token| *token == Token::Semicolon) + 1;
+            self.before(start);
+            self.output += &format!("package {package};");
+            self.after(end);
+        }
+        for (import, span) in &program.imports {
+            self.before(span.start);
+            self.output += &format!("import {};", import.package);
+            self.after(span.end);
+        }
+        for stmt in &program.stmts {
+            self.line(stmt);
+        }
+        self.comments_before(self.source.len());
+        if !self.output.is_empty() {
+            self.output += "\n";
+        }
+    }
+
+    /// Write a statement on its own line, along with the comments before it
+    fn line(&mut self, (stmt, span): &StmtS) {
+        self.before(span.start);
+        self.stmt(stmt, span);
+        self.after(span.end);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, span: &Span) {
+        match stmt {
+            Stmt::Block(block) => self.block(&block.stmts, span.end - 1),
+            Stmt::Class(class) => {
+                self.output += &format!("class {}", class.name);
+                if let Some(super_) = &class.super_ {
+                    self.output += " extends ";
+                    self.expr(super_, 0);
+                }
+                self.output += " ";
+
+                let members = class
+                    .static_fields
+                    .iter()
+                    .map(|(field, span)| (Member::Field(field), span, true))
+                    .chain(
+                        class
+                            .static_methods
+                            .iter()
+                            .map(|(method, span)| (Member::Method(method), span, true)),
+                    )
+                    .chain(
+                        class
+                            .fields
+                            .iter()
+                            .map(|(field, span)| (Member::Field(field), span, false)),
+                    )
+                    .chain(
+                        class
+                            .methods
+                            .iter()
+                            .map(|(method, span)| (Member::Method(method), span, false)),
+                    )
+                    .collect::<Vec<_>>();
+
+                let end = span.end - 1;
+                self.output += "{";
+                if members.is_empty() && !self.has_comment_before(end) {
+                    self.output += "}";
+                    return;
+                }
+                self.open();
+                for (member, span, is_static) in members {
+                    self.before(span.start);
+                    if is_static {
+                        self.output += "static ";
+                    }
+                    match member {
+                        Member::Field(field) => self.assign(field),
+                        Member::Method(method) => self.function(method, span),
+                    }
+                    self.after(span.end);
+                }
+                self.close(end);
+            }
+            Stmt::Expr(expr) => {
+                self.expr_stmt(&expr.value);
+                self.output += ";";
+            }
+            Stmt::For(for_) => {
+                self.output += "for (";
+                match &for_.init {
+                    Some((Stmt::Assign(assign), _)) => self.assign(assign),
+                    Some((Stmt::Expr(expr), _)) => {
+                        self.expr_stmt(&expr.value);
+                        self.output += ";";
+                    }
+                    _ => self.output += ";",
+                }
+                if let Some(cond) = &for_.cond {
+                    self.output += " ";
+                    self.expr(cond, 0);
+                }
+                self.output += ";";
+                if let Some(incr) = &for_.incr {
+                    self.output += " ";
+                    self.expr(incr, 0);
+                }
+                self.output += ")";
+                self.body(&for_.body);
+            }
+            Stmt::Fn(function) => self.function(function, span),
+            Stmt::If(if_) => {
+                self.output += "if (";
+                self.expr(&if_.cond, 0);
+                self.output += ")";
+                self.body(&if_.then);
+                if let Some((else_, span)) = &if_.else_ {
+                    // `else` follows a block's closing brace, or starts a
+                    // new line after a single statement
+                    if matches!(if_.then, (Stmt::Block(_), _)) {
+                        self.output += " ";
+                    } else {
+                        self.newline();
+                    }
+                    self.output += "else ";
+                    self.stmt(else_, span);
+                }
+            }
+            Stmt::Return(return_) => {
+                self.output += "return";
+                if let Some(value) = &return_.value {
+                    self.output += " ";
+                    self.expr(value, 0);
+                }
+                self.output += ";";
+            }
+            Stmt::Assign(assign) => self.assign(assign),
+            Stmt::While(while_) => {
+                self.output += "while (";
+                self.expr(&while_.cond, 0);
+                self.output += ")";
+                self.body(&while_.body);
+            }
+            Stmt::Throw(throw) => {
+                self.output += "throw ";
+                self.expr(&throw.value, 0);
+                self.output += ";";
+            }
+            Stmt::Try(try_) => {
+                self.output += "try ";
+                let open = self.position(span.start, |token| *token == Token::LtBrace);
+                let mut end = self.block_end(open);
+                self.block(&try_.body.stmts, end);
+                if let Some(catch) = &try_.catch {
+                    let open = self.position(end + 1, |token| *token == Token::LtBrace);
+                    end = self.block_end(open);
+                    self.output += &format!(" catch ({}) ", catch.name);
+                    self.block(&catch.body.stmts, end);
+                }
+                if let Some(finally) = &try_.finally {
+                    let open = self.position(end + 1, |token| *token == Token::LtBrace);
+                    end = self.block_end(open);
+                    self.output += " finally ";
+                    self.block(&finally.stmts, end);
+                }
+            }
+            Stmt::Error => self.output += &self.source[span.clone()],
+        }
+    }
+
+    fn assign(&mut self, assign: &StmtAssign) {
+        self.output += &format!("let {}", assign.identifier.name);
+        if let Some(value) = &assign.value {
+            self.output += " = ";
+            self.expr(value, 0);
+        }
+        self.output += ";";
+    }
+
+    fn function(&mut self, function: &StmtFn, span: &Span) {
+        self.output += &format!("fn {}({})", function.name, function.params.join(", "));
+
+        // Functions written as `fn name() => value;` keep that form
+        let params_end = self.position(span.start, |token| *token == Token::RtParen);
+        if self.tokens.iter().find(|(_, span)| span.start > params_end).map(|(token, _)| token)
+            == Some(&Token::FatArrow)
+        {
+            if let [(Stmt::Return(return_), _)] = function.body.stmts.as_slice() {
+                if let Some(value) = &return_.value {
+                    self.output += " => ";
+                    self.expr_stmt(value);
+                    self.output += ";";
+                    return;
+                }
+            }
+        }
+
+        self.output += " ";
+        let open = self.position(params_end, |token| *token == Token::LtBrace);
+        let end = self.block_end(open);
+        self.block(&function.body.stmts, end);
+    }
+
+    /// Write the body of an `if`, `while` or `for` statement
+    fn body(&mut self, (stmt, span): &StmtS) {
+        self.output += " ";
+        self.stmt(stmt, span);
+    }
+
+    /// Write a block whose closing brace is at `end`
+    fn block(&mut self, stmts: &[StmtS], end: usize) {
+        self.output += "{";
+        if stmts.is_empty() && !self.has_comment_before(end) {
+            self.output += "}";
+            return;
+        }
+        self.open();
+        for stmt in stmts {
+            self.line(stmt);
+        }
+        self.close(end);
+    }
+
+    fn open(&mut self) {
+        self.indent += 1;
+        self.at_start = true;
+    }
+
+    fn close(&mut self, end: usize) {
+        self.comments_before(end);
+        self.indent -= 1;
+        self.newline();
+        self.output += "}";
+        self.last = end + 1;
+    }
+
+    /// Write the comments that come before `start`, then start a new line
+    /// for whatever is at `start`
+    fn before(&mut self, start: usize) {
+        self.comments_before(start);
+        self.line_start(start);
+    }
+
+    /// Write the comments that come before `end`, each on its own line
+    fn comments_before(&mut self, end: usize) {
+        while let Some(comment) = self.comments.get(self.comment).cloned() {
+            if comment.start >= end {
+                break;
+            }
+            self.comment += 1;
+            self.line_start(comment.start);
+            self.output += self.source[comment.clone()].trim_end();
+            self.last = self.last.max(comment.end);
+        }
+    }
+
+    /// Start a new line for something at `start`, keeping a single blank
+    /// line if the source had any
+    fn line_start(&mut self, start: usize) {
+        let is_blank = !self.at_start
+            && start > self.last
+            && self.source[self.last..start].matches('\n').count() > 1;
+        if is_blank {
+            self.output += "\n";
+        }
+        self.newline();
+        self.at_start = false;
+    }
+
+    fn newline(&mut self) {
+        if !self.output.is_empty() {
+            self.output += "\n";
+        }
+        self.output += &INDENT.repeat(self.indent);
+    }
+
+    /// Write a comment that follows the end of a statement on the same line
+    fn after(&mut self, end: usize) {
+        self.last = end;
+        let Some(comment) = self.comments.get(self.comment).cloned() else {
+            return;
+        };
+        let is_same_line = comment.start >= end
+            && self.source[end..comment.start].chars().all(|c| c == ',' || c == ' ' || c == '\t');
+        if is_same_line {
+            self.comment += 1;
+            self.output += " ";
+            self.output += self.source[comment.clone()].trim_end();
+            self.last = comment.end;
+        }
+    }
+
+    fn has_comment_before(&self, end: usize) -> bool {
+        self.comments.get(self.comment).is_some_and(|comment| comment.start < end)
+    }
+
+    fn has_comment_in(&self, span: Span) -> bool {
+        self.comments[self.comment..]
+            .iter()
+            .take_while(|comment| comment.start < span.end)
+            .any(|comment| comment.start >= span.start)
+    }
+
+    /// Start of the first token at or after `start` that matches
+    fn position(&self, start: usize, f: impl Fn(&Token) -> bool) -> usize {
+        self.tokens
+            .iter()
+            .find(|(token, span)| span.start >= start && f(token))
+            .map(|(_, span)| span.start)
+            .unwrap_or(self.source.len())
+    }
+
+    /// Start of the brace that closes the one at `open`
+    fn block_end(&self, open: usize) -> usize {
+        let mut depth = 0;
+        for (token, span) in self.tokens.iter().filter(|(_, span)| span.start >= open) {
+            match token {
+                Token::LtBrace => depth += 1,
+                Token::RtBrace if depth == 1 => return span.start,
+                Token::RtBrace => depth -= 1,
+                _ => {}
+            }
+        }
+        self.source.len()
+    }
+
+    /// Write an expression in statement position, which can't start with a
+    /// map literal
+    fn expr_stmt(&mut self, expr: &ExprS) {
+        let start = self.output.len();
+        self.expr(expr, 0);
+        if self.output[start..].starts_with('{') {
+            self.output.insert(start, '(');
+            self.output += ")";
+        }
+    }
+
+    /// Write an expression, wrapping it in parentheses if it binds less
+    /// tightly than `min`
+    fn expr(&mut self, (expr, span): &ExprS, min: u8) {
+        let is_grouped = precedence(expr) < min;
+        if is_grouped {
+            self.output += "(";
+        }
+
+        match expr {
+            Expr::Assign(assign) => {
+                self.output += &identifier(&assign.identifier);
+                self.output += " = ";
+                self.expr(&assign.value, 0);
+            }
+            Expr::Call(call) => {
+                self.expr(&call.callee, PREC_CALL);
+                self.output += "(";
+                self.items(&call.args, call.callee.1.end..span.end - 1, |formatter, arg| {
+                    formatter.expr(arg, 0)
+                });
+                self.output += ")";
+            }
+            Expr::Get(get) => {
+                self.expr(&get.object, PREC_CALL);
+                self.output += ".";
+                self.output += &get.name;
+            }
+            Expr::GetIndex(get) => {
+                self.expr(&get.target, PREC_CALL);
+                self.output += "[";
+                self.expr(&get.index, 0);
+                self.output += "]";
+            }
+            Expr::SetIndex(set) => {
+                self.expr(&set.target, PREC_CALL);
+                self.output += "[";
+                self.expr(&set.index, 0);
+                self.output += "] = ";
+                self.expr(&set.value, 0);
+            }
+            Expr::Infix(infix) => {
+                let precedence = precedence(expr);
+                self.expr(&infix.lt, precedence);
+                self.output += &format!(" {} ", infix.op);
+                self.expr(&infix.rt, precedence + 1);
+            }
+            Expr::Literal(literal) => self.literal(literal, span),
+            Expr::Prefix(prefix) => {
+                self.output += &prefix.op.to_string();
+                // `--x` would read like a decrement
+                if prefix.op == OpPrefix::Negate
+                    && matches!(prefix.rt.0, Expr::Prefix(ref rt) if rt.op == OpPrefix::Negate)
+                {
+                    self.output += " ";
+                }
+                self.expr(&prefix.rt, PREC_PREFIX);
+            }
+            Expr::Set(set) => {
+                self.expr(&set.object, PREC_CALL);
+                self.output += &format!(".{} = ", set.name);
+                self.expr(&set.value, 0);
+            }
+            Expr::Super(super_) => {
+                if is_identifier(&super_.name) {
+                    self.output += &format!("super.{}", super_.name);
+                } else if super_.name.contains(':') {
+                    self.output += &format!("super[{}]", super_.name);
+                } else {
+                    self.output += &format!("super[\"{}\"]", super_.name);
+                }
+            }
+            Expr::Identifier(expr) => self.output += &identifier(&expr.identifier),
+        }
+
+        if is_grouped {
+            self.output += ")";
+        }
+    }
+
+    fn literal(&mut self, literal: &ExprLiteral, span: &Span) {
+        match literal {
+            ExprLiteral::Bool(value) => self.output += &value.to_string(),
+            ExprLiteral::Nil => self.output += "nil",
+            ExprLiteral::Number(value) => {
+                // Keep numbers as they were written (e.g. `1.50`)
+                let written = &self.source[span.clone()];
+                if written.parse() == Ok(*value) {
+                    self.output += written;
+                } else {
+                    self.output += &value.to_string();
+                }
+            }
+            ExprLiteral::String(value) => self.output += &format!("\"{value}\""),
+            ExprLiteral::List(values) => {
+                self.output += "[";
+                self.items(values, span.start..span.end - 1, |formatter, value| {
+                    formatter.expr(value, 0)
+                });
+                self.output += "]";
+            }
+            ExprLiteral::Map(entries) => {
+                self.output += "{";
+                self.items(entries, span.start..span.end - 1, |formatter, (key, value)| {
+                    match key {
+                        (Expr::Literal(literal @ ExprLiteral::String(_)), span)
+                        | (Expr::Literal(literal @ ExprLiteral::Number(_)), span) => {
+                            formatter.literal(literal, span)
+                        }
+                        key => {
+                            formatter.output += "(";
+                            formatter.expr(key, 0);
+                            formatter.output += ")";
+                        }
+                    }
+                    formatter.output += ": ";
+                    formatter.expr(value, 0);
+                });
+                self.output += "}";
+            }
+        }
+    }
+
+    /// Write comma separated items, where `span` runs from the opening
+    /// bracket to the closing one. They go on one line, unless there are
+    /// comments between them that need to be kept.
+    fn items<T: Item>(&mut self, items: &[T], span: Span, write: impl Fn(&mut Self, &T)) {
+        let end = span.end;
+        if !self.has_comment_in(span) {
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    self.output += ", ";
+                }
+                write(self, item);
+            }
+            return;
+        }
+
+        self.open();
+        for (idx, item) in items.iter().enumerate() {
+            let span = item.span();
+            self.before(span.start);
+            write(self, item);
+            if idx + 1 < items.len() {
+                self.output += ",";
+            }
+            let next = items.get(idx + 1).map_or(end, |item| item.span().start);
+            if self.has_comment_before(next) {
+                self.after(span.end);
+            }
+        }
+        self.comments_before(end);
+        self.indent -= 1;
+        self.newline();
+    }
+}
+
+/// Something written by [`Formatter::items`]
+trait Item {
+    fn span(&self) -> Span;
+}
+
+impl Item for ExprS {
+    fn span(&self) -> Span {
+        self.1.clone()
+    }
+}
+
+impl Item for (ExprS, ExprS) {
+    fn span(&self) -> Span {
+        self.0 .1.start..self.1 .1.end
+    }
+}
+
+enum Member<'a> {
+    Field(&'a StmtAssign),
+    Method(&'a StmtFn),
+}
+
+const PREC_ASSIGN: u8 = 1;
+const PREC_PREFIX: u8 = 8;
+const PREC_CALL: u8 = 9;
+const PREC_PRIMARY: u8 = 10;
+
+/// How tightly an expression binds, which decides where it needs parentheses
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Assign(_) | Expr::Set(_) | Expr::SetIndex(_) => PREC_ASSIGN,
+        Expr::Infix(infix) => match infix.op {
+            OpInfix::LogicOr => 2,
+            OpInfix::LogicAnd => 3,
+            OpInfix::Equal | OpInfix::NotEqual => 4,
+            OpInfix::Less | OpInfix::LessEqual | OpInfix::Greater | OpInfix::GreaterEqual => 5,
+            OpInfix::Add | OpInfix::Subtract => 6,
+            OpInfix::Multiply | OpInfix::Divide | OpInfix::Modulus => 7,
+        },
+        Expr::Prefix(_) => PREC_PREFIX,
+        Expr::Call(_) | Expr::Get(_) | Expr::GetIndex(_) | Expr::Super(_) => PREC_CALL,
+        Expr::Literal(_) | Expr::Identifier(_) => PREC_PRIMARY,
+    }
+}
+
+fn identifier(identifier: &Identifier) -> String {
+    match &identifier.package {
+        Some(package) => format!("{package}:{}", identifier.name),
+        None => identifier.name.clone(),
+    }
+}
+
+/// Whether `name` lexes as a single identifier (and not a keyword)
+pub(crate) fn is_identifier(name: &str) -> bool {
+    let mut tokens = Lexer::new(name);
+    matches!(tokens.next(), Some(Ok((0, Token::Identifier(_), end))) if end == name.len())
+        && tokens.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn format_statements() {
+        let source = "\
+let x=1;   let y;
+fn add(a,b){return a+b;}
+fn double(x)=>x*2;
+class  A extends B {static let count=0; static fn make()=>A(); let name; fn init(name){this.name=name;}}
+class Empty {}
+if(x>1)println(x);else{println(y);}
+while (x < 10) x = x + 1;
+if (false) nil; else { println(1); } // after
+for(let i=0;i<3;i=i+1){}
+for(;;){ }
+try{throw 1;}catch(e){println(e);}finally{}
+";
+        let expected = "\
+let x = 1;
+let y;
+fn add(a, b) {
+  return a + b;
+}
+fn double(x) => x * 2;
+class A extends B {
+  static let count = 0;
+  static fn make() => A();
+  let name;
+  fn init(name) {
+    this.name = name;
+  }
+}
+class Empty {}
+if (x > 1) println(x);
+else {
+  println(y);
+}
+while (x < 10) x = x + 1;
+if (false) nil;
+else {
+  println(1);
+} // after
+for (let i = 0; i < 3; i = i + 1) {}
+for (;;) {}
+try {
+  throw 1;
+} catch (e) {
+  println(e);
+} finally {}
+";
+        assert_eq!(expected, format(source).unwrap());
+    }
+
+    #[test]
+    fn format_expressions() {
+        let source = "\
+println((1+2)*3 - (4-5) - 6);
+println(!(a and b) or -(-c));
+({\"a\": 1, 2: [1,2.50], (k): nil}).x = super[\"class\"];
+m[\"k\"] = pkg:name(super[\"a\"], super[pkg:b]);
+";
+        let expected = "\
+println((1 + 2) * 3 - (4 - 5) - 6);
+println(!(a and b) or - -c);
+({\"a\": 1, 2: [1, 2.50], (k): nil}.x = super[\"class\"]);
+m[\"k\"] = pkg:name(super.a, super[pkg:b]);
+";
+        assert_eq!(expected, format(source).unwrap());
+    }
+
+    #[test]
+    fn format_comments_in_items() {
+        let source = "\
+foo(a, // first
+  [1, 2], {\"k\": nil, // key
+  \"j\": 1}
+  // last
+); // after
+";
+        let expected = "\
+foo(
+  a, // first
+  [1, 2],
+  {
+    \"k\": nil, // key
+    \"j\": 1
+  }
+  // last
+); // after
+";
+        assert_eq!(expected, format(source).unwrap());
+    }
+
+    #[test]
+    fn format_comments() {
+        let source = "\
+// Header
+
+package   demo; // trailing
+import other;
+// Leading
+let x = 1;  // after x
+
+
+let y = 2;
+fn f() {
+  // only a comment
+}
+{
+
+  // first
+
+  let z = 3;
+  // last
+}
+// end
+";
+        let expected = "\
+// Header
+
+package demo; // trailing
+import other;
+// Leading
+let x = 1; // after x
+
+let y = 2;
+fn f() {
+  // only a comment
+}
+{
+  // first
+
+  let z = 3;
+  // last
+}
+// end
+";
+        let formatted = format(source).unwrap();
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format(&formatted).unwrap());
+    }
+
+    #[test]
+    fn format_parse_error() {
+        assert!(format("let x = ;").is_err());
+    }
+}
@@ -3,6 +3,7 @@ use std::num::ParseFloatError;
 use logos::Logos;
 
 use crate::error::{Error, ErrorS, SyntaxError};
+use crate::types::Span;
 
 #[derive(Debug)]
 pub struct Lexer<'a> {
@@ -160,6 +161,16 @@ pub enum Token {
     Package,
     #[token("static")]
     Static,
+    #[token("import")]
+    Import,
+    #[token("throw")]
+    Throw,
+    #[token("try")]
+    Try,
+    #[token("catch")]
+    Catch,
+    #[token("finally")]
+    Finally,
 
     #[regex(r"//.*", logos::skip)]
     #[regex(r"[ \r\n\t\f]+", logos::skip)]
@@ -167,6 +178,27 @@ pub enum Token {
     Error,
 }
 
+/// Spans of the `//` comments in a program, which [`Lexer`] skips
+pub fn comments(source: &str) -> Vec<Span> {
+    Trivia::lexer(source)
+        .spanned()
+        .filter(|(token, _)| *token == Trivia::Comment)
+        .map(|(_, span)| span)
+        .collect()
+}
+
+/// Tokens that the parser doesn't need
+#[derive(Debug, Logos, PartialEq)]
+enum Trivia {
+    #[regex(r"//.*")]
+    Comment,
+    // Strings are matched so "//" inside them isn't a comment
+    #[regex(r#""[^"]*""#, logos::skip)]
+    #[regex(r#"[^/"]+"#, logos::skip)]
+    #[error]
+    Other,
+}
+
 fn lex_number(lexer: &mut logos::Lexer<Token>) -> Result<f64, ParseFloatError> {
     let slice = lexer.slice();
     slice.parse::<f64>()
@@ -207,4 +239,11 @@ mod tests {
         let got = Lexer::new("\"\nfoo").collect::<Vec<_>>();
         assert_eq!(exp, got);
     }
+
+    #[test]
+    fn lex_comments() {
+        let source = "// first\nprintln(\"// not a comment\"); // second\n10 / 2;";
+        let got = comments(source).into_iter().map(|span| &source[span]).collect::<Vec<_>>();
+        assert_eq!(vec!["// first", "// second"], got);
+    }
 }
@@ -1,4 +1,5 @@
 pub mod ast;
+pub mod format;
 pub mod lexer;
 pub mod parser;
 
@@ -20,6 +21,17 @@ pub fn is_complete(source: &str) -> bool {
 }
 
 pub fn parse(source: &str, offset: usize) -> Result<Program, Vec<ErrorS>> {
+    let (program, errors) = parse_recover(source, offset);
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parse as much of the program as possible. Statements that couldn't be
+/// parsed are replaced with [`Stmt::Error`](crate::syntax::ast::Stmt::Error).
+pub fn parse_recover(source: &str, offset: usize) -> (Program, Vec<ErrorS>) {
     let lexer = Lexer::new(source).map(|token| match token {
         Ok((l, token, r)) => Ok((l + offset, token, r + offset)),
         Err((e, span)) => Err((e, span.start + offset..span.end + offset)),
@@ -57,5 +69,5 @@ pub fn parse(source: &str, offset: usize) -> Result<Program, Vec<ErrorS>> {
         ParseError::User { error } => error,
     }));
 
-    if errors.is_empty() { Ok(program) } else { Err(errors) }
+    (program, errors)
 }
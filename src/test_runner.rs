@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::error::{self, ErrorS};
+use crate::syntax::ast::Stmt;
+use crate::types::Span;
+use crate::vm::debug::SourceName;
+use crate::vm::VM;
+
+/// Functions declared at the top level with this prefix are tests
+pub const TEST_PREFIX: &str = "test_";
+
+/// The tests in a file and how they went
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub source: String,
+    /// Errors that stopped the file's tests from being found (e.g. syntax
+    /// errors)
+    pub errors: Vec<ErrorS>,
+    pub tests: Vec<TestReport>,
+}
+
+impl FileReport {
+    pub fn passed(&self) -> usize {
+        self.tests.iter().filter(|test| test.failure.is_none()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.tests.len() - self.passed()
+    }
+}
+
+#[derive(Debug)]
+pub struct TestReport {
+    pub name: String,
+    /// The span of the test function's declaration in the file
+    pub span: Span,
+    pub duration: Duration,
+    /// What the test printed
+    pub output: String,
+    pub failure: Option<Failure>,
+}
+
+/// The error that failed a test
+#[derive(Debug)]
+pub struct Failure {
+    pub error: ErrorS,
+    /// The source that the error's span is in. This is the test's file
+    /// unless the error came from the core library or a package.
+    pub source: String,
+    /// Whether `source` is the test's file
+    pub in_file: bool,
+}
+
+impl Failure {
+    /// Write the error as a diagnostic
+    pub fn report(&self, writer: &mut impl termcolor::WriteColor, path: &Path) {
+        let name = match self.in_file {
+            true => path.display().to_string(),
+            false => "<script>".to_string(),
+        };
+        error::report_error_in(writer, &name, &self.source, &self.error);
+    }
+}
+
+/// Find the `test_*` functions in a file and run each of them in a new [`VM`]
+///
+/// Every test runs the whole file first, so the functions and globals it uses
+/// are declared, then calls the test function with no arguments. The test
+/// fails if either raises an error (e.g. a failed `assert`).
+pub fn run_file(path: &Path, source: &str, package_paths: &[PathBuf]) -> FileReport {
+    let mut report = FileReport {
+        path: path.to_path_buf(),
+        source: source.to_string(),
+        errors: Vec::new(),
+        tests: Vec::new(),
+    };
+
+    let program = match crate::syntax::parse(source, 0) {
+        Ok(program) => program,
+        Err(errors) => {
+            report.errors = errors;
+            return report;
+        }
+    };
+
+    for (stmt, span) in &program.stmts {
+        match stmt {
+            Stmt::Fn(fn_) if fn_.name.starts_with(TEST_PREFIX) => {
+                let test = run_test(source, &fn_.name, span.clone(), package_paths);
+                report.tests.push(test);
+            }
+            _ => {}
+        }
+    }
+    report
+}
+
+fn run_test(source: &str, name: &str, span: Span, package_paths: &[PathBuf]) -> TestReport {
+    let mut vm = VM::new();
+    for path in package_paths {
+        vm.add_package_path(path);
+    }
+
+    let start = Instant::now();
+    let mut output = Vec::new();
+    let result = vm.run(source, &mut output).and_then(|()| {
+        let function = vm.get_global(name).expect("test function should be declared");
+        vm.call(&function, &[], &mut output).map(|_| ())
+    });
+    let duration = start.elapsed();
+
+    let failure = result.err().map(|mut errors| failure(&vm, source, errors.remove(0)));
+    let output = String::from_utf8_lossy(&output).into_owned();
+    TestReport { name: name.to_string(), span, duration, output, failure }
+}
+
+/// Move an error's span from the [`VM`]'s source into the test's file when
+/// it's there
+fn failure(vm: &VM, source: &str, (error, span): ErrorS) -> Failure {
+    match vm.source_at(span.start) {
+        Some((start, SourceName::Script)) if span.end - start <= source.len() => Failure {
+            error: (error, span.start - start..span.end - start),
+            source: source.to_string(),
+            in_file: true,
+        },
+        _ => Failure { error: (error, span), source: vm.source.clone(), in_file: false },
+    }
+}
+
+/// Describe the results as a JUnit XML report, with a test suite per file
+pub fn junit(reports: &[FileReport]) -> String {
+    let tests =
+        reports.iter().map(|report| report.tests.len() + report.errors.len()).sum::<usize>();
+    let failures = reports.iter().map(FileReport::failed).sum::<usize>();
+    let errors = reports.iter().map(|report| report.errors.len()).sum::<usize>();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += &format!(
+        "<testsuites name=\"locks\" tests=\"{tests}\" failures=\"{failures}\" errors=\"{errors}\">\n"
+    );
+    for report in reports {
+        let path = escape(&report.path.display().to_string());
+        let time = report.tests.iter().map(|test| test.duration).sum::<Duration>();
+        xml += &format!(
+            "  <testsuite name=\"{path}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            report.tests.len() + report.errors.len(),
+            report.failed(),
+            report.errors.len(),
+            time.as_secs_f64(),
+        );
+
+        // Files that can't be loaded are reported as a test that errored
+        for error in &report.errors {
+            let mut buffer = termcolor::Buffer::no_color();
+            error::report_error_in(
+                &mut buffer,
+                &report.path.display().to_string(),
+                &report.source,
+                error,
+            );
+            xml += &format!("    <testcase name=\"{path}\" classname=\"{path}\">\n");
+            xml += &format!(
+                "      <error message=\"{}\" type=\"{}\">{}</error>\n",
+                escape(&error.0.to_string()),
+                escape(error.0.type_()),
+                escape(&String::from_utf8_lossy(buffer.as_slice())),
+            );
+            xml += "    </testcase>\n";
+        }
+
+        for test in &report.tests {
+            let line = report.source[..test.span.start].matches('\n').count() + 1;
+            xml += &format!(
+                "    <testcase name=\"{}\" classname=\"{path}\" file=\"{path}\" line=\"{line}\" time=\"{:.3}\">\n",
+                escape(&test.name),
+                test.duration.as_secs_f64(),
+            );
+            if let Some(failure) = &test.failure {
+                let mut buffer = termcolor::Buffer::no_color();
+                failure.report(&mut buffer, &report.path);
+                xml += &format!(
+                    "      <failure message=\"{}\" type=\"{}\">{}</failure>\n",
+                    escape(&failure.error.0.to_string()),
+                    escape(failure.error.0.type_()),
+                    escape(&String::from_utf8_lossy(buffer.as_slice())),
+                );
+            }
+            if !test.output.is_empty() {
+                xml += &format!("      <system-out>{}</system-out>\n", escape(&test.output));
+            }
+            xml += "    </testcase>\n";
+        }
+        xml += "  </testsuite>\n";
+    }
+    xml += "</testsuites>\n";
+    xml
+}
+
+/// Escape text for use in XML content and attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
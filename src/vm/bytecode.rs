@@ -0,0 +1,718 @@
+//! Binary format for compiled Locks programs (`.lockc` files)
+//!
+//! All integers are little endian. A file is laid out as:
+//!
+//! ```text
+//! header    "LOCKC" u16(version)
+//! source    string
+//! package   u8(0) | u8(1) string
+//! imports   u32(count) (string span)*
+//! function  string(name) u8(arity) u16(upvalue_count)
+//!           u32(count) u8(op)*
+//!           u32(count) constant*
+//!           u32(count) (span u8(repeat))*
+//!           u32(count) (string(name) u8(slot) u32(start) u32(end))*
+//!           u32(count) string(upvalue name)*
+//!
+//! constant  u8(0) f64 | u8(1) string | u8(2) function
+//! string    u32(length) utf8
+//! span      u32(start) u32(end)
+//! ```
+//!
+//! Spans are relative to the start of the embedded source. A local variable
+//! that's in scope until the end of its function has an end of `u32::MAX`.
+//!
+//! Compiled files may come from anywhere, so each function's ops are checked
+//! before they can run: every op must be known and have all of its operands,
+//! constant, local & upvalue indexes must be in range, jumps must land on an
+//! op, and the stack must have the same height whichever way an op is
+//! reached.
+
+use crate::error::{BytecodeError, ErrorS};
+use crate::syntax::ast::Program;
+use crate::types::{Span, Spanned};
+use crate::vm::chunk::LocalInfo;
+use crate::vm::gc::Gc;
+use crate::vm::object::{ObjectFunction, ObjectType};
+use crate::vm::value::Value;
+use crate::vm::{op, Compiler, STACK_MAX_PER_FRAME};
+
+const HEADER: &[u8] = b"LOCKC";
+const VERSION: u16 = 2;
+
+const CONSTANT_NUMBER: u8 = 0;
+const CONSTANT_STRING: u8 = 1;
+const CONSTANT_FUNCTION: u8 = 2;
+
+/// A compiled program read from bytecode
+#[derive(Debug)]
+pub struct Script<'a> {
+    pub source: &'a str,
+    pub package: Option<&'a str>,
+    pub imports: Vec<Spanned<&'a str>>,
+    /// The encoded script function, allocated by [`Script::function`]
+    function: &'a [u8],
+    offset: usize,
+}
+
+impl Script<'_> {
+    /// Allocate the script function, its constants and nested functions
+    ///
+    /// This is separate from [`deserialize`] so the function can be allocated
+    /// after the imports have run, which may trigger a garbage collection.
+    pub fn function(&self, gc: &mut Gc) -> Result<*mut ObjectFunction, BytecodeError> {
+        let mut reader = Reader { bytes: self.function, offset: self.offset };
+        let function = reader.function(gc, true)?;
+
+        if !reader.bytes.is_empty() {
+            return Err(BytecodeError::Invalid { item: "trailing data".to_string() });
+        }
+
+        Ok(function)
+    }
+}
+
+/// Parse and compile `source` into bytecode
+pub fn compile(source: &str, gc: &mut Gc) -> Result<Vec<u8>, Vec<ErrorS>> {
+    let program = crate::syntax::parse(source, 0)?;
+    let function = Compiler::compile(&program, gc)?;
+
+    Ok(serialize(source, &program, function))
+}
+
+/// Write a compiled program as bytecode
+pub fn serialize(source: &str, program: &Program, function: *mut ObjectFunction) -> Vec<u8> {
+    let mut writer = Writer::default();
+
+    writer.bytes(HEADER);
+    writer.u16(VERSION);
+    writer.string(source);
+
+    match &program.package {
+        Some(package) => {
+            writer.u8(1);
+            writer.string(package);
+        }
+        None => writer.u8(0),
+    }
+
+    writer.u32(program.imports.len());
+    for (import, span) in &program.imports {
+        writer.string(&import.package);
+        writer.span(span);
+    }
+
+    writer.function(function);
+
+    writer.bytes
+}
+
+/// Read a program from bytecode
+///
+/// Spans are moved forward by `offset`, which should be where the script's
+/// source starts in the [`VM`](crate::vm::VM)'s source.
+///
+/// Ops are validated when [`Script::function`] allocates them.
+pub fn deserialize(bytes: &[u8], offset: usize) -> Result<Script<'_>, BytecodeError> {
+    let mut reader = Reader { bytes, offset };
+
+    if reader.bytes(HEADER.len())? != HEADER {
+        return Err(BytecodeError::InvalidHeader);
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion { version, expected: VERSION });
+    }
+
+    let source = reader.string()?;
+
+    let package = match reader.u8()? {
+        0 => None,
+        1 => Some(reader.string()?),
+        _ => return Err(BytecodeError::Invalid { item: "package".to_string() }),
+    };
+
+    let mut imports = Vec::new();
+    for _ in 0..reader.u32()? {
+        let import = reader.string()?;
+        let span = reader.span()?;
+        imports.push((import, span));
+    }
+
+    Ok(Script { source, package, imports, function: reader.bytes, offset })
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: usize) {
+        let value = u32::try_from(value).expect("value is too large for bytecode");
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len());
+        self.bytes(value.as_bytes());
+    }
+
+    fn span(&mut self, span: &Span) {
+        self.u32(span.start);
+        self.u32(span.end);
+    }
+
+    fn function(&mut self, function: *mut ObjectFunction) {
+        let function = unsafe { &*function };
+
+        self.string(unsafe { (*function.name).value });
+        self.u8(function.arity);
+        self.u16(function.upvalue_count);
+
+        self.u32(function.chunk.ops.len());
+        self.bytes(&function.chunk.ops);
+
+        self.u32(function.chunk.constants.len());
+        for &constant in &function.chunk.constants {
+            self.constant(constant);
+        }
+
+        let runs = function.chunk.spans.runs().collect::<Vec<_>>();
+        self.u32(runs.len());
+        for (span, count) in runs {
+            self.span(span);
+            self.u8(count);
+        }
+
+        self.u32(function.chunk.locals.len());
+        for local in &function.chunk.locals {
+            self.string(&local.name);
+            self.u8(local.slot);
+            self.u32(local.start);
+            self.u32(local.end.min(u32::MAX as usize));
+        }
+
+        self.u32(function.chunk.upvalues.len());
+        for name in &function.chunk.upvalues {
+            self.string(name);
+        }
+    }
+
+    fn constant(&mut self, value: Value) {
+        if value.is_number() {
+            self.u8(CONSTANT_NUMBER);
+            self.bytes(&value.as_number().to_le_bytes());
+            return;
+        }
+
+        let object = value.as_object();
+        match object.type_() {
+            ObjectType::String => {
+                self.u8(CONSTANT_STRING);
+                self.string(unsafe { (*object.string).value });
+            }
+            ObjectType::Function => {
+                self.u8(CONSTANT_FUNCTION);
+                self.function(unsafe { object.function });
+            }
+            type_ => unreachable!("the compiler does not emit {type_} constants"),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], BytecodeError> {
+        if self.bytes.len() < len {
+            return Err(BytecodeError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], BytecodeError> {
+        Ok(self.bytes(N)?.try_into().expect("read the wrong number of bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<usize, BytecodeError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<&'a str, BytecodeError> {
+        let len = self.u32()?;
+        std::str::from_utf8(self.bytes(len)?)
+            .map_err(|_| BytecodeError::Invalid { item: "string".to_string() })
+    }
+
+    fn span(&mut self) -> Result<Span, BytecodeError> {
+        let start = self.u32()? + self.offset;
+        let end = self.u32()? + self.offset;
+        Ok(start..end)
+    }
+
+    /// Read a function, which is the script's if `is_script` is set
+    fn function(
+        &mut self,
+        gc: &mut Gc,
+        is_script: bool,
+    ) -> Result<*mut ObjectFunction, BytecodeError> {
+        let name = gc.alloc(self.string()?);
+        let arity = self.u8()?;
+        let mut function = ObjectFunction::new(name, arity);
+        function.upvalue_count = self.u16()?;
+
+        let len = self.u32()?;
+        function.chunk.ops = self.bytes(len)?.to_vec();
+
+        for _ in 0..self.u32()? {
+            let constant = self.constant(gc)?;
+            function
+                .chunk
+                .constants
+                .try_push(constant)
+                .map_err(|_| BytecodeError::Invalid { item: "constant count".to_string() })?;
+        }
+
+        let mut span_count = 0;
+        for _ in 0..self.u32()? {
+            let span = self.span()?;
+            let count = self.u8()?;
+            span_count += count as usize;
+            function.chunk.spans.push_run(span, count);
+        }
+        if span_count != function.chunk.ops.len() {
+            return Err(BytecodeError::Invalid { item: "span table".to_string() });
+        }
+
+        for _ in 0..self.u32()? {
+            let name = self.string()?.to_string();
+            let slot = self.u8()?;
+            let start = self.u32()?;
+            let end = match self.u32()? {
+                end if end == u32::MAX as usize => usize::MAX,
+                end => end,
+            };
+            function.chunk.locals.push(LocalInfo { name, slot, start, end });
+        }
+
+        for _ in 0..self.u32()? {
+            let name = self.string()?.to_string();
+            function.chunk.upvalues.push(name);
+        }
+
+        // The script runs without a closure on the stack or any upvalues
+        let stack_height = if is_script {
+            if function.upvalue_count != 0 {
+                return Err(invalid("upvalue count"));
+            }
+            0
+        } else {
+            function.arity as usize + 1
+        };
+        verify(&function, stack_height)?;
+
+        Ok(gc.alloc(function))
+    }
+
+    fn constant(&mut self, gc: &mut Gc) -> Result<Value, BytecodeError> {
+        match self.u8()? {
+            CONSTANT_NUMBER => {
+                // Other NaNs could be mistaken for objects
+                let number = f64::from_le_bytes(self.array()?);
+                Ok(if number.is_nan() { f64::NAN } else { number }.into())
+            }
+            CONSTANT_STRING => Ok(gc.alloc(self.string()?).into()),
+            CONSTANT_FUNCTION => Ok(self.function(gc, false)?.into()),
+            _ => Err(BytecodeError::Invalid { item: "constant".to_string() }),
+        }
+    }
+}
+
+fn invalid(item: &str) -> BytecodeError {
+    BytecodeError::Invalid { item: item.to_string() }
+}
+
+/// Check that a function's ops can run without reading past its ops,
+/// constants, upvalues or stack, starting with `stack_height` values on the
+/// stack
+fn verify(function: &ObjectFunction, stack_height: usize) -> Result<(), BytecodeError> {
+    let ops = &function.chunk.ops;
+
+    // The length of the op starting at each index
+    let mut lens = vec![0; ops.len()];
+    let mut idx = 0;
+    while idx < ops.len() {
+        lens[idx] = op_len(function, idx)?;
+        idx += lens[idx];
+    }
+
+    // The stack's height before each op that can run
+    let mut heights = Heights { lens: &lens, heights: vec![None; ops.len()], queue: Vec::new() };
+    heights.enter(Some(0), stack_height)?;
+
+    while let Some(idx) = heights.queue.pop() {
+        let height = heights.heights[idx].expect("queued ops have a height");
+        let next = idx + lens[idx];
+        let byte = |offset: usize| ops[idx + offset] as usize;
+        let jump = || u16::from_le_bytes([ops[idx + 1], ops[idx + 2]]) as usize;
+        let local = |slot: usize| if slot < height { Ok(()) } else { Err(invalid("local")) };
+
+        let (pops, pushes) = match ops[idx] {
+            op::JUMP | op::LOOP | op::TRY | op::END_TRY => (0, 0),
+            op::CONSTANT
+            | op::NIL
+            | op::TRUE
+            | op::FALSE
+            | op::GET_GLOBAL
+            | op::GET_UPVALUE
+            | op::CLASS
+            | op::PACKAGE
+            | op::GET_PACKAGE => (0, 1),
+            op::GET_LOCAL => {
+                local(byte(1))?;
+                (0, 1)
+            }
+            op::SET_LOCAL => {
+                local(byte(1))?;
+                (1, 1)
+            }
+            op::CLOSURE => {
+                for capture in (idx + 2..next).step_by(2) {
+                    if ops[capture] == 1 {
+                        local(ops[capture + 1] as usize)?;
+                    }
+                }
+                (0, 1)
+            }
+            op::POP | op::DEFINE_GLOBAL | op::CLOSE_UPVALUE | op::RETURN | op::THROW => (1, 0),
+            op::SET_GLOBAL
+            | op::SET_UPVALUE
+            | op::GET_PROPERTY
+            | op::NOT
+            | op::NEGATE
+            | op::JUMP_IF_FALSE => (1, 1),
+            op::SET_PROPERTY
+            | op::GET_SUPER
+            | op::EQUAL
+            | op::NOT_EQUAL
+            | op::GREATER
+            | op::GREATER_EQUAL
+            | op::LESS
+            | op::LESS_EQUAL
+            | op::ADD
+            | op::SUBTRACT
+            | op::MULTIPLY
+            | op::DIVIDE
+            | op::MODULUS
+            | op::INHERIT
+            | op::METHOD
+            | op::FIELD
+            | op::STATIC_FIELD
+            | op::STATIC_METHOD
+            | op::GET_INDEX => (2, 1),
+            op::SET_INDEX => (3, 1),
+            op::CALL => (byte(1) + 1, 1),
+            op::INVOKE => (byte(2) + 1, 1),
+            op::SUPER_INVOKE => (byte(2) + 2, 1),
+            op::CREATE_LIST => (byte(1), 1),
+            op::CREATE_MAP => (byte(1) * 2, 1),
+            _ => unreachable!("ops are checked by op_len"),
+        };
+        let height = height.checked_sub(pops).ok_or_else(|| invalid("stack height"))? + pushes;
+
+        match ops[idx] {
+            op::RETURN | op::THROW => {}
+            op::JUMP => heights.enter(Some(next + jump()), height)?,
+            op::LOOP => heights.enter(next.checked_sub(jump()), height)?,
+            op::JUMP_IF_FALSE => {
+                heights.enter(Some(next + jump()), height)?;
+                heights.enter(Some(next), height)?;
+            }
+            // The handler runs with the error pushed on to the stack
+            op::TRY => {
+                heights.enter(Some(next + jump()), height + 1)?;
+                heights.enter(Some(next), height)?;
+            }
+            _ => heights.enter(Some(next), height)?,
+        }
+    }
+
+    // Debuggers read locals from the stack while they're in scope
+    for local in &function.chunk.locals {
+        for (idx, height) in heights.heights.iter().enumerate() {
+            let in_scope = idx < local.end && idx + lens[idx] > local.start;
+            if in_scope && height.is_some_and(|height| local.slot as usize >= height) {
+                return Err(invalid("local"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The stack's height before each op, found by following the ops from the
+/// start of a function
+struct Heights<'a> {
+    lens: &'a [usize],
+    heights: Vec<Option<usize>>,
+    /// Ops to follow
+    queue: Vec<usize>,
+}
+
+impl Heights<'_> {
+    /// Reach the op at `idx` with `height` values on the stack
+    fn enter(&mut self, idx: Option<usize>, height: usize) -> Result<(), BytecodeError> {
+        let Some(idx) = idx.filter(|&idx| self.lens.get(idx).is_some_and(|&len| len > 0)) else {
+            return Err(invalid("jump"));
+        };
+        if height > STACK_MAX_PER_FRAME {
+            return Err(invalid("stack height"));
+        }
+
+        match self.heights[idx] {
+            Some(expected) if expected != height => Err(invalid("stack height")),
+            Some(_) => Ok(()),
+            None => {
+                self.heights[idx] = Some(height);
+                self.queue.push(idx);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The length of the op at `idx` with its operands, checking that they're in
+/// range
+fn op_len(function: &ObjectFunction, idx: usize) -> Result<usize, BytecodeError> {
+    let chunk = &function.chunk;
+    let operand = |offset: usize| chunk.ops.get(idx + offset).copied().ok_or_else(|| invalid("op"));
+    let constant = |offset: usize, type_: Option<ObjectType>| {
+        let value =
+            *chunk.constants.get(operand(offset)? as usize).ok_or_else(|| invalid("constant"))?;
+        let is_valid = match type_ {
+            Some(type_) => value.is_object() && value.as_object().type_() == type_,
+            // Function constants are only for closures
+            None => value.is_number() || value.as_object().type_() == ObjectType::String,
+        };
+        if is_valid {
+            Ok(value)
+        } else {
+            Err(invalid("constant"))
+        }
+    };
+    let upvalue = |offset: usize| {
+        if u16::from(operand(offset)?) < function.upvalue_count {
+            Ok(())
+        } else {
+            Err(invalid("upvalue"))
+        }
+    };
+
+    let len = match chunk.ops[idx] {
+        op::NIL
+        | op::TRUE
+        | op::FALSE
+        | op::POP
+        | op::EQUAL
+        | op::NOT_EQUAL
+        | op::GREATER
+        | op::GREATER_EQUAL
+        | op::LESS
+        | op::LESS_EQUAL
+        | op::ADD
+        | op::SUBTRACT
+        | op::MULTIPLY
+        | op::DIVIDE
+        | op::MODULUS
+        | op::NOT
+        | op::NEGATE
+        | op::CLOSE_UPVALUE
+        | op::RETURN
+        | op::INHERIT
+        | op::GET_INDEX
+        | op::SET_INDEX
+        | op::END_TRY
+        | op::THROW => 1,
+        op::GET_LOCAL | op::SET_LOCAL | op::CALL | op::CREATE_LIST | op::CREATE_MAP => {
+            operand(1)?;
+            2
+        }
+        op::GET_UPVALUE | op::SET_UPVALUE => {
+            upvalue(1)?;
+            2
+        }
+        op::CONSTANT => {
+            constant(1, None)?;
+            2
+        }
+        op::GET_GLOBAL
+        | op::DEFINE_GLOBAL
+        | op::SET_GLOBAL
+        | op::GET_PROPERTY
+        | op::SET_PROPERTY
+        | op::GET_SUPER
+        | op::CLASS
+        | op::METHOD
+        | op::FIELD
+        | op::PACKAGE
+        | op::STATIC_FIELD
+        | op::STATIC_METHOD
+        | op::GET_PACKAGE => {
+            constant(1, Some(ObjectType::String))?;
+            2
+        }
+        op::INVOKE | op::SUPER_INVOKE => {
+            constant(1, Some(ObjectType::String))?;
+            operand(2)?;
+            3
+        }
+        op::JUMP | op::JUMP_IF_FALSE | op::LOOP | op::TRY => {
+            operand(2)?;
+            3
+        }
+        op::CLOSURE => {
+            let closure = constant(1, Some(ObjectType::Function))?;
+            let upvalue_count = unsafe { (*closure.as_object().function).upvalue_count };
+            for capture in 0..upvalue_count as usize {
+                let offset = 2 + capture * 2;
+                match operand(offset)? {
+                    // Locals are checked against the stack's height
+                    1 => operand(offset + 1).map(drop)?,
+                    0 => upvalue(offset + 1)?,
+                    _ => return Err(invalid("upvalue")),
+                }
+            }
+            2 + upvalue_count as usize * 2
+        }
+        _ => return Err(invalid("op")),
+    };
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut gc = Gc::default();
+        let source = "import some::pkg; fn add(a, b) { return a + b; } println(add(1.5, \"2\"));";
+        let bytes = compile(source, &mut gc).expect("failed to compile");
+
+        let script = deserialize(&bytes, 10).expect("failed to deserialize");
+        assert_eq!(script.source, source);
+        assert_eq!(script.package, None);
+        assert_eq!(script.imports, vec![("some::pkg", 10..27)]);
+
+        let function = script.function(&mut gc).expect("failed to load function");
+        let program = crate::syntax::parse(source, 0).expect("failed to parse");
+        let expected = Compiler::compile(&program, &mut gc).expect("failed to compile");
+        let (function, expected) = unsafe { (&(*function).chunk, &(*expected).chunk) };
+        assert_eq!(function.ops, expected.ops);
+        assert_eq!(function.constants.len(), expected.constants.len());
+        assert_eq!(function.locals, expected.locals);
+        for idx in 0..function.ops.len() {
+            let span = &expected.spans[idx];
+            assert_eq!(function.spans[idx], span.start + 10..span.end + 10);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let mut gc = Gc::default();
+        let bytes = compile("println(1);", &mut gc).expect("failed to compile");
+
+        assert_eq!(deserialize(b"LOCKS", 0).unwrap_err(), BytecodeError::InvalidHeader);
+
+        let mut version = bytes.clone();
+        version[HEADER.len()] = 99;
+        assert_eq!(
+            deserialize(&version, 0).unwrap_err(),
+            BytecodeError::UnsupportedVersion { version: 99, expected: VERSION }
+        );
+
+        let script = deserialize(&bytes[..bytes.len() - 1], 0).expect("failed to deserialize");
+        assert_eq!(script.function(&mut gc).unwrap_err(), BytecodeError::UnexpectedEof);
+    }
+
+    /// Write a function with `ops` and `constants`, then read it back
+    fn load(ops: &[u8], constants: &[Value]) -> Result<(), BytecodeError> {
+        let mut gc = Gc::default();
+        let mut function = ObjectFunction::new(gc.alloc("test"), 0);
+        for &byte in ops {
+            function.chunk.write_u8(byte, &(0..0));
+        }
+        function.chunk.constants.extend(constants.iter().copied());
+
+        let mut writer = Writer::default();
+        writer.function(gc.alloc(function));
+        let mut reader = Reader { bytes: &writer.bytes, offset: 0 };
+        reader.function(&mut gc, true).map(drop)
+    }
+
+    #[test]
+    fn invalid_ops() {
+        let error = |item: &str| Err(BytecodeError::Invalid { item: item.to_string() });
+        let number = Value::from(1.0);
+
+        assert_eq!(load(&[op::NIL, op::RETURN], &[]), Ok(()));
+        assert_eq!(load(&[0xEE], &[]), error("op"));
+        assert_eq!(load(&[op::CONSTANT], &[]), error("op"));
+        assert_eq!(load(&[op::CONSTANT, 1, op::RETURN], &[number]), error("constant"));
+        assert_eq!(load(&[op::GET_GLOBAL, 0, op::RETURN], &[number]), error("constant"));
+        assert_eq!(load(&[op::GET_UPVALUE, 0, op::RETURN], &[]), error("upvalue"));
+        assert_eq!(load(&[op::NIL, op::GET_LOCAL, 1, op::RETURN], &[]), error("local"));
+        assert_eq!(load(&[op::POP, op::NIL, op::RETURN], &[]), error("stack height"));
+        assert_eq!(load(&[op::NIL, op::CALL, 1, op::RETURN], &[]), error("stack height"));
+        assert_eq!(load(&[op::JUMP, 1, 0, op::CONSTANT, 0, op::RETURN], &[number]), error("jump"));
+        assert_eq!(load(&[op::LOOP, 4, 0, op::NIL, op::RETURN], &[]), error("jump"));
+        // Running off the end of the ops
+        assert_eq!(load(&[op::NIL], &[]), error("jump"));
+        // The `true` branch pushes one more value than the `false` branch
+        assert_eq!(
+            load(&[op::TRUE, op::JUMP_IF_FALSE, 1, 0, op::NIL, op::RETURN], &[]),
+            error("stack height")
+        );
+    }
+
+    #[test]
+    fn nan_constants_are_numbers() {
+        let mut gc = Gc::default();
+        let mut writer = Writer::default();
+        writer.u8(CONSTANT_NUMBER);
+        // A NaN with the bits of a pointer to an object
+        writer.bytes(&(0xfffc_0000_dead_beef_u64).to_le_bytes());
+
+        let mut reader = Reader { bytes: &writer.bytes, offset: 0 };
+        let value = reader.constant(&mut gc).expect("failed to read constant");
+        assert!(value.is_number());
+        assert!(value.as_number().is_nan());
+    }
+}
@@ -11,6 +11,22 @@ pub struct Chunk {
     pub ops: Vec<u8>,
     pub constants: ArrayVec<Value, 256>,
     pub spans: VecRun<Span>,
+    /// Local variables by the range of ops they're in scope for. Used by the
+    /// debugger to name stack slots.
+    pub locals: Vec<LocalInfo>,
+    /// Names of the variables captured by the function, by upvalue index
+    pub upvalues: Vec<String>,
+}
+
+/// A local variable's stack slot and where it's in scope
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LocalInfo {
+    pub name: String,
+    pub slot: u8,
+    /// Index of the first op the variable is in scope for
+    pub start: usize,
+    /// Index of the op after the variable goes out of scope
+    pub end: usize,
 }
 
 impl Chunk {
@@ -53,6 +69,18 @@ impl<T: Eq> VecRun<T> {
     }
 }
 
+impl<T> VecRun<T> {
+    /// Iterate over each run's value and how many times it repeats
+    pub fn runs(&self) -> impl Iterator<Item = (&T, u8)> {
+        self.values.iter().map(|run| (&run.value, run.count))
+    }
+
+    /// Append a run of `count` copies of `value`
+    pub fn push_run(&mut self, value: T, count: u8) {
+        self.values.push(Run { value, count });
+    }
+}
+
 impl<T> Index<usize> for VecRun<T> {
     type Output = T;
 
@@ -1,14 +1,16 @@
 use std::convert::TryInto;
 use std::mem;
+use std::rc::Rc;
 
 use arrayvec::ArrayVec;
 
-use crate::error::{ErrorS, NameError, OverflowError, Result, SyntaxError};
+use crate::error::{ErrorS, ImportError, NameError, OverflowError, Result, SyntaxError};
 use crate::syntax::ast::{
-    Expr, ExprLiteral, ExprS, Identifier, OpInfix, OpPrefix, Program, Stmt, StmtFn, StmtReturn,
-    StmtS,
+    Expr, ExprLiteral, ExprS, Identifier, OpInfix, OpPrefix, Program, Stmt, StmtBlock, StmtFn,
+    StmtReturn, StmtS, StmtTry,
 };
 use crate::types::Span;
+use crate::vm::chunk::LocalInfo;
 use crate::vm::gc::Gc;
 use crate::vm::object::{ObjectFunction, ObjectString};
 use crate::vm::op;
@@ -33,9 +35,10 @@ impl Compiler {
                 upvalues: ArrayVec::new(),
                 parent: None,
                 scope_depth: 0,
+                tries: Vec::new(),
             },
             class_ctx: Vec::new(),
-            package_ctx: PackageCtx { name: None },
+            package_ctx: PackageCtx { name: None, imports: Vec::new() },
         }
     }
 
@@ -59,14 +62,18 @@ impl Compiler {
             let _ = compiler.declare_local("package", &NO_SPAN);
             compiler.define_local();
 
-            let _ = compiler.set_variable("package", &NO_SPAN, gc);
+            let _ =
+                compiler.set_variable(&Identifier::from("package".to_string(), None), &NO_SPAN, gc);
         }
 
+        compiler.package_ctx.imports =
+            program.imports.iter().map(|(import, _)| import.package.to_string()).collect();
+
         for stmt in &program.stmts {
             compiler.compile_stmt(stmt, gc).map_err(|e| vec![e])?;
         }
 
-        if let Some(_) = &program.package {
+        if program.package.is_some() {
             compiler.emit_u8(op::POP, &NO_SPAN);
             compiler.package_ctx.name = None;
         }
@@ -81,13 +88,7 @@ impl Compiler {
     /// Write ops to [`Chunk`].
     fn compile_stmt(&mut self, (stmt, span): &StmtS, gc: &mut Gc) -> Result<()> {
         match stmt {
-            Stmt::Block(block) => {
-                self.begin_scope();
-                for stmt in &block.stmts {
-                    self.compile_stmt(stmt, gc)?;
-                }
-                self.end_scope(span);
-            }
+            Stmt::Block(block) => self.compile_block(block, span, gc)?,
             Stmt::Class(class) => {
                 let has_super = class.super_.is_some();
 
@@ -132,10 +133,10 @@ impl Compiler {
 
                     self.begin_scope();
                     self.declare_local("super", &NO_SPAN)?;
-                    self.define_local();
 
                     // Get parent class `Value` and push it on the VM's stack
                     self.compile_expr(super_, gc)?;
+                    self.define_local();
                     // Get new class `Value` and push it on the VM's stack
                     self.get_variable(
                         &Identifier { name: class.name.clone(), package: None, depth: None },
@@ -154,13 +155,13 @@ impl Compiler {
 
                         self.begin_scope();
                         self.declare_local("super", &NO_SPAN)?;
-                        self.define_local();
 
                         self.get_variable(
                             &Identifier { name: base_class, package: None, depth: None },
                             span,
                             gc,
                         )?;
+                        self.define_local();
 
                         self.get_variable(
                             &Identifier { name: class.name.clone(), package: None, depth: None },
@@ -386,6 +387,7 @@ impl Compiler {
                         None => self.emit_u8(op::NIL, span),
                     },
                 }
+                self.compile_pending_finally(span, gc)?;
                 self.emit_u8(op::RETURN, span);
             }
             Stmt::Assign(assign) => {
@@ -427,7 +429,138 @@ impl Compiler {
                 // Discard the condition.
                 self.emit_u8(op::POP, span);
             }
+            Stmt::Throw(throw) => {
+                self.compile_expr(&throw.value, gc)?;
+                self.emit_u8(op::THROW, span);
+            }
+            Stmt::Try(try_) => self.compile_try(try_, span, gc)?,
+        }
+        Ok(())
+    }
+
+    fn compile_block(&mut self, block: &StmtBlock, span: &Span, gc: &mut Gc) -> Result<()> {
+        self.begin_scope();
+        for stmt in &block.stmts {
+            self.compile_stmt(stmt, gc)?;
+        }
+        self.end_scope(span);
+        Ok(())
+    }
+
+    /// Compile a try statement
+    ///
+    /// When an error is raised after `OP_TRY`, the VM unwinds the stack to
+    /// where it was when the handler was registered then pushes the error on
+    /// to it before jumping to the handler.
+    ///
+    /// Emits the following byte code format:
+    ///
+    /// - `0000 OP_TRY -> FINALLY_HANDLER` (with a finally block)
+    /// - `0003 OP_TRY -> CATCH` (with a catch block)
+    /// - `.... body`
+    /// - `.... OP_END_TRY` (with a catch block)
+    /// - `.... OP_JUMP -> END_CATCH` (with a catch block)
+    /// - `.... CATCH: catch block, with the error as a local`
+    /// - `.... END_CATCH: OP_END_TRY` (with a finally block)
+    /// - `.... finally block`
+    /// - `.... OP_JUMP -> END` (with a finally block)
+    /// - `.... FINALLY_HANDLER: finally block, with the error as a local`
+    /// - `.... OP_THROW` (with a finally block)
+    /// - `.... END:`
+    ///
+    /// A `return` inside the body or catch block runs a copy of the finally
+    /// block before returning, see [`Compiler::compile_pending_finally`].
+    fn compile_try(&mut self, try_: &StmtTry, span: &Span, gc: &mut Gc) -> Result<()> {
+        let finally_handler = try_.finally.as_ref().map(|_| self.emit_jump(op::TRY, span));
+        let finally = try_.finally.clone().map(Rc::new);
+        let handlers = usize::from(finally.is_some());
+
+        match &try_.catch {
+            Some(catch) => {
+                let catch_handler = self.emit_jump(op::TRY, span);
+                self.ctx.tries.push(TryCtx { handlers: handlers + 1, finally: finally.clone() });
+                self.compile_block(&try_.body, span, gc)?;
+                self.emit_u8(op::END_TRY, span);
+                let jump_to_end_catch = self.emit_jump(op::JUMP, span);
+
+                // CATCH:
+                self.patch_jump(catch_handler, span)?;
+                self.ctx.tries.last_mut().expect("try should be in scope").handlers = handlers;
+                self.begin_scope();
+                self.declare_local(&catch.name, span)?;
+                self.define_local();
+                for stmt in &catch.body.stmts {
+                    self.compile_stmt(stmt, gc)?;
+                }
+                self.end_scope(span);
+
+                // END_CATCH:
+                self.patch_jump(jump_to_end_catch, span)?;
+            }
+            None => {
+                self.ctx.tries.push(TryCtx { handlers, finally });
+                self.compile_block(&try_.body, span, gc)?;
+            }
         }
+        self.ctx.tries.pop();
+
+        if let (Some(finally), Some(finally_handler)) = (&try_.finally, finally_handler) {
+            self.emit_u8(op::END_TRY, span);
+            self.compile_block(finally, span, gc)?;
+            let jump_to_end = self.emit_jump(op::JUMP, span);
+
+            // FINALLY_HANDLER:
+            self.patch_jump(finally_handler, span)?;
+            self.begin_scope();
+            // The error takes up a stack slot but can't be referenced by name
+            self.declare_local("", span)?;
+            self.define_local();
+            self.compile_block(finally, span, gc)?;
+            // Raise the error again. This pops it from the stack so the local
+            // is removed without emitting `OP_POP`.
+            self.emit_u8(op::THROW, span);
+            self.pop_local();
+            self.ctx.scope_depth -= 1;
+
+            // END:
+            self.patch_jump(jump_to_end, span)?;
+        }
+
+        Ok(())
+    }
+
+    /// Compile the `finally` blocks of every `try` statement a `return` exits,
+    /// innermost first.
+    ///
+    /// The return value is kept on the stack as an unnamed local while they
+    /// run. Each `try` statement's handlers are removed before its `finally`
+    /// block so errors raised there go to the enclosing handlers.
+    fn compile_pending_finally(&mut self, span: &Span, gc: &mut Gc) -> Result<()> {
+        if self.ctx.tries.iter().all(|try_| try_.finally.is_none()) {
+            return Ok(());
+        }
+
+        self.begin_scope();
+        self.declare_local("", span)?;
+        self.define_local();
+
+        let tries = mem::take(&mut self.ctx.tries);
+        for (idx, try_) in tries.iter().enumerate().rev() {
+            for _ in 0..try_.handlers {
+                self.emit_u8(op::END_TRY, span);
+            }
+            if let Some(finally) = &try_.finally {
+                // A `return` inside the `finally` block only exits the outer
+                // `try` statements.
+                self.ctx.tries = tries[..idx].to_vec();
+                self.compile_block(finally, span, gc)?;
+            }
+        }
+        self.ctx.tries = tries;
+
+        // `OP_RETURN` discards the local.
+        self.pop_local();
+        self.ctx.scope_depth -= 1;
         Ok(())
     }
 
@@ -452,6 +585,7 @@ impl Compiler {
             upvalues: ArrayVec::new(),
             parent: None,
             scope_depth: self.ctx.scope_depth + 1,
+            tries: Vec::new(),
         };
         self.begin_ctx(ctx);
 
@@ -470,11 +604,10 @@ impl Compiler {
             self.compile_stmt(stmt, gc)?;
         }
 
-        // Implicit return at the end of the function.
-        if unsafe { (*self.ctx.function).chunk.ops.last() } != Some(&op::RETURN) {
-            let stmt = (Stmt::Return(StmtReturn { value: None }), NO_SPAN);
-            self.compile_stmt(&stmt, gc)?;
-        }
+        // Implicit return at the end of the function. Branches that didn't
+        // return may jump here even if the last statement was a return.
+        let stmt = (Stmt::Return(StmtReturn { value: None }), NO_SPAN);
+        self.compile_stmt(&stmt, gc)?;
 
         let (function, upvalues) = self.end_ctx();
         let value = function.into();
@@ -495,7 +628,7 @@ impl Compiler {
         match expr {
             Expr::Assign(assign) => {
                 self.compile_expr(&assign.value, gc)?;
-                self.set_variable(&assign.identifier.name, span, gc)?;
+                self.set_variable(&assign.identifier, span, gc)?;
             }
             Expr::Call(call) => {
                 let arg_count = call
@@ -530,10 +663,7 @@ impl Compiler {
                 self.compile_expr(target, gc)?;
 
                 let index = &get_index.index;
-
-                let value = (*index).into();
-                self.emit_u8(op::CONSTANT, span);
-                self.emit_constant(value, span)?;
+                self.compile_expr(index, gc)?;
 
                 self.emit_u8(op::GET_INDEX, span);
             }
@@ -542,10 +672,7 @@ impl Compiler {
                 self.compile_expr(target, gc)?;
 
                 let index = &set_index.index;
-
-                let value = (*index).into();
-                self.emit_u8(op::CONSTANT, span);
-                self.emit_constant(value, span)?;
+                self.compile_expr(index, gc)?;
 
                 let value = &set_index.value;
                 self.compile_expr(value, gc)?;
@@ -647,7 +774,7 @@ impl Compiler {
                 }
                 ExprLiteral::List(values) => {
                     for value in values.iter() {
-                        let _ = self.compile_expr(value.into(), gc);
+                        let _ = self.compile_expr(value, gc);
                     }
 
                     self.emit_u8(op::CREATE_LIST, span);
@@ -659,6 +786,21 @@ impl Compiler {
 
                     self.emit_u8(list_length, span);
                 }
+                ExprLiteral::Map(entries) => {
+                    for (key, value) in entries {
+                        self.compile_expr(key, gc)?;
+                        self.compile_expr(value, gc)?;
+                    }
+
+                    self.emit_u8(op::CREATE_MAP, span);
+
+                    let map_length = entries
+                        .len()
+                        .try_into()
+                        .map_err(|_| (OverflowError::TooManyArgs.into(), span.clone()))?;
+
+                    self.emit_u8(map_length, span);
+                }
             },
             Expr::Prefix(prefix) => {
                 self.compile_expr(&prefix.rt, gc)?;
@@ -737,9 +879,22 @@ impl Compiler {
     /// - globals
     ///   - `0000 OP_GET_GLOBAL`
     ///   - `0001 chunk_constant_idx`
+    /// - package globals
+    ///   - `0000 OP_GET_PACKAGE`
+    ///   - `0001 chunk_constant_idx`
+    ///   - `0002 OP_GET_PROPERTY`
+    ///   - `0003 chunk_constant_idx`
     fn get_variable(&mut self, idenifier: &Identifier, span: &Span, gc: &mut Gc) -> Result<()> {
         let name = idenifier.name.as_str();
 
+        if let Some(package) = self.foreign_package(idenifier) {
+            self.get_package(package, span, gc)?;
+            let name = gc.alloc(name);
+            self.emit_u8(op::GET_PROPERTY, span);
+            self.emit_constant(name.into(), span)?;
+            return Ok(());
+        }
+
         if name == "this" && self.class_ctx.is_empty() {
             return Err((SyntaxError::ThisOutsideClass.into(), span.clone()));
         }
@@ -770,7 +925,22 @@ impl Compiler {
     /// - globals
     ///   - `0000 OP_SET_GLOBAL`
     ///   - `0001 chunk_constant_idx`
-    fn set_variable(&mut self, name: &str, span: &Span, gc: &mut Gc) -> Result<()> {
+    /// - package globals
+    ///   - `0000 OP_GET_PACKAGE`
+    ///   - `0001 chunk_constant_idx`
+    ///   - `0002 OP_SET_PROPERTY`
+    ///   - `0003 chunk_constant_idx`
+    fn set_variable(&mut self, identifier: &Identifier, span: &Span, gc: &mut Gc) -> Result<()> {
+        let name = identifier.name.as_str();
+
+        if let Some(package) = self.foreign_package(identifier) {
+            self.get_package(package, span, gc)?;
+            let name = gc.alloc(name);
+            self.emit_u8(op::SET_PROPERTY, span);
+            self.emit_constant(name.into(), span)?;
+            return Ok(());
+        }
+
         if let Some(local_idx) = self.ctx.resolve_local(name, false, span)? {
             self.emit_u8(op::SET_LOCAL, span);
             self.emit_u8(local_idx, span);
@@ -785,6 +955,37 @@ impl Compiler {
         Ok(())
     }
 
+    /// Returns the package an [`Identifier`] is qualified with unless it's the
+    /// package currently being compiled. Identifiers qualified with the current
+    /// package resolve like any other global.
+    fn foreign_package<'a>(&self, identifier: &'a Identifier) -> Option<&'a str> {
+        let package = identifier.package.as_deref()?;
+
+        match self.package_ctx.name {
+            Some(name) if unsafe { (*name).value } == package => None,
+            _ => Some(package),
+        }
+    }
+
+    /// Push an imported [`ObjectPackage`] on to the VM's stack
+    ///
+    /// Emits the following byte code format:
+    ///
+    /// - `0000 OP_GET_PACKAGE`
+    /// - `0001 chunk_constant_idx`
+    fn get_package(&mut self, package: &str, span: &Span, gc: &mut Gc) -> Result<()> {
+        if !self.package_ctx.imports.iter().any(|import| import == package) {
+            return Err((
+                ImportError::NotImported { name: package.to_string() }.into(),
+                span.clone(),
+            ));
+        }
+
+        let package = gc.alloc(package);
+        self.emit_u8(op::GET_PACKAGE, span);
+        self.emit_constant(package.into(), span)
+    }
+
     /// Add [`Local`] variable to [`CompilerCtx`] by `name`
     ///
     /// Emits no byte ops
@@ -806,6 +1007,7 @@ impl Compiler {
             depth: self.ctx.scope_depth,
             is_initialized: false,
             is_captured: false,
+            info: None,
         };
         self.ctx
             .locals
@@ -818,11 +1020,36 @@ impl Compiler {
     ///
     /// Emits no byte ops
     fn define_local(&mut self) {
-        self.ctx
-            .locals
-            .last_mut()
-            .expect("tried to define a local without declaring it")
-            .is_initialized = true;
+        let slot = self.ctx.locals.len().saturating_sub(1);
+        let local =
+            self.ctx.locals.last_mut().expect("tried to define a local without declaring it");
+        local.is_initialized = true;
+
+        // Hidden locals (e.g. the error in a `finally` handler) and the
+        // function in slot 0 aren't shown in the debugger
+        let is_hidden = local.name.is_empty() || (slot == 0 && local.name != "this");
+        if !is_hidden {
+            let chunk = unsafe { &mut (*self.ctx.function).chunk };
+            local.info = Some(chunk.locals.len());
+            chunk.locals.push(LocalInfo {
+                name: local.name.clone(),
+                slot: slot.try_into().expect("local index overflow"),
+                start: chunk.ops.len(),
+                end: usize::MAX,
+            });
+        }
+    }
+
+    /// Remove the last [`Local`] variable from [`CompilerCtx`], ending its
+    /// scope in the debug info
+    ///
+    /// Emits no byte ops
+    fn pop_local(&mut self) {
+        let local = self.ctx.locals.pop().expect("tried to pop a local without declaring it");
+        if let Some(info) = local.info {
+            let chunk = unsafe { &mut (*self.ctx.function).chunk };
+            chunk.locals[info].end = chunk.ops.len();
+        }
     }
 
     /// A jump takes 1 byte for the instruction followed by 2 bytes for the
@@ -845,12 +1072,8 @@ impl Compiler {
         let offset =
             offset.try_into().map_err(|_| (OverflowError::JumpTooLarge.into(), span.clone()))?;
         let offset = u16::to_le_bytes(offset);
-        unsafe {
-            [
-                (*self.ctx.function).chunk.ops[offset_idx],
-                (*self.ctx.function).chunk.ops[offset_idx + 1],
-            ] = offset
-        };
+        let ops = unsafe { &mut (*self.ctx.function).chunk.ops };
+        [ops[offset_idx], ops[offset_idx + 1]] = offset;
         Ok(())
     }
 
@@ -895,7 +1118,7 @@ impl Compiler {
                 }
 
                 // Clean up compiler context
-                self.ctx.locals.pop();
+                self.pop_local();
             } else {
                 break;
             }
@@ -937,6 +1160,9 @@ pub struct CompilerCtx {
     parent: Option<Box<CompilerCtx>>,
     /// The scope currently executing
     scope_depth: usize,
+    /// The `try` statements whose body or `catch` block is being compiled,
+    /// innermost last
+    tries: Vec<TryCtx>,
 }
 
 impl CompilerCtx {
@@ -966,7 +1192,7 @@ impl CompilerCtx {
         };
 
         if let Some(local_idx) = local_idx {
-            let upvalue_idx = self.add_upvalue(local_idx, true, span)?;
+            let upvalue_idx = self.add_upvalue(name, local_idx, true, span)?;
             return Ok(Some(upvalue_idx));
         };
 
@@ -976,14 +1202,14 @@ impl CompilerCtx {
         };
 
         if let Some(upvalue_idx) = upvalue_idx {
-            let upvalue_idx = self.add_upvalue(upvalue_idx, false, span)?;
+            let upvalue_idx = self.add_upvalue(name, upvalue_idx, false, span)?;
             return Ok(Some(upvalue_idx));
         };
 
         Ok(None)
     }
 
-    fn add_upvalue(&mut self, idx: u8, is_local: bool, span: &Span) -> Result<u8> {
+    fn add_upvalue(&mut self, name: &str, idx: u8, is_local: bool, span: &Span) -> Result<u8> {
         let upvalue = Upvalue { idx, is_local };
         let upvalue_idx = match self.upvalues.iter().position(|u| u == &upvalue) {
             Some(upvalue_idx) => upvalue_idx,
@@ -994,7 +1220,8 @@ impl CompilerCtx {
                 let upvalues = self.upvalues.len();
                 unsafe {
                     (*self.function).upvalue_count =
-                        upvalues.try_into().expect("upvalue index overflow")
+                        upvalues.try_into().expect("upvalue index overflow");
+                    (*self.function).chunk.upvalues.push(name.to_string());
                 };
                 upvalues - 1
             }
@@ -1009,9 +1236,18 @@ struct ClassCtx {
     has_super: bool,
 }
 
+#[derive(Clone, Debug)]
+struct TryCtx {
+    /// The number of error handlers registered by the `try` statement
+    handlers: usize,
+    finally: Option<Rc<StmtBlock>>,
+}
+
 #[derive(Debug)]
 struct PackageCtx {
     name: Option<*mut ObjectString>,
+    /// Names of the packages imported by the program
+    imports: Vec<String>,
 }
 
 /// Local Variable
@@ -1026,6 +1262,8 @@ struct Local {
     is_initialized: bool,
     /// If the variable was captured as an upvalue of a closure
     is_captured: bool,
+    /// Index of the variable's [`LocalInfo`] in the function's chunk
+    info: Option<usize>,
 }
 
 /// Closure Variable
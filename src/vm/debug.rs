@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::vm::value::Value;
+use crate::vm::VM;
+
+/// Which file a piece of the [`VM`]'s source came from
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum SourceName {
+    /// The core Locks library
+    Lib,
+    /// Code passed to [`VM::run`]
+    Script,
+    /// An imported package's file
+    Package(PathBuf),
+}
+
+/// A position in one of the [`VM`]'s source files. Lines and columns start
+/// at 1.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Location {
+    pub source: SourceName,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A function call on the [`VM`]'s stack with its variables
+#[derive(Debug)]
+pub struct DebugFrame {
+    /// The function's name, or an empty string for the top level of a file
+    pub name: String,
+    pub location: Option<Location>,
+    /// Local variables that are in scope, in the order they were declared
+    pub locals: Vec<(String, Value)>,
+    /// Variables captured by the function's closure
+    pub upvalues: Vec<(String, Value)>,
+}
+
+/// Why a program stopped in the debugger
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+}
+
+/// How to continue after the program stops
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Step {
+    /// Run until the next breakpoint
+    Continue,
+    /// Stop at the next line, including inside called functions
+    In,
+    /// Stop at the next line in the current function or its callers
+    Over,
+    /// Stop at the next line after the current function returns
+    Out,
+}
+
+/// Interface between the [`Debugger`] and the user (e.g. a prompt)
+pub trait DebugHook {
+    /// Called when the program stops before running `location`. The [`VM`]
+    /// can be inspected with [`VM::debug_frames`].
+    fn stopped(
+        &mut self,
+        vm: &VM,
+        breakpoints: &mut Breakpoints,
+        location: &Location,
+        reason: StopReason,
+    ) -> Step;
+}
+
+/// Lines to stop at, by source file
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    lines: HashMap<SourceName, HashSet<usize>>,
+}
+
+impl Breakpoints {
+    /// Add a breakpoint. Returns `false` if it already existed.
+    pub fn add(&mut self, source: SourceName, line: usize) -> bool {
+        self.lines.entry(source).or_default().insert(line)
+    }
+
+    /// Remove a breakpoint. Returns `false` if it didn't exist.
+    pub fn remove(&mut self, source: &SourceName, line: usize) -> bool {
+        self.lines.get_mut(source).is_some_and(|lines| lines.remove(&line))
+    }
+
+    /// Remove every breakpoint in a source file
+    pub fn clear(&mut self, source: &SourceName) {
+        self.lines.remove(source);
+    }
+
+    pub fn contains(&self, source: &SourceName, line: usize) -> bool {
+        self.lines.get(source).is_some_and(|lines| lines.contains(&line))
+    }
+
+    /// Breakpoints by source file and line, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&SourceName, usize)> {
+        self.lines.iter().flat_map(|(source, lines)| lines.iter().map(move |&line| (source, line)))
+    }
+}
+
+/// Stops a running program at breakpoints and after steps
+///
+/// Set with [`VM::set_debugger`]. The program only stops at the first op of
+/// each line, and never inside the core Locks library.
+pub struct Debugger {
+    hook: Box<dyn DebugHook>,
+    pub breakpoints: Breakpoints,
+    step: Step,
+    /// Frame depth when the program last stopped
+    depth: usize,
+    /// The line of the last op that was checked in each frame, by depth
+    lines: Vec<Option<(SourceName, usize)>>,
+    /// Offsets that start each line of the [`VM`]'s source, and the length
+    /// of the source when they were found
+    line_starts: Vec<usize>,
+    indexed_len: usize,
+}
+
+impl fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Debugger")
+            .field("breakpoints", &self.breakpoints)
+            .field("step", &self.step)
+            .field("depth", &self.depth)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Debugger {
+    /// Create a debugger that starts with `step`, e.g. [`Step::In`] to stop
+    /// on the first line
+    pub fn new(hook: impl DebugHook + 'static, step: Step) -> Self {
+        Self {
+            hook: Box::new(hook),
+            breakpoints: Breakpoints::default(),
+            step,
+            depth: 0,
+            lines: Vec::new(),
+            line_starts: Vec::new(),
+            indexed_len: 0,
+        }
+    }
+
+    /// Called by the [`VM`] before each op at `offset` in its source
+    pub(crate) fn before_op(&mut self, vm: &VM, offset: usize, depth: usize) {
+        let Some(location) = self.location(vm, offset) else {
+            return;
+        };
+
+        // Only stop on the first op of each line. Returning to a line after
+        // a call doesn't count as a new line, unless stepping out of the call.
+        self.lines.resize(depth + 1, None);
+        let line = Some((location.source.clone(), location.line));
+        let is_step_out = self.step == Step::Out && depth < self.depth;
+        if self.lines[depth] == line && !is_step_out {
+            return;
+        }
+        self.lines[depth] = line;
+
+        let is_breakpoint = self.breakpoints.contains(&location.source, location.line);
+
+        let reason = match self.step {
+            _ if is_breakpoint => StopReason::Breakpoint,
+            Step::In => StopReason::Step,
+            Step::Over if depth <= self.depth => StopReason::Step,
+            Step::Out if depth < self.depth => StopReason::Step,
+            _ => return,
+        };
+
+        self.depth = depth;
+        self.step = self.hook.stopped(vm, &mut self.breakpoints, &location, reason);
+    }
+
+    /// Find the location of an offset in the [`VM`]'s source. Returns [`None`]
+    /// for the core Locks library.
+    fn location(&mut self, vm: &VM, offset: usize) -> Option<Location> {
+        let (start, source) = vm.source_at(offset)?;
+        if *source == SourceName::Lib {
+            return None;
+        }
+
+        // Source is added when packages are imported
+        if self.indexed_len != vm.source.len() {
+            let newlines = vm.source.match_indices('\n').map(|(idx, _)| idx + 1);
+            self.line_starts = [0].into_iter().chain(newlines).collect();
+            self.indexed_len = vm.source.len();
+        }
+
+        let line_idx = self.line_starts.partition_point(|&line_start| line_start <= offset) - 1;
+        let first_line = self.line_starts.partition_point(|&line_start| line_start <= start) - 1;
+
+        Some(Location {
+            source: source.clone(),
+            line: line_idx - first_line + 1,
+            column: offset - self.line_starts[line_idx] + 1,
+        })
+    }
+}
+
+/// Find the location of an offset in the [`VM`]'s source without an index
+/// of its lines
+pub(crate) fn location(vm: &VM, offset: usize) -> Option<Location> {
+    let (start, source) = vm.source_at(offset)?;
+    let before = &vm.source[start..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |idx| idx + 1) + 1;
+    Some(Location { source: source.clone(), line, column })
+}
@@ -1,11 +1,14 @@
+use std::collections::HashSet;
 use std::hash::BuildHasherDefault;
 use std::mem;
+use std::time::Duration;
 
 use hashbrown::hash_map::RawEntryMut;
 use hashbrown::HashMap;
 use rustc_hash::FxHasher;
 
-use crate::vm::object::{Object, ObjectString, ObjectType};
+use crate::vm::heap;
+use crate::vm::object::{Native, Object, ObjectString, ObjectType};
 use crate::vm::value::Value;
 
 #[derive(Debug, Default)]
@@ -14,6 +17,56 @@ pub struct Gc {
     strings: HashMap<String, *mut ObjectString, BuildHasherDefault<FxHasher>>,
     objects: Vec<Object>,
     gray_objects: Vec<Object>,
+    /// Objects that a collection hasn't swept yet. The marked ones survive
+    /// it and are moved back to `objects`.
+    sweeping: Vec<Object>,
+    /// An estimate of the bytes used by the objects in `sweeping`, which
+    /// aren't counted in `allocated_bytes` until they're swept
+    sweeping_bytes: usize,
+    /// An estimate of the bytes used by the objects the collector owns
+    ///
+    /// Objects are counted at the size they're allocated with. Growth after
+    /// that (e.g. a list's values) is counted by [`Gc::grow`] or when the
+    /// objects are recounted as they're swept.
+    allocated_bytes: usize,
+    /// Whether an incremental collection is marking objects. While it is, new
+    /// objects & values written into objects are marked so the collection
+    /// doesn't miss them.
+    marking: bool,
+    pauses: PauseStats,
+    collections: usize,
+    objects_freed: usize,
+    bytes_freed: usize,
+}
+
+/// What the collector has done and what's on the heap, from [`Gc::stats`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GcStats {
+    /// Collections that have finished
+    pub collections: usize,
+    /// Objects on the heap by type, including interned strings. Types with no
+    /// objects are left out.
+    pub objects: Vec<(ObjectType, usize)>,
+    pub allocated_bytes: usize,
+    pub objects_freed: usize,
+    pub bytes_freed: usize,
+    pub pauses: PauseStats,
+}
+
+/// How long the program was paused to collect garbage
+///
+/// With the `gc-incremental` feature every step of a collection is a pause.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PauseStats {
+    pub count: usize,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl PauseStats {
+    pub fn mean(&self) -> Duration {
+        self.total.checked_div(self.count as u32).unwrap_or_default()
+    }
 }
 
 impl Gc {
@@ -21,115 +74,326 @@ impl Gc {
         object.alloc(self)
     }
 
+    pub fn allocated_bytes(&self) -> usize {
+        self.allocated_bytes.saturating_add(self.sweeping_bytes)
+    }
+
+    /// Count bytes that an object grew by since it was allocated
+    pub fn grow(&mut self, bytes: usize) {
+        self.allocated_bytes = self.allocated_bytes.saturating_add(bytes);
+    }
+
+    pub fn pause_stats(&self) -> PauseStats {
+        self.pauses
+    }
+
+    pub fn stats(&self) -> GcStats {
+        let mut counts = [0; ObjectType::ALL.len()];
+        for object in self.objects.iter().chain(&self.sweeping) {
+            counts[object.type_() as usize] += 1;
+        }
+        counts[ObjectType::String as usize] += self.strings.len();
+
+        let objects =
+            ObjectType::ALL.into_iter().zip(counts).filter(|&(_, count)| count > 0).collect();
+
+        GcStats {
+            collections: self.collections,
+            objects,
+            allocated_bytes: self.allocated_bytes(),
+            objects_freed: self.objects_freed,
+            bytes_freed: self.bytes_freed,
+            pauses: self.pauses,
+        }
+    }
+
+    /// Every object the collector owns, including interned strings and
+    /// garbage that hasn't been swept yet
+    pub(super) fn heap(&self) -> impl Iterator<Item = Object> + '_ {
+        self.objects
+            .iter()
+            .chain(&self.sweeping)
+            .copied()
+            .chain(self.strings.values().map(|&string| string.into()))
+    }
+
+    pub fn record_pause(&mut self, pause: Duration) {
+        self.pauses.count += 1;
+        self.pauses.total += pause;
+        self.pauses.max = self.pauses.max.max(pause);
+    }
+
+    pub fn is_marking(&self) -> bool {
+        cfg!(feature = "gc-incremental") && self.marking
+    }
+
+    /// Whether a collection has marked objects but not swept all of them
+    pub fn is_sweeping(&self) -> bool {
+        !self.sweeping.is_empty()
+    }
+
+    /// Start an incremental collection. The roots should be marked next.
+    pub fn start_marking(&mut self) {
+        self.marking = true;
+    }
+
+    /// Mark a value that's been written into an object while an incremental
+    /// collection is marking
+    ///
+    /// Without this, storing an unmarked value in an object that's already
+    /// been traced would hide it from the collection and it'd be freed.
+    pub fn write_barrier(&mut self, value: impl GcMark) {
+        if self.is_marking() {
+            self.mark(value);
+        }
+    }
+
+    /// Find an interned string without allocating it
+    pub fn find_string(&self, value: &str) -> Option<*mut ObjectString> {
+        self.strings.get(value).copied()
+    }
+
     pub fn mark(&mut self, object: impl GcMark) {
         object.mark(self);
     }
 
+    /// Trace every marked object
     pub fn trace(&mut self) {
         while let Some(object) = self.gray_objects.pop() {
-            if cfg!(feature = "gc-trace") {
-                eprintln!("blacken {}: {object}", object.type_());
+            self.blacken(object);
+        }
+    }
+
+    /// Trace up to `budget` marked objects, returning whether there are none
+    /// left to trace
+    pub fn trace_step(&mut self, budget: usize) -> bool {
+        for _ in 0..budget {
+            match self.gray_objects.pop() {
+                Some(object) => self.blacken(object),
+                None => return true,
+            }
+        }
+        self.gray_objects.is_empty()
+    }
+
+    /// Mark the objects that an object references
+    fn blacken(&mut self, object: Object) {
+        if cfg!(feature = "gc-trace") {
+            eprintln!("blacken {}: {object}", object.type_());
+        }
+        match unsafe { (*object.common).type_ } {
+            ObjectType::BoundMethod => {
+                let method = unsafe { object.bound_method };
+                self.mark(unsafe { (*method).this });
+                self.mark(unsafe { (*method).closure });
             }
-            match unsafe { (*object.common).type_ } {
-                ObjectType::BoundMethod => {
-                    let method = unsafe { object.bound_method };
-                    self.mark(unsafe { (*method).this });
-                    self.mark(unsafe { (*method).closure });
+            ObjectType::Class => {
+                let class = unsafe { object.class };
+                self.mark(unsafe { (*class).name });
+
+                // TODO: Is this needed?
+                if let Some(super_) = unsafe { (*class).super_ } {
+                    self.mark(super_);
                 }
-                ObjectType::Class => {
-                    let class = unsafe { object.class };
-                    self.mark(unsafe { (*class).name });
 
-                    // TODO: Is this needed?
-                    if let Some(super_) = unsafe { (*class).super_ } {
-                        self.mark(super_);
-                    }
+                for (&name, &method) in unsafe { &(*class).methods } {
+                    self.mark(name);
+                    self.mark(method);
+                }
 
-                    for (&name, &method) in unsafe { &(*class).methods } {
-                        self.mark(name);
-                        self.mark(method);
-                    }
+                for (&name, &field) in unsafe { &(*class).fields } {
+                    self.mark(name);
+                    self.mark(field);
+                }
 
-                    for (&name, &static_field) in unsafe { &(*class).static_fields } {
-                        self.mark(name);
-                        self.mark(static_field);
-                    }
+                for (&name, &static_field) in unsafe { &(*class).static_fields } {
+                    self.mark(name);
+                    self.mark(static_field);
+                }
 
-                    for (&name, &method) in unsafe { &(*class).static_methods } {
-                        self.mark(name);
-                        self.mark(method);
-                    }
+                for (&name, &method) in unsafe { &(*class).static_methods } {
+                    self.mark(name);
+                    self.mark(method);
                 }
-                ObjectType::Closure => {
-                    let closure = unsafe { object.closure };
-                    self.mark(unsafe { (*closure).function });
-                    for &upvalue in unsafe { &(*closure).upvalues } {
-                        self.mark(upvalue);
-                    }
+            }
+            ObjectType::Closure => {
+                let closure = unsafe { object.closure };
+                self.mark(unsafe { (*closure).function });
+                for &upvalue in unsafe { &(*closure).upvalues } {
+                    self.mark(upvalue);
                 }
-                ObjectType::Function => {
-                    let function = unsafe { object.function };
-                    self.mark(unsafe { (*function).name });
-                    for constant in unsafe { &(*function).chunk.constants } {
-                        if constant.is_object() {
-                            self.mark(constant.as_object());
-                        }
+            }
+            ObjectType::Function => {
+                let function = unsafe { object.function };
+                self.mark(unsafe { (*function).name });
+                for constant in unsafe { &(*function).chunk.constants } {
+                    if constant.is_object() {
+                        self.mark(constant.as_object());
                     }
                 }
-                ObjectType::Instance => {
-                    self.mark(unsafe { (*object.instance).class });
-                    for (&name, &value) in unsafe { (*object.instance).fields.iter() } {
-                        self.mark(name);
-                        self.mark(value);
-                    }
+            }
+            ObjectType::Instance => {
+                self.mark(unsafe { (*object.instance).class });
+                for (&name, &value) in unsafe { (*object.instance).fields.iter() } {
+                    self.mark(name);
+                    self.mark(value);
                 }
-                ObjectType::Native => {}
-                ObjectType::String => {}
-                ObjectType::List => {
-                    let list = unsafe { object.list };
-                    let values = unsafe { &(*list).values };
-
-                    for &value in values {
-                        self.mark(value);
-                    }
+            }
+            ObjectType::Native => match unsafe { (*object.native).native } {
+                Native::Host(host) => self.mark(host.name),
+                Native::StringMethod(_, receiver) => self.mark(receiver),
+                _ => {}
+            },
+            ObjectType::String => {}
+            ObjectType::List => {
+                let list = unsafe { object.list };
+                let values = unsafe { &(*list).values };
+
+                for &value in values {
+                    self.mark(value);
                 }
-                ObjectType::Package => {
-                    let package = unsafe { object.package };
-                    self.mark(unsafe { (*package).name });
+            }
+            ObjectType::Map => {
+                let map = unsafe { object.map };
+
+                for &(key, value) in unsafe { &(*map).entries } {
+                    self.mark(key);
+                    self.mark(value);
                 }
-                ObjectType::Upvalue => {
-                    let upvalue = unsafe { object.upvalue };
-                    self.mark(unsafe { (*upvalue).closed });
+            }
+            ObjectType::Package => {
+                let package = unsafe { object.package };
+                self.mark(unsafe { (*package).name });
+                for (&name, &value) in unsafe { &(*package).globals } {
+                    self.mark(name);
+                    self.mark(value);
                 }
             }
+            ObjectType::Upvalue => {
+                let upvalue = unsafe { object.upvalue };
+                self.mark(unsafe { (*upvalue).closed });
+            }
         }
     }
 
+    /// Free unmarked objects and recount the bytes used by the rest
+    ///
+    /// This ends an incremental collection.
     pub fn sweep(&mut self) {
-        for idx in (0..self.objects.len()).rev() {
-            let object = *unsafe { self.objects.get_unchecked(idx) };
-            if !mem::take(unsafe { &mut (*object.common).is_marked }) {
-                self.objects.swap_remove(idx);
-                object.free();
-            }
+        self.start_sweep();
+        self.sweep_step(usize::MAX);
+    }
+
+    /// Finish sweeping if a collection has started to
+    pub fn finish_sweep(&mut self) {
+        if self.is_sweeping() {
+            self.sweep_step(usize::MAX);
         }
+    }
+
+    /// Stop marking and sweep the interned strings, leaving the other
+    /// objects to [`Gc::sweep_step`]
+    ///
+    /// Strings are swept all at once since an unmarked one could be found
+    /// and reused by [`Gc::alloc`] before it's swept. Objects allocated
+    /// after this aren't marked, so they survive the sweep.
+    pub fn start_sweep(&mut self) {
+        self.marking = false;
+        let mut allocated_bytes = 0;
+        let mut string_bytes = 0;
 
         self.strings.drain_filter(|_, &mut string| {
+            let size = Object::from(string).size();
+            string_bytes += size;
             if mem::take(unsafe { &mut (*string).common.is_marked }) {
+                allocated_bytes += size;
                 false
             } else {
+                self.objects_freed += 1;
+                self.bytes_freed += size;
                 unsafe {
                     let _ = Box::from_raw(string);
                 };
                 true
             }
         });
+
+        self.sweeping = mem::take(&mut self.objects);
+        self.sweeping_bytes = self.allocated_bytes.saturating_sub(string_bytes);
+        self.allocated_bytes = allocated_bytes;
+    }
+
+    /// Sweep up to `budget` objects, returning whether the collection is
+    /// finished
+    pub fn sweep_step(&mut self, budget: usize) -> bool {
+        for _ in 0..budget {
+            let Some(object) = self.sweeping.pop() else {
+                break;
+            };
+            let size = object.size();
+            self.sweeping_bytes = self.sweeping_bytes.saturating_sub(size);
+            if mem::take(unsafe { &mut (*object.common).is_marked }) {
+                self.allocated_bytes += size;
+                self.objects.push(object);
+            } else {
+                self.objects_freed += 1;
+                self.bytes_freed += size;
+                object.free();
+            }
+        }
+
+        if self.sweeping.is_empty() {
+            self.sweeping_bytes = 0;
+            self.collections += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Check that no object reachable from `roots` references an object
+    /// that's been freed, panicking if one does
+    ///
+    /// This finds references separately from [`Gc::trace`], so an object it
+    /// misses is caught here after its memory is freed.
+    pub fn verify(&self, roots: impl IntoIterator<Item = Value>) {
+        let live = self.heap().map(|object| unsafe { object.common }).collect::<HashSet<_>>();
+
+        // Values to check and the object that references them
+        let mut pending =
+            roots.into_iter().map(|root| (root, None)).collect::<Vec<(_, Option<Object>)>>();
+        let mut seen = HashSet::new();
+
+        while let Some((value, from)) = pending.pop() {
+            if !value.is_object() {
+                continue;
+            }
+            let object = value.as_object();
+            let ptr = unsafe { object.common };
+
+            if !live.contains(&ptr) {
+                match from {
+                    Some(from) => panic!(
+                        "gc-verify: {} at {:p} references freed object {ptr:p}",
+                        from.type_(),
+                        unsafe { from.common }
+                    ),
+                    None => panic!("gc-verify: a root references freed object {ptr:p}"),
+                }
+            }
+
+            if seen.insert(ptr) {
+                pending.extend(
+                    heap::edges(object).into_iter().map(|(_, value)| (value, Some(object))),
+                );
+            }
+        }
     }
 }
 
 impl Drop for Gc {
     fn drop(&mut self) {
-        for object in &self.objects {
+        for object in self.objects.iter().chain(&self.sweeping) {
             object.free();
         }
         for &string in self.strings.values() {
@@ -142,6 +406,8 @@ impl Drop for Gc {
 
 pub trait GcAlloc<T> {
     fn alloc(self, gc: &mut Gc) -> T;
+    /// The bytes that allocating this would add to the heap
+    fn size(&self, gc: &Gc) -> usize;
 }
 
 /// Handle allocating objects
@@ -157,9 +423,17 @@ where
             eprintln!("allocate {}: {object}", object.type_());
         }
 
+        gc.allocated_bytes += object.size();
         gc.objects.push(object);
+        // Objects created during an incremental collection survive it
+        gc.write_barrier(object);
         object_ptr
     }
+
+    fn size(&self, _: &Gc) -> usize {
+        let object: Object = (self as *const T as *mut T).into();
+        object.size()
+    }
 }
 
 /// Handle allocating string objects differently
@@ -168,7 +442,7 @@ where
     S: AsRef<str> + Into<String>,
 {
     fn alloc(self, gc: &mut Gc) -> *mut ObjectString {
-        match gc.strings.raw_entry_mut().from_key(self.as_ref()) {
+        let string = match gc.strings.raw_entry_mut().from_key(self.as_ref()) {
             RawEntryMut::Occupied(entry) => *entry.get(),
             RawEntryMut::Vacant(entry) => {
                 let string = self.into();
@@ -176,11 +450,26 @@ where
                     eprintln!("allocate string: {string}");
                 }
                 let object = Box::into_raw(Box::new(ObjectString::new(unsafe {
-                    mem::transmute(string.as_str())
+                    mem::transmute::<&str, &'static str>(string.as_str())
                 })));
                 entry.insert(string, object);
+                gc.allocated_bytes += Object::from(object).size();
                 object
             }
+        };
+
+        // Interned strings may be reused after being missed by an incremental
+        // collection's marking
+        gc.write_barrier(string);
+        string
+    }
+
+    fn size(&self, gc: &Gc) -> usize {
+        // Interned strings are reused
+        if gc.strings.contains_key(self.as_ref()) {
+            0
+        } else {
+            mem::size_of::<ObjectString>() + self.as_ref().len()
         }
     }
 }
@@ -0,0 +1,465 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::hash::BuildHasherDefault;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashMap;
+use rustc_hash::FxHasher;
+
+use crate::vm::object::{Native, Object, ObjectCommon, ObjectString, ObjectType};
+use crate::vm::value::Value;
+
+/// What a reference from an object (or from the [`VM`](crate::vm::VM)) is
+/// called
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Edge {
+    /// A named value, like a field, a method or a global
+    Property(*mut ObjectString),
+    /// A list value or a stack slot
+    Element(usize),
+    /// A reference the VM uses to run the program, like an instance's class
+    Internal(&'static str),
+    /// A reference that isn't interesting on its own, like a field's name
+    Hidden,
+}
+
+/// Every reference `object` holds, with what it's called
+///
+/// This finds references separately from [`Gc::trace`](super::Gc::trace), so
+/// `gc-verify` can catch a reference that tracing misses.
+pub(super) fn edges(object: Object) -> Vec<(Edge, Value)> {
+    let mut edges = Vec::new();
+    unsafe {
+        match object.type_() {
+            ObjectType::BoundMethod => {
+                let method = &*object.bound_method;
+                edges.push((Edge::Internal("this"), method.this.into()));
+                edges.push((Edge::Internal("method"), method.closure.into()));
+            }
+            ObjectType::Class => {
+                let class = &*object.class;
+                edges.push((Edge::Internal("name"), class.name.into()));
+                if let Some(super_) = class.super_ {
+                    edges.push((Edge::Internal("super"), super_.into()));
+                }
+                edges.extend(properties(&class.methods));
+                edges.extend(properties(&class.fields));
+                edges.extend(properties(&class.static_fields));
+                edges.extend(properties(&class.static_methods));
+            }
+            ObjectType::Closure => {
+                let closure = &*object.closure;
+                edges.push((Edge::Internal("function"), closure.function.into()));
+                edges.extend(
+                    closure
+                        .upvalues
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, &upvalue)| (Edge::Element(idx), Value::from(upvalue))),
+                );
+            }
+            ObjectType::Function => {
+                let function = &*object.function;
+                edges.push((Edge::Internal("name"), function.name.into()));
+                edges.extend(
+                    function.chunk.constants.iter().map(|&constant| (Edge::Hidden, constant)),
+                );
+            }
+            ObjectType::Instance => {
+                let instance = &*object.instance;
+                edges.push((Edge::Internal("class"), instance.class.into()));
+                edges.extend(properties(&instance.fields));
+            }
+            ObjectType::Native => match (*object.native).native {
+                Native::Host(host) => edges.push((Edge::Internal("name"), host.name.into())),
+                Native::StringMethod(_, receiver) => {
+                    edges.push((Edge::Internal("this"), receiver.into()));
+                }
+                _ => {}
+            },
+            ObjectType::String => {}
+            ObjectType::List => edges.extend(
+                (*object.list)
+                    .values
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, &value)| (Edge::Element(idx), value)),
+            ),
+            ObjectType::Map => {
+                for (idx, &(key, value)) in (*object.map).entries.iter().enumerate() {
+                    let edge = if key.is_object() && key.as_object().type_() == ObjectType::String {
+                        Edge::Property(key.as_object().string)
+                    } else {
+                        Edge::Element(idx)
+                    };
+                    edges.extend([(Edge::Hidden, key), (edge, value)]);
+                }
+            }
+            ObjectType::Package => {
+                let package = &*object.package;
+                edges.push((Edge::Internal("name"), package.name.into()));
+                edges.extend(properties(&package.globals));
+            }
+            ObjectType::Upvalue => edges.push((Edge::Internal("value"), (*object.upvalue).closed)),
+        }
+    }
+    edges
+}
+
+/// References to the names & values in a map of names to values
+pub(super) fn properties<V: Copy + Into<Value>>(
+    map: &HashMap<*mut ObjectString, V, BuildHasherDefault<FxHasher>>,
+) -> impl Iterator<Item = (Edge, Value)> + '_ {
+    map.iter().flat_map(|(&name, &value)| {
+        [(Edge::Hidden, name.into()), (Edge::Property(name), value.into())]
+    })
+}
+
+/// The objects on a [`VM`](crate::vm::VM)'s heap and the references between
+/// them, from [`VM::heap_snapshot`](crate::vm::VM::heap_snapshot)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeapSnapshot {
+    /// References held by the VM itself, like globals & values on the stack
+    pub roots: Vec<HeapEdge>,
+    pub nodes: Vec<HeapNode>,
+}
+
+/// An object in a [`HeapSnapshot`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeapNode {
+    pub type_: ObjectType,
+    /// A string's value, the name of a class, function, package or native,
+    /// or the class of an instance. Lists, maps & upvalues are named after
+    /// their type.
+    pub name: String,
+    /// Bytes used by the object, not counting the objects it references
+    pub self_size: usize,
+    pub edges: Vec<HeapEdge>,
+}
+
+/// A reference to [`HeapSnapshot::nodes`]`[to]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeapEdge {
+    pub kind: HeapEdgeKind,
+    pub to: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeapEdgeKind {
+    Property(String),
+    Element(usize),
+    Internal(&'static str),
+    Hidden,
+}
+
+impl HeapSnapshot {
+    pub(super) fn new(objects: impl Iterator<Item = Object>, roots: &[(Edge, Value)]) -> Self {
+        let objects = objects.collect::<Vec<_>>();
+        let indexes = objects
+            .iter()
+            .enumerate()
+            .map(|(idx, object)| (unsafe { object.common }, idx))
+            .collect::<HashMap<*mut ObjectCommon, usize, BuildHasherDefault<FxHasher>>>();
+
+        // Values that aren't objects aren't on the heap, so only references
+        // to objects become edges
+        let heap_edges = |edges: &[(Edge, Value)]| {
+            edges
+                .iter()
+                .filter(|(_, value)| value.is_object())
+                .filter_map(|&(edge, value)| {
+                    let to = *indexes.get(&unsafe { value.as_object().common })?;
+                    let kind = match edge {
+                        Edge::Property(name) => {
+                            HeapEdgeKind::Property(unsafe { (*name).value }.to_string())
+                        }
+                        Edge::Element(idx) => HeapEdgeKind::Element(idx),
+                        Edge::Internal(name) => HeapEdgeKind::Internal(name),
+                        Edge::Hidden => HeapEdgeKind::Hidden,
+                    };
+                    Some(HeapEdge { kind, to })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let nodes = objects
+            .iter()
+            .map(|&object| HeapNode {
+                type_: object.type_(),
+                name: name(object),
+                self_size: object.size(),
+                edges: heap_edges(&edges(object)),
+            })
+            .collect();
+
+        Self { roots: heap_edges(roots), nodes }
+    }
+
+    /// `path` with a number before its extension, e.g. `out.1.json`
+    pub(super) fn numbered_path(path: &Path, number: usize) -> PathBuf {
+        let mut name = path.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!(".{number}"));
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        path.with_file_name(name)
+    }
+
+    /// Write the snapshot to a file, in the Chrome DevTools heap snapshot
+    /// format if its extension is `.heapsnapshot` and as JSON otherwise
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let writer = &mut BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|extension| extension == "heapsnapshot") {
+            self.write_chrome(writer)?;
+        } else {
+            self.write_json(writer)?;
+        }
+        writer.flush()
+    }
+
+    /// Write the snapshot as JSON
+    ///
+    /// ```json
+    /// {
+    ///   "roots": [{ "kind": "property", "name": "node", "to": 0 }],
+    ///   "nodes": [
+    ///     {
+    ///       "type": "Instance",
+    ///       "name": "Node",
+    ///       "self_size": 64,
+    ///       "edges": [{ "kind": "internal", "name": "class", "to": 1 }]
+    ///     }
+    ///   ]
+    /// }
+    /// ```
+    pub fn write_json(&self, writer: &mut impl Write) -> io::Result<()> {
+        fn write_edges(writer: &mut impl Write, edges: &[HeapEdge]) -> io::Result<()> {
+            write!(writer, "[")?;
+            for (idx, edge) in edges.iter().enumerate() {
+                let separator = if idx == 0 { "" } else { "," };
+                let to = edge.to;
+                match &edge.kind {
+                    HeapEdgeKind::Property(name) => write!(
+                        writer,
+                        r#"{separator}{{"kind":"property","name":{},"to":{to}}}"#,
+                        json_string(name)
+                    )?,
+                    HeapEdgeKind::Element(index) => write!(
+                        writer,
+                        r#"{separator}{{"kind":"element","index":{index},"to":{to}}}"#
+                    )?,
+                    HeapEdgeKind::Internal(name) => write!(
+                        writer,
+                        r#"{separator}{{"kind":"internal","name":{},"to":{to}}}"#,
+                        json_string(name)
+                    )?,
+                    HeapEdgeKind::Hidden => {
+                        write!(writer, r#"{separator}{{"kind":"hidden","to":{to}}}"#)?;
+                    }
+                }
+            }
+            write!(writer, "]")
+        }
+
+        write!(writer, r#"{{"roots":"#)?;
+        write_edges(writer, &self.roots)?;
+        writeln!(writer, r#","nodes":["#)?;
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let separator = if idx == 0 { "" } else { ",\n" };
+            write!(
+                writer,
+                r#"{separator}{{"type":"{:?}","name":{},"self_size":{},"edges":"#,
+                node.type_,
+                json_string(&node.name),
+                node.self_size
+            )?;
+            write_edges(writer, &node.edges)?;
+            write!(writer, "}}")?;
+        }
+        writeln!(writer, "\n]}}")
+    }
+
+    /// Write the snapshot in the format Chrome DevTools' Memory panel loads
+    ///
+    /// The first node is a synthetic root that references
+    /// [`HeapSnapshot::roots`]. The objects follow in the same order as
+    /// [`HeapSnapshot::nodes`].
+    pub fn write_chrome(&self, writer: &mut impl Write) -> io::Result<()> {
+        const NODE_FIELDS: usize = 6;
+        const NODE_SYNTHETIC: usize = 9;
+        const EDGE_ELEMENT: usize = 1;
+        const EDGE_PROPERTY: usize = 2;
+        const EDGE_INTERNAL: usize = 3;
+        const EDGE_HIDDEN: usize = 4;
+
+        let mut strings = Strings::default();
+        let mut nodes = String::new();
+        let mut edges = String::new();
+
+        let mut write_node = |index: usize,
+                              type_: usize,
+                              name: &str,
+                              self_size: usize,
+                              node_edges: &[HeapEdge]| {
+            let separator = if index == 0 { "" } else { ",\n" };
+            let name = strings.index(name);
+            // Ids are odd like V8's, which uses even ids for other things
+            let id = index * 2 + 1;
+            let _ =
+                write!(nodes, "{separator}{type_},{name},{id},{self_size},{},0", node_edges.len());
+
+            for (idx, edge) in node_edges.iter().enumerate() {
+                let (type_, name_or_index) = match &edge.kind {
+                    HeapEdgeKind::Property(name) => (EDGE_PROPERTY, strings.index(name)),
+                    HeapEdgeKind::Element(index) => (EDGE_ELEMENT, *index),
+                    HeapEdgeKind::Internal(name) => (EDGE_INTERNAL, strings.index(name)),
+                    HeapEdgeKind::Hidden => (EDGE_HIDDEN, idx),
+                };
+                let separator = if edges.is_empty() { "" } else { ",\n" };
+                // Nodes are referenced by the offset of their first
+                // field, after the synthetic root
+                let to_node = (edge.to + 1) * NODE_FIELDS;
+                let _ = write!(edges, "{separator}{type_},{name_or_index},{to_node}");
+            }
+        };
+
+        write_node(0, NODE_SYNTHETIC, "(roots)", 0, &self.roots);
+        for (idx, node) in self.nodes.iter().enumerate() {
+            write_node(
+                idx + 1,
+                chrome_node_type(node.type_),
+                &node.name,
+                node.self_size,
+                &node.edges,
+            );
+        }
+
+        let edge_count =
+            self.roots.len() + self.nodes.iter().map(|node| node.edges.len()).sum::<usize>();
+        writeln!(writer, "{{")?;
+        writeln!(writer, r#""snapshot":{{"meta":{{"#)?;
+        writeln!(
+            writer,
+            r#""node_fields":["type","name","id","self_size","edge_count","trace_node_id"],"#
+        )?;
+        writeln!(
+            writer,
+            r#""node_types":[["hidden","array","string","object","code","closure","regexp","number","native","synthetic","concatenated string","sliced string","symbol","bigint"],"string","number","number","number","number"],"#
+        )?;
+        writeln!(writer, r#""edge_fields":["type","name_or_index","to_node"],"#)?;
+        writeln!(
+            writer,
+            r#""edge_types":[["context","element","property","internal","hidden","shortcut","weak"],"string_or_number","node"],"#
+        )?;
+        writeln!(
+            writer,
+            r#""trace_function_info_fields":["function_id","name","script_name","script_id","line","column"],"#
+        )?;
+        writeln!(
+            writer,
+            r#""trace_node_fields":["id","function_info_index","count","size","children"],"#
+        )?;
+        writeln!(writer, r#""sample_fields":["timestamp_us","last_assigned_id"],"#)?;
+        writeln!(writer, r#""location_fields":["object_index","script_id","line","column"]}},"#)?;
+        writeln!(
+            writer,
+            r#""node_count":{},"edge_count":{edge_count},"trace_function_count":0}},"#,
+            self.nodes.len() + 1
+        )?;
+        writeln!(writer, r#""nodes":[{nodes}],"#)?;
+        writeln!(writer, r#""edges":[{edges}],"#)?;
+        writeln!(
+            writer,
+            r#""trace_function_infos":[],"trace_tree":[],"samples":[],"locations":[],"#
+        )?;
+        write!(writer, r#""strings":["#)?;
+        for (idx, string) in strings.strings.iter().enumerate() {
+            let separator = if idx == 0 { "" } else { ",\n" };
+            write!(writer, "{separator}{}", json_string(string))?;
+        }
+        writeln!(writer, "]}}")
+    }
+}
+
+/// What a snapshot calls an object
+fn name(object: Object) -> String {
+    unsafe {
+        match object.type_() {
+            ObjectType::BoundMethod => (*(*(*(*object.bound_method).closure).function).name).value,
+            ObjectType::Class => (*(*object.class).name).value,
+            ObjectType::Closure => (*(*(*object.closure).function).name).value,
+            ObjectType::Function => (*(*object.function).name).value,
+            ObjectType::Instance => (*(*(*object.instance).class).name).value,
+            ObjectType::Native => return (*object.native).native.to_string(),
+            ObjectType::String => (*object.string).value,
+            ObjectType::Package => (*(*object.package).name).value,
+            ObjectType::List | ObjectType::Map | ObjectType::Upvalue => {
+                return object.type_().to_string();
+            }
+        }
+    }
+    .to_string()
+}
+
+fn chrome_node_type(type_: ObjectType) -> usize {
+    const HIDDEN: usize = 0;
+    const ARRAY: usize = 1;
+    const STRING: usize = 2;
+    const OBJECT: usize = 3;
+    const CODE: usize = 4;
+    const CLOSURE: usize = 5;
+    const NATIVE: usize = 8;
+
+    match type_ {
+        ObjectType::BoundMethod | ObjectType::Closure => CLOSURE,
+        ObjectType::Class | ObjectType::Instance | ObjectType::Map | ObjectType::Package => OBJECT,
+        ObjectType::Function => CODE,
+        ObjectType::Native => NATIVE,
+        ObjectType::String => STRING,
+        ObjectType::List => ARRAY,
+        ObjectType::Upvalue => HIDDEN,
+    }
+}
+
+/// The string table of a Chrome heap snapshot, which names & edges refer
+/// to by index
+#[derive(Default)]
+struct Strings {
+    strings: Vec<String>,
+    indexes: HashMap<String, usize, BuildHasherDefault<FxHasher>>,
+}
+
+impl Strings {
+    fn index(&mut self, string: &str) -> usize {
+        if let Some(&idx) = self.indexes.get(string) {
+            return idx;
+        }
+        let idx = self.strings.len();
+        self.strings.push(string.to_string());
+        self.indexes.insert(string.to_string(), idx);
+        idx
+    }
+}
+
+/// Quote & escape text as a JSON string
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,229 @@
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+
+use crate::error::MemoryError;
+use crate::vm::object::{ObjectList, ObjectMap, ObjectType};
+use crate::vm::value::Value;
+use crate::vm::VM;
+
+/// An owned copy of a Locks value that can be used safely outside the [`VM`]
+///
+/// Strings, lists and maps are copied out of the garbage collected heap.
+/// Every other object (e.g. functions, classes, instances) is kept as an
+/// [`ObjectHandle`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostValue {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    List(Vec<HostValue>),
+    /// Key value pairs in insertion order
+    Map(Vec<(HostValue, HostValue)>),
+    Object(ObjectHandle),
+}
+
+/// A reference to a Locks object that the [`VM`] won't collect while the
+/// handle, or a clone of it, exists
+///
+/// Handles can only be passed back to the [`VM`] they came from.
+pub struct ObjectHandle {
+    idx: usize,
+    handles: Rc<RefCell<Handles>>,
+}
+
+impl ObjectHandle {
+    /// The object, or [`None`] if the handle is from another [`VM`]
+    fn value(&self, handles: &Rc<RefCell<Handles>>) -> Option<Value> {
+        Rc::ptr_eq(&self.handles, handles).then(|| self.handles.borrow().slots[self.idx].0)
+    }
+}
+
+impl Clone for ObjectHandle {
+    fn clone(&self) -> Self {
+        self.handles.borrow_mut().slots[self.idx].1 += 1;
+        Self { idx: self.idx, handles: Rc::clone(&self.handles) }
+    }
+}
+
+impl Drop for ObjectHandle {
+    fn drop(&mut self) {
+        let mut handles = self.handles.borrow_mut();
+        let slot = &mut handles.slots[self.idx];
+        slot.1 -= 1;
+        if slot.1 == 0 {
+            slot.0 = Value::NIL;
+            handles.free.push(self.idx);
+        }
+    }
+}
+
+/// Handles to the same object are equal
+impl PartialEq for ObjectHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.handles, &other.handles)
+            && self.value(&self.handles) == other.value(&other.handles)
+    }
+}
+
+/// The object isn't shown because the [`VM`] may have been dropped
+impl Debug for ObjectHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ObjectHandle").field(&self.idx).finish()
+    }
+}
+
+/// A [`VM`]'s objects that have an [`ObjectHandle`], which are roots for the
+/// garbage collector
+#[derive(Debug, Default)]
+pub(crate) struct Handles {
+    /// Each handle's object and how many clones of the handle exist, or nil
+    /// and `0` once they've all been dropped
+    slots: Vec<(Value, usize)>,
+    /// Slots that can be reused
+    free: Vec<usize>,
+}
+
+impl Handles {
+    /// The objects that have handles
+    pub(crate) fn values(&self) -> impl Iterator<Item = Value> + '_ {
+        self.slots.iter().filter(|(_, count)| *count > 0).map(|&(value, _)| value)
+    }
+}
+
+impl HostValue {
+    /// Copy a [`Value`] out of the heap, adding any objects that aren't copied
+    /// to `handles`
+    pub(crate) fn from_value(value: Value, handles: &Rc<RefCell<Handles>>) -> Self {
+        Self::copy(value, handles, &mut Vec::new())
+    }
+
+    /// `seen` holds the lists and maps currently being copied. A list or map
+    /// that contains itself is kept as a handle instead of recursing forever.
+    fn copy(value: Value, handles: &Rc<RefCell<Handles>>, seen: &mut Vec<Value>) -> Self {
+        if value.is_nil() {
+            return HostValue::Nil;
+        }
+        if value.is_bool() {
+            return HostValue::Bool(value.as_bool());
+        }
+        if value.is_number() {
+            return HostValue::Number(value.as_number());
+        }
+
+        let object = value.as_object();
+        match object.type_() {
+            ObjectType::String => HostValue::String(unsafe { (*object.string).value }.to_string()),
+            ObjectType::List | ObjectType::Map if seen.contains(&value) => {
+                HostValue::Object(Self::handle(value, handles))
+            }
+            ObjectType::List => {
+                seen.push(value);
+                let values = unsafe { &(*object.list).values };
+                let list = values.iter().map(|&value| Self::copy(value, handles, seen)).collect();
+                seen.pop();
+
+                HostValue::List(list)
+            }
+            ObjectType::Map => {
+                seen.push(value);
+                let entries = unsafe { &(*object.map).entries };
+                let map = entries
+                    .iter()
+                    .map(|&(key, value)| {
+                        (Self::copy(key, handles, seen), Self::copy(value, handles, seen))
+                    })
+                    .collect();
+                seen.pop();
+
+                HostValue::Map(map)
+            }
+            _ => HostValue::Object(Self::handle(value, handles)),
+        }
+    }
+
+    fn handle(value: Value, handles: &Rc<RefCell<Handles>>) -> ObjectHandle {
+        let mut table = handles.borrow_mut();
+        let idx = match table.free.pop() {
+            Some(idx) => {
+                table.slots[idx] = (value, 1);
+                idx
+            }
+            None => {
+                table.slots.push((value, 1));
+                table.slots.len() - 1
+            }
+        };
+        ObjectHandle { idx, handles: Rc::clone(handles) }
+    }
+}
+
+impl VM {
+    /// Allocate a [`Value`] for a [`HostValue`] on the heap
+    ///
+    /// The objects allocated for it are kept in `temporaries` so a list or
+    /// map's items aren't collected before it's built. Callers truncate it
+    /// once the value is reachable another way.
+    ///
+    /// # Panics
+    ///
+    /// If it contains an [`ObjectHandle`] from another [`VM`].
+    pub(crate) fn alloc_host(&mut self, value: &HostValue) -> Result<Value, MemoryError> {
+        let value = match value {
+            HostValue::Nil => Value::NIL,
+            HostValue::Bool(value) => (*value).into(),
+            HostValue::Number(value) => (*value).into(),
+            HostValue::String(value) => self.try_alloc(value.as_str())?.into(),
+            HostValue::List(values) => {
+                let values =
+                    values.iter().map(|value| self.alloc_host(value)).collect::<Result<_, _>>()?;
+                self.try_alloc(ObjectList::new(values))?.into()
+            }
+            HostValue::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| Ok((self.alloc_host(key)?, self.alloc_host(value)?)))
+                    .collect::<Result<_, _>>()?;
+                self.try_alloc(ObjectMap::new(entries))?.into()
+            }
+            HostValue::Object(handle) => {
+                return Ok(handle.value(&self.handles).expect("object handle is from another VM"));
+            }
+        };
+        if value.is_object() {
+            self.temporaries.push(value);
+        }
+        Ok(value)
+    }
+}
+
+impl From<bool> for HostValue {
+    fn from(value: bool) -> Self {
+        HostValue::Bool(value)
+    }
+}
+
+impl From<f64> for HostValue {
+    fn from(value: f64) -> Self {
+        HostValue::Number(value)
+    }
+}
+
+impl From<&str> for HostValue {
+    fn from(value: &str) -> Self {
+        HostValue::String(value.to_string())
+    }
+}
+
+impl From<String> for HostValue {
+    fn from(value: String) -> Self {
+        HostValue::String(value)
+    }
+}
+
+impl From<Vec<HostValue>> for HostValue {
+    fn from(values: Vec<HostValue>) -> Self {
+        HostValue::List(values)
+    }
+}
@@ -1,50 +1,118 @@
 mod allocator;
+pub mod bytecode;
 mod chunk;
 mod compiler;
+pub mod debug;
 mod disassembler;
 mod gc;
+mod heap;
+mod host;
+mod native;
 mod object;
 mod op;
+pub mod profile;
+mod string;
 mod util;
 mod value;
 
+use std::cell::RefCell;
 use std::hash::BuildHasherDefault;
 use std::io::Write;
-use std::{mem, ptr};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::{fs, iter, mem, ptr, slice};
 
 use arrayvec::ArrayVec;
 pub use compiler::Compiler;
 pub use disassembler::Disassembler;
-pub use gc::Gc;
+pub use gc::{Gc, GcStats, PauseStats};
 use hashbrown::hash_map::Entry;
 use hashbrown::HashMap;
+pub use heap::{HeapEdge, HeapEdgeKind, HeapNode, HeapSnapshot};
+pub use host::{HostValue, ObjectHandle};
+pub use native::{NativeFn, VmCtx};
+pub use object::ObjectType;
 use rustc_hash::FxHasher;
+pub use string::StringMethod;
 
 use crate::error::{
-    AttributeError, Error, ErrorS, IndexError, IoError, NameError, OverflowError, Result, TypeError,
+    AssertionError, AttributeError, BytecodeError, Error, ErrorS, ImportError, IndexError, IoError,
+    MemoryError, NameError, OverflowError, Result, TypeError, UncaughtError,
 };
-use crate::vm::allocator::GLOBAL;
+use crate::syntax::ast::Program;
+use crate::types::Span;
+use crate::vm::debug::{DebugFrame, Debugger, SourceName};
 use crate::vm::gc::GcAlloc;
+use crate::vm::heap::Edge;
+use crate::vm::host::Handles;
 use crate::vm::object::{
-    Native, ObjectBoundMethod, ObjectClass, ObjectClosure, ObjectFunction, ObjectInstance,
-    ObjectList, ObjectNative, ObjectPackage, ObjectString, ObjectType, ObjectUpvalue,
+    HostNative, Native, Object, ObjectBoundMethod, ObjectClass, ObjectClosure, ObjectFunction,
+    ObjectInstance, ObjectList, ObjectMap, ObjectNative, ObjectPackage, ObjectString,
+    ObjectUpvalue,
 };
-use crate::vm::value::Value;
+use crate::vm::profile::Profiler;
+use crate::vm::util::Instant;
+pub use crate::vm::value::Value;
 
-const GC_HEAP_GROW_FACTOR: usize = 2;
+/// Objects traced or swept by each step of an incremental collection
+const GC_STEP_OBJECTS: usize = 256;
 const FRAMES_MAX: usize = 64;
 const STACK_MAX: usize = FRAMES_MAX * STACK_MAX_PER_FRAME;
 const STACK_MAX_PER_FRAME: usize = u8::MAX as usize + 1;
 
-const LOCKS_LIB: &'static str = include_str!("../../res/lib/locks.locks");
+const LOCKS_LIB: &str = include_str!("../../res/lib/locks.locks");
+
+/// How a call to [`VM::start`] or [`VM::resume`] stopped
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RunOutcome {
+    /// The program ran to the end
+    Finished,
+    /// The program ran out of instruction budget and can be resumed
+    Suspended,
+}
+
+/// Limits & tuning for a [`VM`]'s heap
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct VmConfig {
+    /// Raise a `MemoryError` instead of allocating an object or growing a
+    /// list or map that would put the heap over this many bytes, even after
+    /// collecting garbage
+    pub max_heap_bytes: Option<usize>,
+    /// After a collection, collect again once the heap is this many times
+    /// larger
+    pub gc_grow_factor: usize,
+    /// Bytes the heap can grow to before the first collection. Collections
+    /// never run on a smaller heap than this.
+    pub initial_threshold: usize,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self { max_heap_bytes: None, gc_grow_factor: 2, initial_threshold: 1024 * 1024 }
+    }
+}
 
 #[derive(Debug)]
 pub struct VM {
     pub globals: HashMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>,
     pub open_upvalues: Vec<*mut ObjectUpvalue>,
 
+    /// Packages that have been declared or imported, by name
+    pub packages: HashMap<*mut ObjectString, *mut ObjectPackage, BuildHasherDefault<FxHasher>>,
+    /// Directories searched for a package's source file when it's imported
+    package_paths: Vec<PathBuf>,
+    /// Names of the packages currently being imported. Used to detect
+    /// circular imports.
+    importing: Vec<*mut ObjectString>,
+    /// The importers' globals while an imported package is running with its own
+    suspended_globals: Vec<HashMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>>,
+    /// Globals defined by the natives & the core Locks library. Imported
+    /// packages start running with a copy of these.
+    prelude: Option<HashMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>>,
+
     pub gc: Gc,
     next_gc: usize,
+    config: VmConfig,
 
     /// `frames` is the current stack of frames running in the [`VM`].
     ///
@@ -53,6 +121,26 @@ pub struct VM {
     frames: ArrayVec<CallFrame, FRAMES_MAX>,
     frame: CallFrame,
 
+    /// Error handlers registered by `try` statements, most recent last
+    handlers: Vec<ErrorHandler>,
+
+    /// Instructions left to run before suspending, if limited
+    budget: Option<u64>,
+    /// Whether the program stopped because it ran out of budget and can be
+    /// resumed
+    suspended: bool,
+
+    /// Where the `heap_snapshot()` native writes snapshots, if anywhere
+    heap_snapshot_path: Option<PathBuf>,
+    heap_snapshots_written: usize,
+
+    /// Objects that the host has an [`ObjectHandle`] to
+    handles: Rc<RefCell<Handles>>,
+    /// Objects allocated by a native or copied from the host that nothing
+    /// references yet. They're kept alive until the native returns or the
+    /// copy is on the stack.
+    temporaries: Vec<Value>,
+
     /// `stack` can be safely accessed without bounds checking because:
     /// - Each frame can store a theoretical maximum of `STACK_MAX_PER_FRAME`
     ///   values on the stack.
@@ -67,57 +155,439 @@ pub struct VM {
 
     // String allocated for the "init" constructor method on classes
     init_string: *mut ObjectString,
+    // String allocated for the "message" field on error instances
+    message_string: *mut ObjectString,
     pub source: String,
+    /// Where each file starts in `source`, in order
+    sources: Vec<(usize, SourceName)>,
+    debugger: Option<Box<Debugger>>,
+    profiler: Option<Box<Profiler>>,
 }
 
 impl VM {
     pub fn new() -> VM {
-        let vm = VM::default();
+        VM::default()
+    }
 
-        vm
+    pub fn with_config(config: VmConfig) -> VM {
+        VM { next_gc: config.initial_threshold, config, ..VM::default() }
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
     }
 
     pub fn run(&mut self, source: &str, stdout: &mut impl Write) -> Result<(), Vec<ErrorS>> {
-        let mut errors: Vec<ErrorS> = vec![];
+        self.budget = None;
+        self.start_program(source, stdout)?;
+
+        Ok(())
+    }
 
-        // Load the core Locks library code first
-        if let Err(mut errs) = self.load(&LOCKS_LIB, stdout) {
-            errors.append(&mut errs);
+    /// Run `source` until it finishes or `budget` instructions have been
+    /// executed
+    ///
+    /// A [`RunOutcome::Suspended`] program keeps its state in the [`VM`] and
+    /// continues with [`VM::resume`]. Starting another program discards it.
+    pub fn start(
+        &mut self,
+        source: &str,
+        budget: u64,
+        stdout: &mut impl Write,
+    ) -> Result<RunOutcome, Vec<ErrorS>> {
+        self.budget = Some(budget);
+        self.start_program(source, stdout)
+    }
+
+    /// Continue running a suspended program for up to `budget` more
+    /// instructions
+    ///
+    /// This does nothing if there isn't a suspended program.
+    pub fn resume(
+        &mut self,
+        budget: u64,
+        stdout: &mut impl Write,
+    ) -> Result<RunOutcome, Vec<ErrorS>> {
+        if !self.suspended {
+            return Ok(RunOutcome::Finished);
         }
 
-        // Then load the user code
-        if let Err(mut errs) = self.load(&source, stdout) {
-            errors.append(&mut errs);
+        self.suspended = false;
+        self.budget = Some(budget);
+
+        let outcome = match self.execute(stdout) {
+            Ok(Some(_)) => RunOutcome::Finished,
+            Ok(None) => RunOutcome::Suspended,
+            Err(e) => return Err(vec![e]),
+        };
+        self.suspended = outcome == RunOutcome::Suspended;
+
+        Ok(outcome)
+    }
+
+    /// Discard the suspended program so it can't be resumed
+    pub fn cancel(&mut self) {
+        self.suspended = false;
+        // Closures that escaped keep the values they captured
+        let stack = self.stack.as_mut_ptr();
+        self.close_upvalues(stack);
+        self.stack_top = stack;
+        self.frames.clear();
+        self.handlers.clear();
+        self.open_upvalues.clear();
+    }
+
+    /// Returns `true` if there's a program waiting to be resumed
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Run a program compiled to bytecode (e.g. by `locks compile`)
+    pub fn run_bytecode(
+        &mut self,
+        bytes: &[u8],
+        stdout: &mut impl Write,
+    ) -> Result<(), Vec<ErrorS>> {
+        self.budget = None;
+        self.cancel();
+        self.load_prelude(stdout)?;
+
+        let offset = self.source.len();
+        let script =
+            bytecode::deserialize(bytes, offset).map_err(|e| vec![(e.into(), offset..offset)])?;
+        self.push_source(script.source, SourceName::Script);
+
+        for &(import, ref span) in &script.imports {
+            self.import(import, span, stdout)?;
         }
 
-        if errors.len() > 0 {
-            return Err(errors);
+        let function =
+            script.function(&mut self.gc).map_err(|e| vec![(e.into(), offset..offset)])?;
+        self.run_script(function, stdout)?;
+
+        Ok(())
+    }
+
+    fn start_program(
+        &mut self,
+        source: &str,
+        stdout: &mut impl Write,
+    ) -> Result<RunOutcome, Vec<ErrorS>> {
+        self.cancel();
+        self.load_prelude(stdout)?;
+
+        // Then load the user code
+        let outcome = self.load(source, SourceName::Script, stdout)?;
+        self.suspended = outcome == RunOutcome::Suspended;
+
+        Ok(outcome)
+    }
+
+    /// Load the core Locks library code first. This only happens once so
+    /// `run` can be called repeatedly (e.g. the REPL). It doesn't count
+    /// towards the budget.
+    fn load_prelude(&mut self, stdout: &mut impl Write) -> Result<(), Vec<ErrorS>> {
+        if self.prelude.is_none() {
+            let budget = self.budget.take();
+            self.load(LOCKS_LIB, SourceName::Lib, stdout)?;
+            self.budget = budget;
+            self.prelude = Some(self.globals.clone());
         }
 
         Ok(())
     }
 
-    fn load(&mut self, source: &str, stdout: &mut impl Write) -> Result<(), Vec<ErrorS>> {
+    /// Add a directory to search for package source files in when importing
+    ///
+    /// The package `some::path` is resolved to the file `some/path.locks`
+    /// relative to each directory, in the order they were added.
+    pub fn add_package_path(&mut self, path: impl Into<PathBuf>) {
+        self.package_paths.push(path.into());
+    }
+
+    /// Register a Rust function that Locks code can call as the global `name`
+    ///
+    /// Calls with a number of arguments other than `arity` raise a
+    /// `TypeError` before `function` is called.
+    pub fn register_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let name = self.gc.alloc(name);
+        let native = HostNative { name, arity, function };
+        let native = self.gc.alloc(ObjectNative::new(Native::Host(native))).into();
+
+        self.globals.insert(name, native);
+        if let Some(prelude) = &mut self.prelude {
+            prelude.insert(name, native);
+        }
+    }
+
+    /// Get a copy of the global variable `name`
+    pub fn get_global(&self, name: &str) -> Option<HostValue> {
+        let name = self.gc.find_string(name)?;
+        self.globals.get(&name).map(|&value| HostValue::from_value(value, &self.handles))
+    }
+
+    /// Define or replace the global variable `name`
+    ///
+    /// This raises a `MemoryError` if copying `value` on to the heap puts it
+    /// over [`VmConfig::max_heap_bytes`].
+    ///
+    /// # Panics
+    ///
+    /// If `value` contains an [`ObjectHandle`] from another [`VM`].
+    pub fn set_global(
+        &mut self,
+        name: &str,
+        value: impl Into<HostValue>,
+    ) -> Result<(), Vec<ErrorS>> {
+        let temporaries = self.temporaries.len();
+        let result = self.alloc_host(&value.into()).and_then(|value| {
+            let name = self.try_alloc(name)?;
+            self.gc.write_barrier(value);
+            self.globals.insert(name, value);
+            Ok(())
+        });
+        self.temporaries.truncate(temporaries);
+
+        let span = self.source.len()..self.source.len();
+        result.map_err(|e| vec![(e.into(), span)])
+    }
+
+    /// Call a function, class or method (e.g. from [`VM::get_global`]) with
+    /// `args` and return the result
+    ///
+    /// The call runs without an instruction budget and discards any
+    /// suspended program.
+    ///
+    /// # Panics
+    ///
+    /// If `function` or `args` contain an [`ObjectHandle`] from another
+    /// [`VM`].
+    pub fn call(
+        &mut self,
+        function: &HostValue,
+        args: &[HostValue],
+        stdout: &mut impl Write,
+    ) -> Result<HostValue, Vec<ErrorS>> {
+        let arg_count = match u8::try_from(args.len()) {
+            Ok(arg_count) => arg_count,
+            Err(_) => {
+                let span = self.source.len()..self.source.len();
+                return Err(vec![(OverflowError::TooManyArgs.into(), span)]);
+            }
+        };
+
+        self.cancel();
+        self.budget = None;
+
+        // The callee and arguments are on the stack so they aren't collected
+        // while the rest are copied
+        let temporaries = self.temporaries.len();
+        for value in iter::once(function).chain(args) {
+            let value = self.alloc_host(value);
+            self.temporaries.truncate(temporaries);
+            match value {
+                Ok(value) => self.push(value),
+                Err(e) => {
+                    self.cancel();
+                    let span = self.source.len()..self.source.len();
+                    return Err(vec![(e.into(), span)]);
+                }
+            }
+        }
+
+        // Run a function that only calls the callee on the stack and returns
+        // its result, so errors and natives work the same as in scripts
+        let span = self.source.len()..self.source.len();
+        let name = self.gc.alloc("");
+        let mut trampoline = ObjectFunction::new(name, 0);
+        trampoline.chunk.write_u8(op::CALL, &span);
+        trampoline.chunk.write_u8(arg_count, &span);
+        trampoline.chunk.write_u8(op::RETURN, &span);
+        let trampoline = self.gc.alloc(trampoline);
+
+        self.frame = CallFrame {
+            closure: self.gc.alloc(ObjectClosure::new(trampoline, Vec::new())),
+            ip: unsafe { (*trampoline).chunk.ops.as_ptr() },
+            stack: self.stack.as_mut_ptr(),
+        };
+
+        let value = self.execute(stdout).map_err(|e| vec![e])?;
+
+        Ok(value.map_or(HostValue::Nil, |value| HostValue::from_value(value, &self.handles)))
+    }
+
+    /// Stop the program at breakpoints and steps, or stop debugging with
+    /// [`None`]
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.debugger = debugger.map(Box::new);
+    }
+
+    /// Time the functions & lines that run, or stop profiling with [`None`]
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler.map(Box::new);
+    }
+
+    /// Stop profiling and return the [`Profiler`], if there was one
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take().map(|profiler| *profiler)
+    }
+
+    /// The current call stack, innermost call first
+    ///
+    /// Meant to be used while the program is stopped in a [`Debugger`].
+    pub fn debug_frames(&self) -> Vec<DebugFrame> {
+        let current = (&self.frame, true);
+        let callers = self.frames.iter().map(|frame| (frame, false));
+
+        let mut frames = Vec::new();
+        for (frame, is_current) in callers.chain([current]).rev() {
+            if frame.closure.is_null() {
+                continue;
+            }
+
+            let closure = unsafe { &*frame.closure };
+            let function = unsafe { &*closure.function };
+            let chunk = &function.chunk;
+
+            // Callers have already read the `CALL` op they're waiting on
+            let idx = unsafe { frame.ip.offset_from(chunk.ops.as_ptr()) } as usize;
+            let idx = if is_current { idx } else { idx.saturating_sub(1) };
+
+            let locals = chunk
+                .locals
+                .iter()
+                .filter(|local| local.start <= idx && idx < local.end)
+                .map(|local| {
+                    let value = unsafe { *frame.stack.add(local.slot as usize) };
+                    (local.name.clone(), value)
+                })
+                .collect();
+
+            let upvalues = chunk
+                .upvalues
+                .iter()
+                .zip(&closure.upvalues)
+                .map(|(name, &upvalue)| (name.clone(), unsafe { *(*upvalue).location }))
+                .collect();
+
+            frames.push(DebugFrame {
+                name: unsafe { (*function.name).value }.to_string(),
+                location: debug::location(self, chunk.spans[idx].start),
+                locals,
+                upvalues,
+            });
+        }
+        frames
+    }
+
+    /// Global variables defined by the program, sorted by name
+    pub fn debug_globals(&self) -> Vec<(String, Value)> {
+        let prelude = self.prelude.as_ref();
+        let mut globals = self
+            .globals
+            .iter()
+            .filter(|&(name, value)| prelude.and_then(|prelude| prelude.get(name)) != Some(value))
+            .map(|(&name, &value)| (unsafe { (*name).value }.to_string(), value))
+            .collect::<Vec<_>>();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        globals
+    }
+
+    /// The start and name of the file that an offset in `source` is in
+    pub(crate) fn source_at(&self, offset: usize) -> Option<(usize, &SourceName)> {
+        let idx = self.sources.partition_point(|&(start, _)| start <= offset).checked_sub(1)?;
+        let (start, name) = &self.sources[idx];
+        Some((*start, name))
+    }
+
+    /// The start, text and name of the file that an offset in `source` is in
+    pub(crate) fn source_file(&self, offset: usize) -> Option<(usize, &str, &SourceName)> {
+        let idx = self.sources.partition_point(|&(start, _)| start <= offset).checked_sub(1)?;
+        let (start, name) = &self.sources[idx];
+        let end = self.sources.get(idx + 1).map_or(self.source.len(), |&(end, _)| end);
+        Some((*start, &self.source[*start..end], name))
+    }
+
+    /// Let the [`Debugger`] stop the program before the next op runs
+    fn debug_op(&mut self) {
+        let Some(mut debugger) = self.debugger.take() else {
+            return;
+        };
+
+        let function = unsafe { (*self.frame.closure).function };
+        let idx = unsafe { self.frame.ip.offset_from((*function).chunk.ops.as_ptr()) } as usize;
+        let offset = unsafe { &(*function).chunk.spans }[idx].start;
+        debugger.before_op(self, offset, self.frames.len());
+
+        self.debugger = Some(debugger);
+    }
+
+    /// Let the [`Profiler`] time the last op and see the next one
+    fn profile_op(&mut self) {
+        let Some(mut profiler) = self.profiler.take() else {
+            return;
+        };
+
+        profiler.before_op(self);
+
+        self.profiler = Some(profiler);
+    }
+
+    fn load(
+        &mut self,
+        source: &str,
+        name: SourceName,
+        stdout: &mut impl Write,
+    ) -> Result<RunOutcome, Vec<ErrorS>> {
+        let offset = self.push_source(source, name);
+        let program = crate::syntax::parse(source, offset)?;
+
+        self.load_program(&program, stdout)
+    }
+
+    /// Add source to `self.source` and return the offset it starts at
+    ///
+    /// This helps us keep track of what the offset should be on future calls to `load`
+    fn push_source(&mut self, source: &str, name: SourceName) -> usize {
         // This will change with each call to `run`
         let offset = self.source.len();
+        self.sources.push((offset, name));
 
-        // Add current source to self.source
-        // This helps us keep track of what the offset should be on future calls to `load`
         self.source.reserve(source.len() + 1);
         self.source.push_str(source);
         self.source.push('\n');
 
-        let program = crate::syntax::parse(source, offset)?;
+        offset
+    }
+
+    /// Import the program's packages then compile and run it
+    fn load_program(
+        &mut self,
+        program: &Program,
+        stdout: &mut impl Write,
+    ) -> Result<RunOutcome, Vec<ErrorS>> {
+        for (import, span) in &program.imports {
+            self.import(&import.package, span, stdout)?;
+        }
+
+        let function = Compiler::compile(program, &mut self.gc)?;
 
-        let function = Compiler::compile(&program, &mut self.gc)?;
+        self.run_script(function, stdout)
+    }
 
+    /// Run a compiled script function after its imports have been loaded
+    fn run_script(
+        &mut self,
+        function: *mut ObjectFunction,
+        stdout: &mut impl Write,
+    ) -> Result<RunOutcome, Vec<ErrorS>> {
         #[cfg(feature = "pprof")]
         let guard = pprof::ProfilerGuardBuilder::default()
             .blocklist(&["libc", "libgcc", "pthread", "vdso"])
             .build()
             .expect("could not start pprof");
 
-        self.run_function(function, stdout).map_err(|e| vec![e])?;
+        let outcome = self.run_function(function, stdout).map_err(|e| vec![e])?;
 
         #[cfg(feature = "pprof")]
         {
@@ -133,9 +603,97 @@ impl VM {
             std::fs::write("profile.pb", content).expect("error writing pprof report to file");
         }
 
+        Ok(outcome)
+    }
+
+    /// Import a package by name
+    ///
+    /// Packages are only loaded once. The package's source file is compiled and
+    /// run with its own globals which are then stored on its [`ObjectPackage`].
+    fn import(
+        &mut self,
+        name: &str,
+        span: &Span,
+        stdout: &mut impl Write,
+    ) -> Result<(), Vec<ErrorS>> {
+        let package_name = self.gc.alloc(name);
+
+        if self.packages.contains_key(&package_name) {
+            return Ok(());
+        }
+
+        if self.importing.contains(&package_name) {
+            return Err(vec![(
+                ImportError::Circular { name: name.to_string() }.into(),
+                span.clone(),
+            )]);
+        }
+
+        let not_found =
+            || vec![(ImportError::NotFound { name: name.to_string() }.into(), span.clone())];
+        let path = self.package_path(name).ok_or_else(not_found)?;
+        let source = fs::read_to_string(&path).map_err(|_| not_found())?;
+
+        let offset = self.push_source(&source, SourceName::Package(path.clone()));
+        let program = crate::syntax::parse(&source, offset)?;
+
+        if program.package.as_deref() != Some(name) {
+            return Err(vec![(
+                ImportError::PackageMismatch {
+                    name: name.to_string(),
+                    path: path.display().to_string(),
+                }
+                .into(),
+                span.clone(),
+            )]);
+        }
+
+        // Run the package with a fresh set of globals
+        self.importing.push(package_name);
+        let globals = mem::replace(&mut self.globals, self.prelude.clone().unwrap_or_default());
+        self.suspended_globals.push(globals);
+
+        let result = self.load_program(&program, stdout);
+
+        let globals = self.suspended_globals.pop().expect("importer globals were not suspended");
+        let package_globals = mem::replace(&mut self.globals, globals);
+        self.importing.pop();
+
+        // Packages can't be suspended part way through being imported
+        let result = match result {
+            Ok(RunOutcome::Suspended) => {
+                self.cancel();
+                Err(vec![(OverflowError::BudgetExhausted.into(), span.clone())])
+            }
+            result => result,
+        };
+
+        if let Err(errors) = result {
+            self.packages.remove(&package_name);
+            return Err(errors);
+        }
+
+        // The package was registered when its `package` declaration ran
+        let package = match self.packages.get(&package_name) {
+            Some(&package) => package,
+            None => self.gc.alloc(ObjectPackage::new(package_name)),
+        };
+        for (&name, &value) in &package_globals {
+            self.gc.write_barrier(name);
+            self.gc.write_barrier(value);
+        }
+        unsafe { (*package).globals = package_globals };
+        self.packages.insert(package_name, package);
+
         Ok(())
     }
 
+    /// Find a package's source file in the package paths
+    fn package_path(&self, name: &str) -> Option<PathBuf> {
+        let relative_path = PathBuf::from(format!("{}.locks", name.replace("::", "/")));
+        self.package_paths.iter().map(|path| path.join(&relative_path)).find(|path| path.is_file())
+    }
+
     /// Run a compiled function from the compiler
     ///
     /// This is only called by vm.run
@@ -143,10 +701,11 @@ impl VM {
         &mut self,
         function: *mut ObjectFunction,
         stdout: &mut impl Write,
-    ) -> Result<()> {
+    ) -> Result<RunOutcome> {
         self.stack_top = self.stack.as_mut_ptr();
 
         self.frames.clear();
+        self.handlers.clear();
 
         self.frame = CallFrame {
             closure: self.gc.alloc(ObjectClosure::new(function, Vec::new())),
@@ -154,9 +713,37 @@ impl VM {
             stack: self.stack_top,
         };
 
+        match self.execute(stdout)? {
+            Some(_) => Ok(RunOutcome::Finished),
+            None => Ok(RunOutcome::Suspended),
+        }
+    }
+
+    /// Run ops from the current frame until it returns, returning its value
+    ///
+    /// Returns [`None`] if the budget ran out first. Running again continues
+    /// from the next op.
+    fn execute(&mut self, stdout: &mut impl Write) -> Result<Option<Value>> {
+        let function = unsafe { (*self.frame.closure).function };
         let disassembler = Disassembler::new(unsafe { &(*function).chunk });
 
-        loop {
+        let value = loop {
+            // The op runs when the program is resumed, so the hooks wait until
+            // then
+            if let Some(budget) = &mut self.budget {
+                if *budget == 0 {
+                    return Ok(None);
+                }
+                *budget -= 1;
+            }
+
+            if self.debugger.is_some() {
+                self.debug_op();
+            }
+            if self.profiler.is_some() {
+                self.profile_op();
+            }
+
             if cfg!(feature = "vm-trace") {
                 let function = unsafe { (*self.frame.closure).function };
                 let idx = unsafe { self.frame.ip.offset_from((*function).chunk.ops.as_ptr()) };
@@ -164,7 +751,7 @@ impl VM {
                 println!("{} {}", op_idx_str, op_str);
             }
 
-            match self.read_u8() {
+            let result = match self.read_u8() {
                 op::CONSTANT => self.op_constant(),
                 op::NIL => self.op_nil(),
                 op::TRUE => self.op_true(),
@@ -205,10 +792,19 @@ impl VM {
                     let value = self.pop();
                     self.close_upvalues(self.frame.stack);
 
+                    // Discard handlers registered by `try` statements the function returned from
+                    while self
+                        .handlers
+                        .last()
+                        .is_some_and(|handler| handler.frames_len >= self.frames.len())
+                    {
+                        self.handlers.pop();
+                    }
+
                     self.stack_top = self.frame.stack;
                     match self.frames.pop() {
                         Some(frame) => self.frame = frame,
-                        None => break,
+                        None => break Some(value),
                     }
                     self.push(value);
 
@@ -219,13 +815,29 @@ impl VM {
                 op::METHOD => self.op_method(),
                 op::FIELD => self.op_field(),
                 op::CREATE_LIST => self.op_create_list(),
+                op::CREATE_MAP => self.op_create_map(),
                 op::GET_INDEX => self.op_get_index(),
                 op::SET_INDEX => self.op_set_index(),
                 op::PACKAGE => self.op_package(),
                 op::STATIC_FIELD => self.op_static_field(),
                 op::STATIC_METHOD => self.op_static_method(),
+                op::GET_PACKAGE => self.op_get_package(),
+                op::TRY => self.op_try(),
+                op::END_TRY => self.op_end_try(),
+                op::THROW => self.op_throw(),
                 _ => util::unreachable(),
-            }?;
+            };
+
+            // Hand runtime errors to the script if it's handling them
+            if let Err(error) = result {
+                if self.handlers.is_empty() {
+                    return Err(error);
+                }
+
+                let (error, _) = error;
+                let value = self.error_value(&error);
+                self.throw(value)?;
+            }
 
             if cfg!(feature = "vm-trace") {
                 eprint!("     ");
@@ -236,44 +848,86 @@ impl VM {
                 }
                 eprintln!();
             }
-        }
+        };
 
         debug_assert_eq!(
             self.frame.stack, self.stack_top,
             "VM finished executing but stack is not empty"
         );
-        Ok(())
+        Ok(value)
     }
 
     fn op_create_list(&mut self) -> Result<()> {
-        let length = self.read_u8();
+        let length = self.read_u8() as usize;
 
-        let mut values = vec![];
+        // The values stay on the stack while the list is allocated so they
+        // aren't collected
+        let values = unsafe { slice::from_raw_parts(self.stack_top.sub(length), length) };
+        let list = self.alloc(ObjectList::new(values.to_vec()))?;
 
-        for _ in 0..length {
-            let value = self.pop();
+        self.stack_top = unsafe { self.stack_top.sub(length) };
+        self.push(list.into());
 
-            values.push(value);
-        }
+        Ok(())
+    }
 
-        values.reverse();
+    /// Pop key & value pairs from the stack and push a new [`ObjectMap`] of them
+    fn op_create_map(&mut self) -> Result<()> {
+        let length = self.read_u8() as usize;
 
-        let list = self.gc.alloc(ObjectList::new(values));
-        let value = list.into();
+        // The pairs stay on the stack while the map is allocated so they
+        // aren't collected
+        let pairs = unsafe { slice::from_raw_parts(self.stack_top.sub(length * 2), length * 2) };
+        let entries = pairs.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect();
+        let map = self.alloc(ObjectMap::new(entries))?;
 
-        self.push(value);
+        self.stack_top = unsafe { self.stack_top.sub(length * 2) };
+        self.push(map.into());
 
         Ok(())
     }
 
     fn op_get_index(&mut self) -> Result<()> {
         let index = self.pop();
+
+        let target = unsafe { *self.peek(0) };
+        if target.is_object() && target.as_object().type_() == ObjectType::Map {
+            let map = unsafe { target.as_object().map };
+
+            return match unsafe { (*map).get(index) } {
+                Some(value) => {
+                    self.pop();
+                    self.push(value);
+                    Ok(())
+                }
+                None => self.err(IndexError::KeyNotFound { key: index.repr() }),
+            };
+        }
+
+        // Indexing with a string accesses a property
+        if index.is_object() && index.as_object().type_() == ObjectType::String {
+            return self.get_property(unsafe { index.as_object().string });
+        }
+
+        if target.is_object() && target.as_object().type_() == ObjectType::String {
+            let value = match self.string_index(unsafe { target.as_object().string }, index) {
+                Ok(value) => value,
+                Err(e) => return self.err(e),
+            };
+            self.pop();
+            self.push(value);
+            return Ok(());
+        }
+
         let target = self.pop();
 
         if target.is_object() {
             if target.as_object().type_() == ObjectType::List {
                 let list = unsafe { &(*target.as_object().list) };
-                let list_idx = index.as_number() as usize;
+                let list_idx = match Self::list_index(index) {
+                    Ok(list_idx) => list_idx,
+                    Err(e) => return self.err(e),
+                };
 
                 if list_idx >= list.values.len() {
                     return self.err(IndexError::OutOfBounds {
@@ -303,43 +957,196 @@ impl VM {
             eprintln!("Pushing package on to stack");
         }
 
-        let package = self.alloc(ObjectPackage::new(name)).into();
+        let package = match self.packages.get(&name) {
+            Some(&package) => package,
+            None => {
+                let package = self.alloc(ObjectPackage::new(name))?;
+                self.packages.insert(name, package);
+                package
+            }
+        };
 
         if cfg!(feature = "vm-trace") {
             eprintln!("Pushed package on to stack");
         }
 
-        self.push(package);
+        self.push(package.into());
+
+        Ok(())
+    }
+
+    /// Register an error handler for the rest of the `try` statement's body
+    fn op_try(&mut self) -> Result<()> {
+        let offset = self.read_u16() as usize;
+
+        self.handlers.push(ErrorHandler {
+            frames_len: self.frames.len(),
+            stack_top: self.stack_top,
+            ip: unsafe { self.frame.ip.add(offset) },
+        });
+
+        Ok(())
+    }
+
+    /// Remove the error handler registered by the `try` statement's body
+    fn op_end_try(&mut self) -> Result<()> {
+        self.handlers.pop();
+        Ok(())
+    }
+
+    fn op_throw(&mut self) -> Result<()> {
+        let value = self.pop();
+        self.throw(value)
+    }
+
+    /// Raise a [`Value`] as an error
+    ///
+    /// This unwinds the frames & stack to the most recent error handler, then
+    /// pushes the value on to the stack for the handler. Without a handler the
+    /// value is returned as an [`UncaughtError`].
+    fn throw(&mut self, value: Value) -> Result<()> {
+        let Some(handler) = self.handlers.pop() else {
+            let error = self.uncaught_error(value);
+            return self.err(error);
+        };
+
+        self.close_upvalues(handler.stack_top);
+
+        while self.frames.len() > handler.frames_len {
+            self.frame = self.frames.pop().expect("frame should exist for error handler");
+        }
+
+        self.frame.ip = handler.ip;
+        self.stack_top = handler.stack_top;
+        self.push(value);
 
         Ok(())
     }
 
+    /// Create an instance of the error class matching the [`Error`]'s type
+    /// (e.g. `TypeError`) with the error's message
+    fn error_value(&mut self, error: &Error) -> Value {
+        let type_ = self.gc.alloc(error.type_());
+        let message = error.message();
+
+        // Errors use the classes from the core Locks library even if the
+        // script defined its own with the same name
+        let class = self.prelude.as_ref().unwrap_or(&self.globals).get(&type_).copied();
+
+        match class {
+            Some(class) if class.is_object() && class.as_object().type_() == ObjectType::Class => {
+                let instance =
+                    self.alloc_unlimited(ObjectInstance::new(unsafe { class.as_object().class }));
+
+                // Keep the instance on the stack while allocating the message
+                self.push(instance.into());
+                let message = self.alloc_unlimited(message).into();
+                self.gc.write_barrier(message);
+                unsafe { (*instance).fields.insert(self.message_string, message) };
+                self.pop()
+            }
+            _ => self.alloc_unlimited(message).into(),
+        }
+    }
+
+    /// Describe a thrown [`Value`] that wasn't caught
+    fn uncaught_error(&self, value: Value) -> UncaughtError {
+        if value.is_object() && value.as_object().type_() == ObjectType::Instance {
+            let instance = unsafe { value.as_object().instance };
+
+            if let Some(message) = unsafe { (*instance).fields.get(&self.message_string) } {
+                return UncaughtError {
+                    type_: unsafe { (*(*(*instance).class).name).value.to_string() },
+                    message: message.to_string(),
+                };
+            }
+        }
+
+        UncaughtError { type_: "Error".to_string(), message: value.to_string() }
+    }
+
+    /// Push an imported [`ObjectPackage`] by name on to the stack
+    fn op_get_package(&mut self) -> Result<()> {
+        let name = unsafe { self.read_value().as_object().string };
+
+        match self.packages.get(&name) {
+            Some(&package) => {
+                self.push(package.into());
+                Ok(())
+            }
+            None => {
+                self.err(ImportError::NotImported { name: unsafe { (*name).value.to_string() } })
+            }
+        }
+    }
+
     fn op_set_index(&mut self) -> Result<()> {
         let value = self.pop();
         let index = self.pop();
         let target = self.pop();
 
-        if target.is_object() {
-            if target.as_object().type_() == ObjectType::List {
-                let list = unsafe { &mut (*target.as_object().list) };
-                let list_idx = index.as_number() as usize;
+        if target.is_object() && target.as_object().type_() == ObjectType::Map {
+            let map = unsafe { target.as_object().map };
+            if unsafe { (*map).get(index) }.is_none() {
+                let bytes = mem::size_of::<(Value, Value)>() + mem::size_of::<(Value, usize)>();
+                self.grow(bytes, &[target, index, value])?;
+            }
+            self.gc.write_barrier(index);
+            self.gc.write_barrier(value);
+            unsafe { (*map).insert(index, value) };
+            self.push(value);
+            return Ok(());
+        }
 
-                if list_idx >= list.values.len() {
-                    list.values.resize(list_idx, Value::NIL);
-                    list.values.push(value);
-                } else {
-                    list.values[list_idx] = value;
-                }
+        // Indexing with a string sets a property
+        if index.is_object() && index.as_object().type_() == ObjectType::String {
+            self.push(value);
+            self.push(target);
+            return self.set_property(unsafe { index.as_object().string });
+        }
 
-                self.push(value);
+        if target.is_object() && target.as_object().type_() == ObjectType::List {
+            let list = unsafe { &mut (*target.as_object().list) };
+            let list_idx = match Self::list_index(index) {
+                Ok(list_idx) => list_idx,
+                Err(e) => return self.err(e),
+            };
+
+            self.gc.write_barrier(value);
+            if list_idx >= list.values.len() {
+                let added = list_idx + 1 - list.values.len();
+                self.grow(added.saturating_mul(mem::size_of::<Value>()), &[target, value])?;
+                list.values.resize(list_idx, Value::NIL);
+                list.values.push(value);
             } else {
-                return self.err(TypeError::NotIndexable { type_: target.type_().to_string() });
+                list.values[list_idx] = value;
             }
+
+            self.push(value);
+        } else {
+            return self.err(TypeError::NotIndexable { type_: target.type_().to_string() });
         }
 
         Ok(())
     }
 
+    /// Convert a [`Value`] used to index a list in to a `usize`
+    ///
+    /// Only non-negative integers are valid list indexes.
+    fn list_index(index: Value) -> Result<usize, TypeError> {
+        if !index.is_number() {
+            return Err(TypeError::InvalidIndexType { type_: index.type_().to_string() });
+        }
+
+        let number = index.as_number();
+
+        if number < 0.0 || number.fract() != 0.0 || number > usize::MAX as f64 {
+            return Err(TypeError::InvalidIndex { index: index.to_string() });
+        }
+
+        Ok(number as usize)
+    }
+
     /// Get a constant [`Value`] from the [`CallFrame`]'s constant's table
     /// push it on to the stack.
     ///
@@ -407,21 +1214,45 @@ impl VM {
         let value = self.pop();
 
         match self.globals.get(&name) {
-            Some(_) => {
+            Some(&existing) if !self.is_library_error_class(name, existing) => {
                 self.err(NameError::AlreadyDefined { name: unsafe { (*name).value.to_string() } })
             }
-            None => {
+            _ => {
+                self.gc.write_barrier(value);
                 self.globals.insert(name, value);
                 Ok(())
             }
         }
     }
 
+    /// Whether a global is one of the error classes from the core Locks
+    /// library, which scripts can declare their own classes in place of
+    fn is_library_error_class(&self, name: *mut ObjectString, value: Value) -> bool {
+        let is_library_global =
+            self.prelude.as_ref().is_some_and(|prelude| prelude.get(&name) == Some(&value));
+        if !is_library_global
+            || !value.is_object()
+            || value.as_object().type_() != ObjectType::Class
+        {
+            return false;
+        }
+
+        let mut class = Some(unsafe { value.as_object().class });
+        while let Some(current) = class {
+            if unsafe { (*(*current).name).value } == "Error" {
+                return true;
+            }
+            class = unsafe { (*current).super_ };
+        }
+        false
+    }
+
     fn op_set_global(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
         let value = unsafe { *self.peek(0) };
         match self.globals.entry(name) {
             Entry::Occupied(mut entry) => {
+                self.gc.write_barrier(value);
                 entry.insert(value);
                 Ok(())
             }
@@ -433,7 +1264,8 @@ impl VM {
 
     fn op_get_upvalue(&mut self) -> Result<()> {
         let upvalue_idx = self.read_u8() as usize;
-        let object = *unsafe { (*self.frame.closure).upvalues.get_unchecked(upvalue_idx) };
+        let upvalues = unsafe { &(*self.frame.closure).upvalues };
+        let object = *unsafe { upvalues.get_unchecked(upvalue_idx) };
         let value = unsafe { *(*object).location };
         self.push(value);
         Ok(())
@@ -441,14 +1273,22 @@ impl VM {
 
     fn op_set_upvalue(&mut self) -> Result<()> {
         let upvalue_idx = self.read_u8() as usize;
-        let object = *unsafe { (*self.frame.closure).upvalues.get_unchecked(upvalue_idx) };
-        let value = unsafe { (*object).location };
-        unsafe { *value = *self.peek(0) };
+        let upvalues = unsafe { &(*self.frame.closure).upvalues };
+        let object = *unsafe { upvalues.get_unchecked(upvalue_idx) };
+        let location = unsafe { (*object).location };
+        let value = unsafe { *self.peek(0) };
+        self.gc.write_barrier(value);
+        unsafe { *location = value };
         Ok(())
     }
 
     fn op_get_property(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
+        self.get_property(name)
+    }
+
+    /// Replace the object on top of the stack with its property's value
+    fn get_property(&mut self, name: *mut ObjectString) -> Result<()> {
         let value = unsafe { *self.peek(0) };
 
         if !value.is_object() {
@@ -493,7 +1333,8 @@ impl VM {
                     }
                     None => match unsafe { (*(*instance).class).get_method(name) } {
                         Some(&method) => {
-                            let bound_method = self.alloc(ObjectBoundMethod::new(instance, method));
+                            let bound_method =
+                                self.alloc(ObjectBoundMethod::new(instance, method))?;
                             self.pop();
                             self.push(bound_method.into());
                         }
@@ -506,6 +1347,36 @@ impl VM {
                     },
                 }
             }
+            ObjectType::Package => {
+                let package = unsafe { object.package };
+
+                match unsafe { (*package).globals.get(&name) } {
+                    Some(&value) => {
+                        self.pop();
+                        self.push(value);
+                    }
+                    None => {
+                        return self.err(NameError::NotDefined {
+                            name: unsafe {
+                                format!("{}:{}", (*(*package).name).value, (*name).value)
+                            },
+                        });
+                    }
+                }
+            }
+            // Methods are bound to the string so they can be called later
+            ObjectType::String => {
+                let Some(method) = StringMethod::from_name(unsafe { (*name).value }) else {
+                    return self.err(AttributeError::NoSuchAttribute {
+                        type_: value.type_().to_string(),
+                        name: unsafe { (*name).value.to_string() },
+                    });
+                };
+                let native = Native::StringMethod(method, unsafe { object.string });
+                let native = self.alloc(ObjectNative::new(native))?;
+                self.pop();
+                self.push(native.into());
+            }
             _ => {
                 return self.err(AttributeError::NoSuchAttribute {
                     type_: value.type_().to_string(),
@@ -519,6 +1390,12 @@ impl VM {
 
     fn op_set_property(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
+        self.set_property(name)
+    }
+
+    /// Pop the object on top of the stack and set its property to the value
+    /// beneath it
+    fn set_property(&mut self, name: *mut ObjectString) -> Result<()> {
         let value = self.pop();
 
         if !value.is_object() {
@@ -536,14 +1413,15 @@ impl VM {
                 let value = unsafe { *self.peek(0) };
                 let has_field = unsafe { (*class).get_static_field(name) };
 
-                if let Some(_) = has_field {
+                if has_field.is_some() {
+                    self.gc.write_barrier(value);
                     unsafe { (*class).static_fields.insert(name, value) };
                     return Ok(());
                 }
 
                 let class_has_method = unsafe { (*class).get_static_method(name) };
 
-                if let Some(_) = class_has_method {
+                if class_has_method.is_some() {
                     return self.err(TypeError::InvalidStaticMethodAssignment {
                         name: unsafe { (*name).value.to_owned() },
                         type_: unsafe { (*(*class).name).value.to_owned() },
@@ -569,14 +1447,15 @@ impl VM {
                 let value = unsafe { *self.peek(0) };
                 let has_field = unsafe { (*instance).fields.get(&name) };
 
-                if let Some(_) = has_field {
+                if has_field.is_some() {
+                    self.gc.write_barrier(value);
                     unsafe { (*instance).fields.insert(name, value) };
                     return Ok(());
                 }
 
                 let class_has_method = unsafe { (*(*instance).class).methods.get(&name) };
 
-                if let Some(_) = class_has_method {
+                if class_has_method.is_some() {
                     return self.err(TypeError::InvalidMethodAssignment {
                         name: unsafe { (*name).value.to_owned() },
                         type_: unsafe { (*(*(*instance).class).name).value.to_owned() },
@@ -588,22 +1467,37 @@ impl VM {
                     name: unsafe { (*name).value.to_string() },
                 })
             }
-            _ => {
-                return self.err(AttributeError::NoSuchAttribute {
-                    type_: value.type_().to_string(),
-                    name: unsafe { (*name).value.to_string() },
-                });
+            ObjectType::Package => {
+                let package = unsafe { object.package };
+                let value = unsafe { *self.peek(0) };
+
+                match unsafe { (*package).globals.entry(name) } {
+                    Entry::Occupied(mut entry) => {
+                        self.gc.write_barrier(value);
+                        entry.insert(value);
+                        Ok(())
+                    }
+                    Entry::Vacant(_) => self.err(NameError::NotDefined {
+                        name: unsafe { format!("{}:{}", (*(*package).name).value, (*name).value) },
+                    }),
+                }
             }
+            _ => self.err(AttributeError::NoSuchAttribute {
+                type_: value.type_().to_string(),
+                name: unsafe { (*name).value.to_string() },
+            }),
         }
     }
 
     fn op_get_super(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
+        self.check_operand(0, ObjectType::Class)?;
+        self.check_operand(1, ObjectType::Instance)?;
         let super_ = unsafe { self.pop().as_object().class };
         match unsafe { (*super_).get_method(name) } {
             Some(&method) => {
                 let instance = unsafe { (*self.peek(0)).as_object().instance };
-                let bound_method = self.alloc(ObjectBoundMethod::new(instance, method));
+                let bound_method = self.alloc(ObjectBoundMethod::new(instance, method))?;
                 self.pop();
                 self.push(bound_method.into());
             }
@@ -649,10 +1543,13 @@ impl VM {
     ///
     /// Both values must be either numbers or strings
     fn op_add(&mut self) -> Result<()> {
-        let b = self.pop();
-        let a = self.pop();
+        // The operands stay on the stack while the result is allocated so
+        // they aren't collected
+        let b = unsafe { *self.peek(0) };
+        let a = unsafe { *self.peek(1) };
 
         if a.is_number() && b.is_number() {
+            self.stack_top = unsafe { self.stack_top.sub(2) };
             self.push((a.as_number() + b.as_number()).into());
             return Ok(());
         }
@@ -662,8 +1559,16 @@ impl VM {
             let b = b.as_object();
 
             if a.type_() == ObjectType::String && b.type_() == ObjectType::String {
-                let result = unsafe { [(*a.string).value, (*b.string).value] }.concat();
-                let result = Value::from(self.alloc(result));
+                let (a, b) = unsafe { ((*a.string).value, (*b.string).value) };
+
+                // Check the heap limit before concatenating so a huge string
+                // is never built
+                if let Err(e) = self.reserve(mem::size_of::<ObjectString>() + a.len() + b.len()) {
+                    return self.err(e);
+                }
+
+                let result = Value::from(self.alloc([a, b].concat())?);
+                self.stack_top = unsafe { self.stack_top.sub(2) };
                 self.push(result);
                 return Ok(());
             }
@@ -753,6 +1658,7 @@ impl VM {
     fn op_invoke(&mut self, stdout: &mut impl Write) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
         let arg_count = self.read_u8() as usize;
+        self.check_operand(arg_count, ObjectType::Instance)?;
         let instance = unsafe { (*self.peek(arg_count)).as_object().instance };
 
         match unsafe { (*instance).fields.get(&name) } {
@@ -770,6 +1676,7 @@ impl VM {
     fn op_super_invoke(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
         let arg_count = self.read_u8() as usize;
+        self.check_operand(0, ObjectType::Class)?;
         let super_ = unsafe { self.pop().as_object().class };
 
         match unsafe { (*super_).get_method(name) } {
@@ -802,12 +1709,13 @@ impl VM {
                 let location = unsafe { self.frame.stack.add(upvalue_idx) };
                 self.capture_upvalue(location)
             } else {
-                unsafe { *(*self.frame.closure).upvalues.get_unchecked(upvalue_idx) }
+                let upvalues = unsafe { &(*self.frame.closure).upvalues };
+                *unsafe { upvalues.get_unchecked(upvalue_idx) }
             };
             upvalues.push(upvalue);
         }
 
-        let closure = self.alloc(ObjectClosure::new(function, upvalues));
+        let closure = self.alloc(ObjectClosure::new(function, upvalues))?;
         self.push(closure.into());
         Ok(())
     }
@@ -824,7 +1732,7 @@ impl VM {
     /// The [`ObjectClass`] is pushed on to the VM's stack.
     fn op_class(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
-        let class = self.alloc(ObjectClass::new(name)).into();
+        let class = self.alloc(ObjectClass::new(name))?.into();
         self.push(class);
         Ok(())
     }
@@ -835,6 +1743,7 @@ impl VM {
     ///
     /// This pops the child [`ObjectClass`] of the VM's stack.
     fn op_inherit(&mut self) -> Result<()> {
+        self.check_operand(0, ObjectType::Class)?;
         let class = unsafe { self.pop().as_object().class };
         let super_ = {
             let value = unsafe { *self.peek(0) };
@@ -848,6 +1757,8 @@ impl VM {
             }
         };
 
+        // The fields' values are reachable from the superclass
+        self.gc.write_barrier(super_);
         unsafe { (*class).fields = (*super_).fields.clone() };
 
         unsafe {
@@ -864,8 +1775,10 @@ impl VM {
     /// This pop's the [`Value`] from the VM's stack for the field value.
     fn op_field(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
+        self.check_operand(1, ObjectType::Class)?;
         let value = self.pop();
         let class = unsafe { (*self.peek(0)).as_object().class };
+        self.gc.write_barrier(value);
         unsafe { (*class).fields.insert(name, value) };
         Ok(())
     }
@@ -877,8 +1790,10 @@ impl VM {
     /// This pop's the [`Value`] from the VM's stack for the field value.
     fn op_static_field(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
+        self.check_operand(1, ObjectType::Class)?;
         let value = self.pop();
         let class = unsafe { (*self.peek(0)).as_object().class };
+        self.gc.write_barrier(value);
         unsafe { (*class).static_fields.insert(name, value) };
         Ok(())
     }
@@ -892,8 +1807,11 @@ impl VM {
     /// The next [`Value`] on the VM's stack is used as the [`ObjectClass`] for the method.
     fn op_static_method(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
+        self.check_operand(0, ObjectType::Closure)?;
+        self.check_operand(1, ObjectType::Class)?;
         let method = unsafe { self.pop().as_object().closure };
         let class = unsafe { (*self.peek(0)).as_object().class };
+        self.gc.write_barrier(method);
         unsafe { (*class).static_methods.insert(name, method) };
         Ok(())
     }
@@ -907,63 +1825,267 @@ impl VM {
     /// The next [`Value`] on the VM's stack is used as the [`ObjectClass`] for the method.
     fn op_method(&mut self) -> Result<()> {
         let name = unsafe { self.read_value().as_object().string };
+        self.check_operand(0, ObjectType::Closure)?;
+        self.check_operand(1, ObjectType::Class)?;
         let method = unsafe { self.pop().as_object().closure };
         let class = unsafe { (*self.peek(0)).as_object().class };
+        self.gc.write_barrier(method);
         unsafe { (*class).methods.insert(name, method) };
         Ok(())
     }
 
     /// Allocate memory for object
-    fn alloc<T>(&mut self, object: impl GcAlloc<T>) -> T {
+    ///
+    /// This raises a [`MemoryError`] instead if the object would put the heap
+    /// over [`VmConfig::max_heap_bytes`], even after collecting garbage.
+    fn alloc<T: Copy + Into<Object>>(&mut self, object: impl GcAlloc<T>) -> Result<T> {
+        match self.try_alloc(object) {
+            Ok(object) => Ok(object),
+            Err(e) => Err((e.into(), self.span())),
+        }
+    }
+
+    /// Allocate memory for object like [`VM::alloc`], without a span for
+    /// the error. Used by natives and when copying values from the host.
+    fn try_alloc<T: Copy + Into<Object>>(
+        &mut self,
+        object: impl GcAlloc<T>,
+    ) -> Result<T, MemoryError> {
+        // The limit is checked first so a large object is never allocated
+        // over it. Anything the object references is already reachable,
+        // since allocating may collect garbage anyway.
+        self.reserve(object.size(&self.gc))?;
+        Ok(self.alloc_unlimited(object))
+    }
+
+    /// Allocate memory for object even if the heap is over its limit. Used
+    /// when raising errors, and for upvalues since the limit is checked when
+    /// their closure is allocated.
+    fn alloc_unlimited<T>(&mut self, object: impl GcAlloc<T>) -> T {
         // If gc is on
         if !cfg!(feature = "gc-off")
             // If gc-stress is enabled
             && (cfg!(feature = "gc-stress")
             // Or memory needs to be freed
-            || GLOBAL.allocated_bytes() > self.next_gc)
+            || self.gc.allocated_bytes() > self.next_gc
+            // Or an incremental collection has started
+            || self.gc.is_marking()
+            || self.gc.is_sweeping())
         {
-            self.gc();
+            if cfg!(feature = "gc-incremental") {
+                self.gc_step();
+            } else {
+                self.gc();
+            }
         }
 
         // Allocate memory using the garbage collector
         self.gc.alloc(object)
     }
 
+    /// Count bytes that an object on the heap grew by (e.g. a list's values)
+    ///
+    /// This raises a [`MemoryError`] instead if they'd put the heap over
+    /// [`VmConfig::max_heap_bytes`], even after collecting garbage. `roots`
+    /// are kept alive while collecting, so they should include the object.
+    fn grow(&mut self, bytes: usize, roots: &[Value]) -> Result<()> {
+        let stack_top = self.stack_top;
+        for &root in roots {
+            self.push(root);
+        }
+        let result = self.reserve(bytes);
+        self.stack_top = stack_top;
+
+        match result {
+            Ok(()) => {
+                self.gc.grow(bytes);
+                Ok(())
+            }
+            Err(e) => self.err(e),
+        }
+    }
+
+    /// Check that `bytes` more fit on the heap under
+    /// [`VmConfig::max_heap_bytes`], collecting garbage if they don't
+    fn reserve(&mut self, bytes: usize) -> Result<(), MemoryError> {
+        if let Some(limit) = self.config.max_heap_bytes {
+            let fits = |vm: &Self| vm.gc.allocated_bytes().saturating_add(bytes) <= limit;
+            if !cfg!(feature = "gc-off") && !fits(self) {
+                self.gc();
+            }
+            if !fits(self) {
+                return Err(MemoryError::HeapLimitExceeded { limit });
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect garbage all at once
     fn gc(&mut self) {
+        let start = Instant::now();
         if cfg!(feature = "gc-trace") {
             eprintln!("-- gc begin");
         }
 
-        self.gc.mark(self.init_string);
+        // Objects created during an incremental collection survive it, so it's
+        // finished before collecting again
+        if self.gc.is_marking() {
+            self.mark_roots();
+            self.gc.trace();
+            self.gc.sweep();
+        } else {
+            self.gc.finish_sweep();
+        }
 
-        let mut stack_ptr = self.stack.as_ptr();
-        while stack_ptr < self.stack_top {
-            self.gc.mark(unsafe { *stack_ptr });
-            stack_ptr = unsafe { stack_ptr.add(1) };
+        self.mark_roots();
+        self.gc.trace();
+        self.gc.sweep();
+        self.verify_heap();
+        self.end_gc(start);
+    }
+
+    /// Check that no reachable object references one that's been freed, with
+    /// the `gc-verify` feature
+    fn verify_heap(&self) {
+        if cfg!(feature = "gc-verify") {
+            self.gc.verify(self.roots().into_iter().map(|(_, root)| root));
         }
+    }
 
-        for (&name, &value) in &self.globals {
-            self.gc.mark(name);
-            self.gc.mark(value);
+    /// Run a step of an incremental collection, starting one if needed
+    ///
+    /// Each step traces a few objects. Once there are none left the roots are
+    /// marked again, since writes to the stack & globals aren't behind write
+    /// barriers, and the following steps sweep a few objects each until the
+    /// collection finishes.
+    fn gc_step(&mut self) {
+        let start = Instant::now();
+        let finished = if self.gc.is_sweeping() {
+            self.gc.sweep_step(GC_STEP_OBJECTS)
+        } else {
+            if !self.gc.is_marking() {
+                if cfg!(feature = "gc-trace") {
+                    eprintln!("-- gc begin");
+                }
+                self.gc.start_marking();
+                self.mark_roots();
+            }
+
+            if self.gc.trace_step(GC_STEP_OBJECTS) {
+                self.mark_roots();
+                self.gc.trace();
+                self.gc.start_sweep();
+                self.gc.sweep_step(GC_STEP_OBJECTS)
+            } else {
+                false
+            }
+        };
+
+        if finished {
+            self.verify_heap();
+            self.end_gc(start);
+        } else {
+            self.gc.record_pause(start.elapsed());
+        }
+    }
+
+    fn end_gc(&mut self, start: Instant) {
+        self.next_gc = (self.gc.allocated_bytes() * self.config.gc_grow_factor)
+            .max(self.config.initial_threshold);
+        self.gc.record_pause(start.elapsed());
+
+        if cfg!(feature = "gc-trace") {
+            eprintln!("-- gc end");
         }
+    }
+
+    fn mark_roots(&mut self) {
+        for (_, root) in self.roots() {
+            self.gc.mark(root);
+        }
+    }
 
-        self.gc.mark(self.frame.closure);
+    /// The values that are reachable without going through another object,
+    /// with what they're called in a heap snapshot
+    fn roots(&self) -> Vec<(Edge, Value)> {
+        let mut roots = vec![
+            (Edge::Internal("init"), self.init_string.into()),
+            (Edge::Internal("message"), self.message_string.into()),
+        ];
+
+        let stack_len = unsafe { self.stack_top.offset_from(self.stack.as_ptr()) } as usize;
+        roots.extend(
+            self.stack[..stack_len]
+                .iter()
+                .enumerate()
+                .map(|(idx, &value)| (Edge::Element(idx), value)),
+        );
+
+        roots.extend(heap::properties(&self.globals));
+
+        // There's no frame until a program has run
+        if !self.frame.closure.is_null() {
+            roots.push((Edge::Internal("frame"), self.frame.closure.into()));
+        }
         for frame in &self.frames {
-            self.gc.mark(frame.closure);
+            roots.push((Edge::Internal("frame"), frame.closure.into()));
         }
 
         for &upvalue in &self.open_upvalues {
-            self.gc.mark(upvalue);
+            roots.push((Edge::Internal("upvalue"), upvalue.into()));
         }
 
-        self.gc.trace();
-        self.gc.sweep();
+        roots.extend(heap::properties(&self.packages));
 
-        self.next_gc = GLOBAL.allocated_bytes() * GC_HEAP_GROW_FACTOR;
+        for &name in &self.importing {
+            roots.push((Edge::Hidden, name.into()));
+        }
 
-        if cfg!(feature = "gc-trace") {
-            eprintln!("-- gc end");
+        for value in self.handles.borrow().values() {
+            roots.push((Edge::Internal("handle"), value));
+        }
+
+        for &value in &self.temporaries {
+            roots.push((Edge::Internal("temporary"), value));
         }
+
+        // Globals that aren't in scope are still alive, but they'd clutter a
+        // snapshot if they were named
+        for globals in self.suspended_globals.iter().chain(&self.prelude) {
+            for (&name, &value) in globals {
+                roots.extend([(Edge::Hidden, name.into()), (Edge::Hidden, value)]);
+            }
+        }
+
+        roots
+    }
+
+    /// What the garbage collector has done and what's on the heap
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc.stats()
+    }
+
+    /// Record the objects on the heap and the references between them
+    ///
+    /// Garbage is collected first, so the snapshot only has objects that are
+    /// still reachable.
+    pub fn heap_snapshot(&mut self) -> HeapSnapshot {
+        if !cfg!(feature = "gc-off") {
+            self.gc();
+        }
+        HeapSnapshot::new(self.gc.heap(), &self.roots())
+    }
+
+    /// Make the `heap_snapshot()` native write snapshots next to `path`,
+    /// numbered in the order they're taken (e.g. `out.1.json`, `out.2.json`).
+    /// It does nothing until this is set.
+    pub fn set_heap_snapshot_path(&mut self, path: impl Into<PathBuf>) {
+        self.heap_snapshot_path = Some(path.into());
+    }
+
+    /// Number of snapshots the program has written with `heap_snapshot()`
+    pub fn heap_snapshots_written(&self) -> usize {
+        self.heap_snapshots_written
     }
 
     /// Attempt to call [`Value`] as callable
@@ -1021,7 +2143,7 @@ impl VM {
     ///
     /// Calls the `init` method if it exists on the [`ObjectClass`].
     fn call_class(&mut self, class: *mut ObjectClass, arg_count: usize) -> Result<()> {
-        let instance = self.alloc(ObjectInstance::new(class));
+        let instance = self.alloc(ObjectInstance::new(class))?;
 
         // Replace the [`ObjectClass`] in the VM's stack with the new [`ObjectInstance`]
         unsafe { *self.peek(arg_count) = Value::from(instance) };
@@ -1078,7 +2200,7 @@ impl VM {
         arg_count: usize,
         stdout: &mut impl Write,
     ) -> Result<()> {
-        let value = match { unsafe { (*native).native } } {
+        let value = match unsafe { (*native).native } {
             Native::Clock => {
                 self.pop();
 
@@ -1118,7 +2240,13 @@ impl VM {
                     }
                     ObjectType::String => {
                         let string = unsafe { (obj).string };
-                        let length = unsafe { (*string).value.len() };
+                        let length = unsafe { (*string).value }.chars().count();
+
+                        (length as f64).into()
+                    }
+                    ObjectType::Map => {
+                        let map = unsafe { (obj).map };
+                        let length = unsafe { (*map).len() };
 
                         (length as f64).into()
                     }
@@ -1194,14 +2322,13 @@ impl VM {
                         ObjectType::Instance => "instance",
                         ObjectType::String => "string",
                         ObjectType::List => "list",
+                        ObjectType::Map => "map",
                         ObjectType::Package => "package",
                         ObjectType::Upvalue => "upvalue",
                     },
                 };
 
-                let type_str = self.alloc(ObjectString::new(&obj_type)).into();
-
-                type_str
+                self.alloc(ObjectString::new(obj_type))?.into()
             }
             Native::InstanceOf => {
                 if arg_count != 2 {
@@ -1229,6 +2356,7 @@ impl VM {
                         ObjectType::Instance => "instance",
                         ObjectType::String => "string",
                         ObjectType::List => "list",
+                        ObjectType::Map => "map",
                         ObjectType::Package => "package",
                         ObjectType::Upvalue => "upvalue",
                     },
@@ -1256,6 +2384,7 @@ impl VM {
                         ObjectType::Instance => "instance",
                         ObjectType::String => "string",
                         ObjectType::List => "list",
+                        ObjectType::Map => "map",
                         ObjectType::Package => "package",
                         ObjectType::Upvalue => "upvalue",
                     },
@@ -1275,6 +2404,112 @@ impl VM {
 
                 Value::from(same_class)
             }
+            Native::Assert => {
+                if arg_count != 1 {
+                    return self.err(TypeError::ArityMismatch {
+                        name: "assert".to_string(),
+                        exp_args: 1,
+                        got_args: arg_count,
+                    });
+                }
+
+                let value = self.pop();
+                self.pop();
+
+                if !value.to_bool() {
+                    return self.err(AssertionError::Failed);
+                }
+                Value::NIL
+            }
+            Native::AssertEq => {
+                if arg_count != 2 {
+                    return self.err(TypeError::ArityMismatch {
+                        name: "assert_eq".to_string(),
+                        exp_args: 2,
+                        got_args: arg_count,
+                    });
+                }
+
+                let expected = self.pop();
+                let actual = self.pop();
+                self.pop();
+
+                if actual != expected {
+                    return self.err(AssertionError::NotEqual {
+                        actual: actual.repr(),
+                        expected: expected.repr(),
+                    });
+                }
+                Value::NIL
+            }
+            Native::AssertNe => {
+                if arg_count != 2 {
+                    return self.err(TypeError::ArityMismatch {
+                        name: "assert_ne".to_string(),
+                        exp_args: 2,
+                        got_args: arg_count,
+                    });
+                }
+
+                let unexpected = self.pop();
+                let actual = self.pop();
+                self.pop();
+
+                if actual == unexpected {
+                    return self.err(AssertionError::Equal { unexpected: unexpected.repr() });
+                }
+                Value::NIL
+            }
+            Native::HeapSnapshot => {
+                self.pop();
+
+                if arg_count != 0 {
+                    return self.err(TypeError::ArityMismatch {
+                        name: "heap_snapshot".to_string(),
+                        exp_args: 0,
+                        got_args: arg_count,
+                    });
+                }
+
+                if let Some(path) = &self.heap_snapshot_path {
+                    let path = HeapSnapshot::numbered_path(path, self.heap_snapshots_written + 1);
+                    if self.heap_snapshot().save(&path).is_err() {
+                        return self.err(IoError::WriteError { file: path.display().to_string() });
+                    }
+                    self.heap_snapshots_written += 1;
+                }
+                Value::NIL
+            }
+            Native::StringMethod(method, receiver) => {
+                match self.call_string_method(method, receiver, arg_count) {
+                    Ok(value) => value,
+                    Err(e) => return self.err(e),
+                }
+            }
+            Native::Host(host) => {
+                if arg_count != host.arity {
+                    return self.err(TypeError::ArityMismatch {
+                        name: unsafe { (*host.name).value }.to_string(),
+                        exp_args: host.arity,
+                        got_args: arg_count,
+                    });
+                }
+
+                // The arguments stay on the stack during the call so they
+                // aren't collected
+                let args =
+                    unsafe { slice::from_raw_parts(self.stack_top.sub(arg_count), arg_count) }
+                        .to_vec();
+                let temporaries = self.temporaries.len();
+                let result = (host.function)(&mut VmCtx::new(self), &args);
+                self.temporaries.truncate(temporaries);
+                self.stack_top = unsafe { self.stack_top.sub(arg_count + 1) };
+
+                match result {
+                    Ok(value) => value,
+                    Err(e) => return self.err(e),
+                }
+            }
         };
 
         self.push(value);
@@ -1336,7 +2571,8 @@ impl VM {
     fn read_value(&mut self) -> Value {
         let constant_idx = self.read_u8() as usize;
         let function = unsafe { (*self.frame.closure).function };
-        *unsafe { (*function).chunk.constants.get_unchecked(constant_idx) }
+        let constants = unsafe { &(*function).chunk.constants };
+        *unsafe { constants.get_unchecked(constant_idx) }
     }
 
     /// Pushes a [`Value`] to the stack.
@@ -1367,7 +2603,7 @@ impl VM {
         {
             Some(&upvalue) => upvalue,
             None => {
-                let upvalue = self.alloc(ObjectUpvalue::new(location));
+                let upvalue = self.alloc_unlimited(ObjectUpvalue::new(location));
                 self.open_upvalues.push(upvalue);
                 upvalue
             }
@@ -1378,21 +2614,40 @@ impl VM {
         for idx in (0..self.open_upvalues.len()).rev() {
             let upvalue = *unsafe { self.open_upvalues.get_unchecked(idx) };
             if last <= unsafe { (*upvalue).location } {
-                unsafe { (*upvalue).closed = *(*upvalue).location };
+                let value = unsafe { *(*upvalue).location };
+                self.gc.write_barrier(value);
+                unsafe { (*upvalue).closed = value };
                 unsafe { (*upvalue).location = &mut (*upvalue).closed };
                 self.open_upvalues.swap_remove(idx);
             }
         }
     }
 
+    /// Check the type of the value `n` slots back in the stack, for ops that
+    /// the compiler only emits with an object of that type there. Compiled
+    /// files may have been written by something else.
+    fn check_operand(&mut self, n: usize, type_: ObjectType) -> Result<()> {
+        let value = unsafe { *self.peek(n) };
+        if value.is_object() && value.as_object().type_() == type_ {
+            Ok(())
+        } else {
+            self.err(BytecodeError::Invalid { item: format!("{type_} operand") })
+        }
+    }
+
     /// Wraps an [`Error`] in a span using the offset of the last executed
     /// instruction.
     #[cold]
     fn err(&self, err: impl Into<Error>) -> Result<()> {
+        Err((err.into(), self.span()))
+    }
+
+    /// The span of the op that's running
+    fn span(&self) -> Span {
         let function = unsafe { (*self.frame.closure).function };
         let idx = unsafe { self.frame.ip.offset_from((*function).chunk.ops.as_ptr()) } as usize;
-        let span = unsafe { (*function).chunk.spans[idx - 1].clone() };
-        Err((err.into(), span))
+        let spans = unsafe { &(*function).chunk.spans };
+        spans[idx - 1].clone()
     }
 }
 
@@ -1403,6 +2658,7 @@ impl Default for VM {
         let mut globals = HashMap::with_capacity_and_hasher(256, BuildHasherDefault::default());
 
         let init_string = gc.alloc("init");
+        let message_string = gc.alloc("message");
 
         globals.insert(gc.alloc("clock"), gc.alloc(ObjectNative::new(Native::Clock)).into());
         globals.insert(gc.alloc("len"), gc.alloc(ObjectNative::new(Native::Length)).into());
@@ -1411,13 +2667,33 @@ impl Default for VM {
         globals.insert(gc.alloc("typeof"), gc.alloc(ObjectNative::new(Native::TypeOf)).into());
         globals
             .insert(gc.alloc("instanceof"), gc.alloc(ObjectNative::new(Native::InstanceOf)).into());
+        globals.insert(gc.alloc("assert"), gc.alloc(ObjectNative::new(Native::Assert)).into());
+        globals.insert(gc.alloc("assert_eq"), gc.alloc(ObjectNative::new(Native::AssertEq)).into());
+        globals.insert(gc.alloc("assert_ne"), gc.alloc(ObjectNative::new(Native::AssertNe)).into());
+        globals.insert(
+            gc.alloc("heap_snapshot"),
+            gc.alloc(ObjectNative::new(Native::HeapSnapshot)).into(),
+        );
 
-        let vm = Self {
+        let mut vm = Self {
             globals,
             open_upvalues: Vec::with_capacity(256),
+            packages: HashMap::default(),
+            package_paths: Vec::new(),
+            importing: Vec::new(),
+            suspended_globals: Vec::new(),
+            prelude: None,
             gc,
-            next_gc: 1024 * 1024,
+            next_gc: VmConfig::default().initial_threshold,
+            config: VmConfig::default(),
             frames: ArrayVec::new(),
+            handlers: Vec::new(),
+            budget: None,
+            suspended: false,
+            heap_snapshot_path: None,
+            heap_snapshots_written: 0,
+            handles: Rc::default(),
+            temporaries: Vec::new(),
             frame: CallFrame {
                 closure: ptr::null_mut(),
                 ip: ptr::null_mut(),
@@ -1426,13 +2702,27 @@ impl Default for VM {
             stack: Box::new([Value::default(); STACK_MAX]),
             stack_top: ptr::null_mut(),
             init_string,
+            message_string,
             source: String::new(),
+            sources: Vec::new(),
+            debugger: None,
+            profiler: None,
         };
-
+        // The host can allocate before a program runs
+        vm.stack_top = vm.stack.as_mut_ptr();
         vm
     }
 }
 
+/// Where to resume running when an error is raised inside a `try` statement
+#[derive(Debug)]
+struct ErrorHandler {
+    /// The number of frames below the frame that registered the handler
+    frames_len: usize,
+    stack_top: *mut Value,
+    ip: *const u8,
+}
+
 #[derive(Debug)]
 pub struct CallFrame {
     closure: *mut ObjectClosure,
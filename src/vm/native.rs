@@ -0,0 +1,73 @@
+use crate::error::Error;
+use crate::vm::host::HostValue;
+use crate::vm::object::{ObjectList, ObjectType};
+use crate::vm::value::Value;
+use crate::vm::VM;
+
+/// Signature of a function registered with [`VM::register_native`]
+///
+/// It's called with the arguments passed to it in Locks, already checked
+/// against the registered arity. Returning an [`Error`] raises it in the
+/// running program where it can be caught with `try`/`catch`.
+pub type NativeFn = fn(&mut VmCtx, &[Value]) -> Result<Value, Error>;
+
+/// Access to the [`VM`] from inside a [`NativeFn`]
+///
+/// Objects allocated through the context are owned by the garbage collector.
+/// Allocating may collect garbage, but values created during a call stay
+/// valid until the native function returns. Allocating raises a
+/// `MemoryError` instead if the heap is over [`VmConfig::max_heap_bytes`]
+/// after collecting.
+///
+/// [`VmConfig::max_heap_bytes`]: crate::vm::VmConfig::max_heap_bytes
+pub struct VmCtx<'a> {
+    vm: &'a mut VM,
+}
+
+impl<'a> VmCtx<'a> {
+    pub(crate) fn new(vm: &'a mut VM) -> Self {
+        Self { vm }
+    }
+
+    /// Allocate a Locks string
+    pub fn alloc_string(&mut self, value: &str) -> Result<Value, Error> {
+        let value = self.vm.try_alloc(value)?.into();
+        self.vm.temporaries.push(value);
+        Ok(value)
+    }
+
+    /// Allocate a Locks list containing `values`
+    pub fn alloc_list(&mut self, values: Vec<Value>) -> Result<Value, Error> {
+        let value = self.vm.try_alloc(ObjectList::new(values))?.into();
+        self.vm.temporaries.push(value);
+        Ok(value)
+    }
+
+    /// Allocate a copy of a [`HostValue`]
+    ///
+    /// # Panics
+    ///
+    /// If it contains an [`ObjectHandle`](crate::vm::ObjectHandle) from
+    /// another [`VM`].
+    pub fn alloc_host(&mut self, value: &HostValue) -> Result<Value, Error> {
+        Ok(self.vm.alloc_host(value)?)
+    }
+
+    /// Returns the contents of `value` if it's a string
+    pub fn as_str(&self, value: Value) -> Option<&str> {
+        if value.is_object() && value.as_object().type_() == ObjectType::String {
+            Some(unsafe { (*value.as_object().string).value })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the items of `value` if it's a list
+    pub fn as_list(&self, value: Value) -> Option<&[Value]> {
+        if value.is_object() && value.as_object().type_() == ObjectType::List {
+            Some(unsafe { &(*value.as_object().list).values })
+        } else {
+            None
+        }
+    }
+}
@@ -7,6 +7,7 @@ use rustc_hash::FxHasher;
 
 use crate::vm::chunk::Chunk;
 use crate::vm::value::Value;
+use crate::vm::{NativeFn, StringMethod};
 
 const _: () = assert!(mem::size_of::<Object>() == 4 || mem::size_of::<Object>() == 8);
 
@@ -22,6 +23,7 @@ pub union Object {
     pub native: *mut ObjectNative,
     pub string: *mut ObjectString,
     pub list: *mut ObjectList,
+    pub map: *mut ObjectMap,
     pub package: *mut ObjectPackage,
     pub upvalue: *mut ObjectUpvalue,
 }
@@ -33,6 +35,63 @@ impl Object {
         unsafe { (*self.common).type_ }
     }
 
+    /// An estimate of the bytes the [`Object`] uses, including the buffers it
+    /// owns (e.g. a list's values)
+    pub fn size(self) -> usize {
+        /// The bytes used by a hash map's entries
+        fn entries<K, V>(map: &HashMap<K, V, BuildHasherDefault<FxHasher>>) -> usize {
+            map.capacity() * mem::size_of::<(K, V)>()
+        }
+
+        unsafe {
+            match self.type_() {
+                ObjectType::BoundMethod => mem::size_of::<ObjectBoundMethod>(),
+                ObjectType::Class => {
+                    let class = &*self.class;
+                    mem::size_of::<ObjectClass>()
+                        + entries(&class.methods)
+                        + entries(&class.fields)
+                        + entries(&class.static_fields)
+                        + entries(&class.static_methods)
+                }
+                ObjectType::Closure => {
+                    let closure = &*self.closure;
+                    mem::size_of::<ObjectClosure>()
+                        + closure.upvalues.capacity() * mem::size_of::<*mut ObjectUpvalue>()
+                }
+                ObjectType::Function => {
+                    let function = &*self.function;
+                    mem::size_of::<ObjectFunction>() + function.chunk.ops.capacity()
+                }
+                ObjectType::Instance => {
+                    mem::size_of::<ObjectInstance>() + entries(&(*self.instance).fields)
+                }
+                ObjectType::Native => mem::size_of::<ObjectNative>(),
+                ObjectType::String => {
+                    let string = &*self.string;
+                    mem::size_of::<ObjectString>() + string.value.len()
+                }
+                // Lists & maps are counted by their length rather than their
+                // capacity, the same as when they grow, so the heap limit can
+                // be checked before they do
+                ObjectType::List => {
+                    let list = &*self.list;
+                    mem::size_of::<ObjectList>() + list.values.len() * mem::size_of::<Value>()
+                }
+                ObjectType::Map => {
+                    let map = &*self.map;
+                    mem::size_of::<ObjectMap>()
+                        + map.len()
+                            * (mem::size_of::<(Value, Value)>() + mem::size_of::<(Value, usize)>())
+                }
+                ObjectType::Package => {
+                    mem::size_of::<ObjectPackage>() + entries(&(*self.package).globals)
+                }
+                ObjectType::Upvalue => mem::size_of::<ObjectUpvalue>(),
+            }
+        }
+    }
+
     /// Frees the value being pointed to by the [`Object`], based on its type.
     pub fn free(self) {
         match self.type_() {
@@ -76,6 +135,11 @@ impl Object {
                     let _ = Box::from_raw(self.list);
                 };
             }
+            ObjectType::Map => {
+                unsafe {
+                    let _ = Box::from_raw(self.map);
+                };
+            }
             ObjectType::Package => {
                 unsafe {
                     let _ = Box::from_raw(self.package);
@@ -101,8 +165,7 @@ impl Display for Object {
         match self.type_() {
             ObjectType::BoundMethod => {
                 write!(f, "<bound method {}>", unsafe {
-                    let value = (*(*(*(*self.bound_method).closure).function).name).value;
-                    value
+                    (*(*(*(*self.bound_method).closure).function).name).value
                 })
             }
             ObjectType::Class => {
@@ -130,23 +193,18 @@ impl Display for Object {
                     "[{}]",
                     unsafe { (*self.list).values.clone() }
                         .into_iter()
-                        .map(|x| {
-                            if x.is_object() {
-                                let f = match x.as_object().type_() {
-                                    ObjectType::String => {
-                                        format!("{:?}", unsafe { (*x.as_object().string).value })
-                                    }
-                                    _ => format!("{:?}", x),
-                                };
-
-                                f.trim().to_string()
-                            } else {
-                                let f = format!("{:?}", x);
-
-                                f.trim().to_string()
-                            }
-                        })
-                        .map(|x| x)
+                        .map(Value::repr)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                write!(f, "{}", v)
+            }
+            ObjectType::Map => {
+                let v = format!(
+                    "{{{}}}",
+                    unsafe { &(*self.map).entries }
+                        .iter()
+                        .map(|&(key, value)| format!("{}: {}", key.repr(), value.repr()))
                         .collect::<Vec<_>>()
                         .join(", ")
                 );
@@ -179,6 +237,7 @@ impl_from_object!(instance, ObjectInstance);
 impl_from_object!(native, ObjectNative);
 impl_from_object!(string, ObjectString);
 impl_from_object!(list, ObjectList);
+impl_from_object!(map, ObjectMap);
 impl_from_object!(package, ObjectPackage);
 impl_from_object!(upvalue, ObjectUpvalue);
 
@@ -206,10 +265,27 @@ pub enum ObjectType {
     Instance,
     String,
     List,
+    Map,
     Package,
     Upvalue,
 }
 
+impl ObjectType {
+    pub const ALL: [ObjectType; 11] = [
+        ObjectType::BoundMethod,
+        ObjectType::Class,
+        ObjectType::Closure,
+        ObjectType::Function,
+        ObjectType::Native,
+        ObjectType::Instance,
+        ObjectType::String,
+        ObjectType::List,
+        ObjectType::Map,
+        ObjectType::Package,
+        ObjectType::Upvalue,
+    ];
+}
+
 impl Display for ObjectType {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
@@ -221,6 +297,7 @@ impl Display for ObjectType {
             ObjectType::Native => write!(f, "native"),
             ObjectType::String => write!(f, "string"),
             ObjectType::List => write!(f, "list"),
+            ObjectType::Map => write!(f, "map"),
             ObjectType::Package => write!(f, "package"),
             ObjectType::Upvalue => write!(f, "upvalue"),
         }
@@ -393,7 +470,7 @@ impl ObjectNative {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug)]
 pub enum Native {
     Clock,
     Length,
@@ -401,6 +478,24 @@ pub enum Native {
     PrintLn,
     TypeOf,
     InstanceOf,
+    Assert,
+    AssertEq,
+    AssertNe,
+    /// Write a heap snapshot to the path set by
+    /// [`VM::set_heap_snapshot_path`](crate::vm::VM::set_heap_snapshot_path)
+    HeapSnapshot,
+    /// A string's method (e.g. `"abc".upper`) and the string it's called on
+    StringMethod(StringMethod, *mut ObjectString),
+    /// Function registered by the embedding application with
+    /// [`VM::register_native`](crate::vm::VM::register_native)
+    Host(HostNative),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HostNative {
+    pub name: *mut ObjectString,
+    pub arity: usize,
+    pub function: NativeFn,
 }
 
 impl Display for Native {
@@ -412,6 +507,12 @@ impl Display for Native {
             Native::PrintLn => write!(f, "println"),
             Native::TypeOf => write!(f, "typeof"),
             Native::InstanceOf => write!(f, "instanceof"),
+            Native::Assert => write!(f, "assert"),
+            Native::AssertEq => write!(f, "assert_eq"),
+            Native::AssertNe => write!(f, "assert_ne"),
+            Native::HeapSnapshot => write!(f, "heap_snapshot"),
+            Native::StringMethod(method, _) => write!(f, "{}", method.name()),
+            Native::Host(host) => write!(f, "{}", unsafe { (*host.name).value }),
         }
     }
 }
@@ -444,17 +545,68 @@ impl ObjectList {
     }
 }
 
+/// Key value pairs where the keys are compared by identity
+///
+/// Strings are interned so equal strings are the same key.
+#[derive(Debug)]
+#[repr(C)]
+pub struct ObjectMap {
+    pub common: ObjectCommon,
+    /// Entries in the order their keys were first inserted
+    pub entries: Vec<(Value, Value)>,
+    /// Index of each key's entry in `entries`
+    indexes: HashMap<Value, usize, BuildHasherDefault<FxHasher>>,
+}
+
+impl ObjectMap {
+    pub fn new(entries: Vec<(Value, Value)>) -> Self {
+        let common = ObjectCommon { type_: ObjectType::Map, is_marked: false };
+        let mut map = Self { common, entries: Vec::new(), indexes: HashMap::default() };
+
+        for (key, value) in entries {
+            map.insert(key, value);
+        }
+
+        map
+    }
+
+    pub fn get(&self, key: Value) -> Option<Value> {
+        self.indexes.get(&key).map(|&idx| self.entries[idx].1)
+    }
+
+    /// Insert or replace the value for a key
+    pub fn insert(&mut self, key: Value, value: Value) {
+        match self.indexes.get(&key) {
+            Some(&idx) => self.entries[idx].1 = value,
+            None => {
+                self.indexes.insert(key, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct ObjectPackage {
     pub common: ObjectCommon,
     pub name: *mut ObjectString,
+    /// Globals defined by the package's source file once it's been imported
+    pub globals: HashMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>,
 }
 
 impl ObjectPackage {
     pub fn new(name: *mut ObjectString) -> Self {
         let common = ObjectCommon { type_: ObjectType::Package, is_marked: false };
-        Self { common, name }
+        Self { common, name, globals: HashMap::default() }
     }
 }
 
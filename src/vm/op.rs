@@ -89,5 +89,20 @@ iota! {
     // Reads a 1 byte offset for the package name
     PACKAGE,
     STATIC_FIELD,
-    STATIC_METHOD
+    STATIC_METHOD,
+    // Reads a 1 byte offset for the package name, and pushes the imported
+    // package onto the stack.
+    GET_PACKAGE,
+    // Reads a 2-byte offset, and registers an error handler at the instruction
+    // pointer incremented by that offset. Errors raised before the matching
+    // END_TRY unwind the stack and jump to the handler with the error pushed
+    // onto the stack.
+    TRY,
+    // Removes the most recently registered error handler.
+    END_TRY,
+    // Pops a value from the stack and raises it as an error.
+    THROW,
+    // Reads a 1-byte count, pops that many key & value pairs from the stack,
+    // and pushes a map of them onto the stack.
+    CREATE_MAP
 }
@@ -0,0 +1,330 @@
+use std::hash::BuildHasherDefault;
+use std::iter;
+use std::time::Duration;
+
+use hashbrown::HashMap;
+use rustc_hash::FxHasher;
+
+use crate::vm::debug::{self, Location};
+use crate::vm::object::{ObjectClosure, ObjectFunction};
+use crate::vm::util::Instant;
+use crate::vm::value::Value;
+use crate::vm::{CallFrame, VM};
+
+/// Times the functions & lines of a program as it runs
+///
+/// Set it with [`VM::set_profiler`], then take it back with
+/// [`VM::take_profiler`] once the program finishes and call
+/// [`Profiler::finish`] for the results.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    functions: Vec<FunctionStats>,
+    function_ids: HashMap<*mut ObjectFunction, usize, BuildHasherDefault<FxHasher>>,
+    lines: Vec<LineStats>,
+    /// Lines by the offset in the VM's source that they start at
+    line_ids: HashMap<usize, usize, BuildHasherDefault<FxHasher>>,
+    /// Call stacks that have run, as a tree of functions
+    nodes: Vec<Node>,
+    node_ids: HashMap<(Option<usize>, usize), usize, BuildHasherDefault<FxHasher>>,
+    /// The [`VM`]'s frames when the last op ran, outermost first
+    stack: Vec<Entry>,
+    /// When the first op ran
+    start: Option<Instant>,
+    /// When the last op ran
+    last: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct FunctionStats {
+    name: String,
+    /// The offset of the function's first op, which tells functions apart if
+    /// one is freed and another is allocated in its place
+    start: usize,
+    /// The line each of the function's ops is on
+    op_lines: Vec<usize>,
+    calls: u64,
+    self_time: Duration,
+    timer: Timer,
+}
+
+#[derive(Debug)]
+struct LineStats {
+    start: usize,
+    /// Calls to functions made from the line
+    calls: u64,
+    self_time: Duration,
+    timer: Timer,
+}
+
+#[derive(Debug)]
+struct Node {
+    parent: Option<usize>,
+    function: usize,
+    self_time: Duration,
+}
+
+#[derive(Debug)]
+struct Entry {
+    closure: *mut ObjectClosure,
+    stack: *mut Value,
+    function: usize,
+    line: usize,
+    node: usize,
+}
+
+/// Time that a function or line is on the stack. Recursive calls are only
+/// timed once.
+#[derive(Debug, Default)]
+struct Timer {
+    /// How many times it's on the stack
+    active: u32,
+    since: Option<Instant>,
+    total: Duration,
+}
+
+impl Timer {
+    fn start(&mut self, now: Instant) {
+        if self.active == 0 {
+            self.since = Some(now);
+        }
+        self.active += 1;
+    }
+
+    fn stop(&mut self, now: Instant) {
+        self.active -= 1;
+        if self.active == 0 {
+            if let Some(since) = self.since.take() {
+                self.total += now.duration_since(since);
+            }
+        }
+    }
+}
+
+/// What a [`Profiler`] measured
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    /// Time from the first op to [`Profiler::finish`]
+    pub total_time: Duration,
+    /// Functions in the order they were first called
+    pub functions: Vec<FunctionProfile>,
+    /// Lines in the order they first ran
+    pub lines: Vec<LineProfile>,
+    /// Time spent in each call stack, as indexes into
+    /// [`Profile::functions`] starting with the outermost function
+    pub stacks: Vec<(Vec<usize>, Duration)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionProfile {
+    /// The function's name, or an empty string for the top level of a file
+    pub name: String,
+    /// Where the function's body starts
+    pub location: Option<Location>,
+    pub calls: u64,
+    /// Time spent running the function's own code
+    pub self_time: Duration,
+    /// Time spent in the function, including the functions it calls
+    pub total_time: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineProfile {
+    pub location: Option<Location>,
+    /// Calls to functions made from the line
+    pub calls: u64,
+    /// Time spent running the line's own code
+    pub self_time: Duration,
+    /// Time spent on the line, including the functions it calls
+    pub total_time: Duration,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the time since the last op towards it, then catch up with calls
+    /// & returns that it made
+    pub(super) fn before_op(&mut self, vm: &VM) {
+        let now = Instant::now();
+        self.start.get_or_insert(now);
+        self.record(now);
+
+        // Frames that returned or were unwound by an error
+        let depth = vm.frames.len() + 1;
+        while let Some(entry) = self.stack.last() {
+            let frame = frame_at(vm, self.stack.len() - 1);
+            if frame
+                .is_some_and(|frame| entry.closure == frame.closure && entry.stack == frame.stack)
+            {
+                break;
+            }
+            self.pop(now);
+        }
+
+        // Frames that were called
+        for idx in self.stack.len()..depth {
+            if let Some(frame) = frame_at(vm, idx) {
+                self.push(vm, frame, idx + 1 == depth, now);
+            }
+        }
+
+        // The line of the op that's about to run
+        if let Some(entry) = self.stack.last_mut() {
+            let line = self.functions[entry.function].op_lines[op_idx(&vm.frame, true)];
+            if line != entry.line {
+                self.lines[entry.line].timer.stop(now);
+                self.lines[line].timer.start(now);
+                entry.line = line;
+            }
+        }
+    }
+
+    /// Stop timing and collect the results
+    pub fn finish(mut self, vm: &VM) -> Profile {
+        let now = Instant::now();
+        self.record(now);
+        while !self.stack.is_empty() {
+            self.pop(now);
+        }
+
+        let stacks = self
+            .nodes
+            .iter()
+            .filter(|node| !node.self_time.is_zero())
+            .map(|node| {
+                let mut stack = vec![node.function];
+                let mut parent = node.parent;
+                while let Some(idx) = parent {
+                    stack.push(self.nodes[idx].function);
+                    parent = self.nodes[idx].parent;
+                }
+                stack.reverse();
+                (stack, node.self_time)
+            })
+            .collect();
+
+        Profile {
+            total_time: self.start.map_or(Duration::ZERO, |start| now.duration_since(start)),
+            functions: self
+                .functions
+                .into_iter()
+                .map(|function| FunctionProfile {
+                    location: debug::location(vm, function.start),
+                    name: function.name,
+                    calls: function.calls,
+                    self_time: function.self_time,
+                    total_time: function.timer.total,
+                })
+                .collect(),
+            lines: self
+                .lines
+                .into_iter()
+                .map(|line| LineProfile {
+                    location: debug::location(vm, line.start),
+                    calls: line.calls,
+                    self_time: line.self_time,
+                    total_time: line.timer.total,
+                })
+                .collect(),
+            stacks,
+        }
+    }
+
+    /// Count the time since the last op towards the function & line it ran in
+    fn record(&mut self, now: Instant) {
+        if let (Some(entry), Some(last)) = (self.stack.last(), self.last) {
+            let elapsed = now.duration_since(last);
+            self.functions[entry.function].self_time += elapsed;
+            self.lines[entry.line].self_time += elapsed;
+            self.nodes[entry.node].self_time += elapsed;
+        }
+        self.last = Some(now);
+    }
+
+    fn push(&mut self, vm: &VM, frame: &CallFrame, is_current: bool, now: Instant) {
+        let function = self.function_id(vm, unsafe { (*frame.closure).function });
+        let line = self.functions[function].op_lines[op_idx(frame, is_current)];
+        let parent = self.stack.last().map(|parent| (parent.line, parent.node));
+
+        self.functions[function].calls += 1;
+        self.functions[function].timer.start(now);
+        self.lines[line].timer.start(now);
+        if let Some((parent_line, _)) = parent {
+            self.lines[parent_line].calls += 1;
+        }
+
+        let key = (parent.map(|(_, parent_node)| parent_node), function);
+        let node = *self.node_ids.entry(key).or_insert_with(|| {
+            self.nodes.push(Node { parent: key.0, function, self_time: Duration::ZERO });
+            self.nodes.len() - 1
+        });
+
+        self.stack.push(Entry { closure: frame.closure, stack: frame.stack, function, line, node });
+    }
+
+    fn pop(&mut self, now: Instant) {
+        if let Some(entry) = self.stack.pop() {
+            self.functions[entry.function].timer.stop(now);
+            self.lines[entry.line].timer.stop(now);
+        }
+    }
+
+    fn function_id(&mut self, vm: &VM, ptr: *mut ObjectFunction) -> usize {
+        let function = unsafe { &*ptr };
+        let start = function.chunk.spans.runs().next().map_or(0, |(span, _)| span.start);
+
+        match self.function_ids.get(&ptr) {
+            Some(&id) if self.functions[id].start == start => return id,
+            _ => {}
+        }
+
+        let source = vm.source.as_bytes();
+        let mut op_lines = Vec::with_capacity(function.chunk.ops.len());
+        for (span, count) in function.chunk.spans.runs() {
+            let offset = span.start.min(source.len());
+            let line_start =
+                source[..offset].iter().rposition(|&byte| byte == b'\n').map_or(0, |idx| idx + 1);
+            let line = *self.line_ids.entry(line_start).or_insert_with(|| {
+                self.lines.push(LineStats {
+                    start: line_start,
+                    calls: 0,
+                    self_time: Duration::ZERO,
+                    timer: Timer::default(),
+                });
+                self.lines.len() - 1
+            });
+            op_lines.extend(iter::repeat(line).take(count as usize));
+        }
+
+        self.functions.push(FunctionStats {
+            name: unsafe { (*function.name).value }.to_string(),
+            start,
+            op_lines,
+            calls: 0,
+            self_time: Duration::ZERO,
+            timer: Timer::default(),
+        });
+        let id = self.functions.len() - 1;
+        self.function_ids.insert(ptr, id);
+        id
+    }
+}
+
+/// The [`VM`]'s frame at a depth, starting with the outermost
+fn frame_at(vm: &VM, idx: usize) -> Option<&CallFrame> {
+    if idx == vm.frames.len() {
+        Some(&vm.frame)
+    } else {
+        vm.frames.get(idx)
+    }
+}
+
+/// The op a frame is running. Callers have already read the call they're
+/// waiting on.
+fn op_idx(frame: &CallFrame, is_current: bool) -> usize {
+    let function = unsafe { &*(*frame.closure).function };
+    let idx = unsafe { frame.ip.offset_from(function.chunk.ops.as_ptr()) } as usize;
+    let idx = if is_current { idx } else { idx.saturating_sub(1) };
+    idx.min(function.chunk.ops.len().saturating_sub(1))
+}
@@ -0,0 +1,238 @@
+use std::slice;
+
+use crate::error::{Error, IndexError, MemoryError, TypeError};
+use crate::vm::object::{ObjectList, ObjectString, ObjectType};
+use crate::vm::value::Value;
+use crate::vm::VM;
+
+/// A method that can be called on a string (e.g. `"abc".upper()`)
+///
+/// Indexes count characters rather than bytes, the same as `len()` and
+/// indexing a string.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StringMethod {
+    Substring,
+    IndexOf,
+    Split,
+    Join,
+    Trim,
+    Upper,
+    Lower,
+    Replace,
+    StartsWith,
+    EndsWith,
+    ToNumber,
+}
+
+impl StringMethod {
+    pub const ALL: [StringMethod; 11] = [
+        StringMethod::Substring,
+        StringMethod::IndexOf,
+        StringMethod::Split,
+        StringMethod::Join,
+        StringMethod::Trim,
+        StringMethod::Upper,
+        StringMethod::Lower,
+        StringMethod::Replace,
+        StringMethod::StartsWith,
+        StringMethod::EndsWith,
+        StringMethod::ToNumber,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|method| method.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StringMethod::Substring => "substring",
+            StringMethod::IndexOf => "index_of",
+            StringMethod::Split => "split",
+            StringMethod::Join => "join",
+            StringMethod::Trim => "trim",
+            StringMethod::Upper => "upper",
+            StringMethod::Lower => "lower",
+            StringMethod::Replace => "replace",
+            StringMethod::StartsWith => "starts_with",
+            StringMethod::EndsWith => "ends_with",
+            StringMethod::ToNumber => "to_number",
+        }
+    }
+
+    pub fn params(self) -> &'static [&'static str] {
+        match self {
+            StringMethod::Substring => &["start", "end"],
+            StringMethod::IndexOf => &["needle"],
+            StringMethod::Split => &["separator"],
+            StringMethod::Join => &["list"],
+            StringMethod::Trim | StringMethod::Upper | StringMethod::Lower => &[],
+            StringMethod::Replace => &["from", "to"],
+            StringMethod::StartsWith => &["prefix"],
+            StringMethod::EndsWith => &["suffix"],
+            StringMethod::ToNumber => &[],
+        }
+    }
+}
+
+impl VM {
+    /// Call a [`StringMethod`] on `receiver` with the arguments on top of the
+    /// stack, then pop them and the method
+    ///
+    /// The method and arguments stay on the stack until the call returns so
+    /// they aren't collected while it allocates.
+    pub(super) fn call_string_method(
+        &mut self,
+        method: StringMethod,
+        receiver: *mut ObjectString,
+        arg_count: usize,
+    ) -> Result<Value, Error> {
+        let params = method.params();
+        if arg_count != params.len() {
+            return Err(TypeError::ArityMismatch {
+                name: method.name().to_string(),
+                exp_args: params.len(),
+                got_args: arg_count,
+            }
+            .into());
+        }
+
+        let args =
+            unsafe { slice::from_raw_parts(self.stack_top.sub(arg_count), arg_count) }.to_vec();
+        let string = unsafe { (*receiver).value };
+
+        let value = match method {
+            StringMethod::Substring => {
+                let start = char_index(args[0])?;
+                let end = char_index(args[1])?;
+                let length = string.chars().count();
+                if end > length || start > end {
+                    let wanted_index = end.max(start);
+                    return Err(IndexError::OutOfBounds { wanted_index, length }.into());
+                }
+
+                let start = byte_offset(string, start);
+                let end = byte_offset(string, end);
+                self.try_alloc(&string[start..end])?.into()
+            }
+            StringMethod::IndexOf => {
+                let needle = string_arg(args[0])?;
+                match string.find(needle) {
+                    Some(idx) => (string[..idx].chars().count() as f64).into(),
+                    None => (-1.0).into(),
+                }
+            }
+            StringMethod::Split => {
+                let separator = string_arg(args[0])?;
+                // Splitting on nothing splits the string into its characters
+                let parts = if separator.is_empty() {
+                    string.char_indices().map(|(idx, c)| &string[idx..idx + c.len_utf8()]).collect()
+                } else {
+                    string.split(separator).collect::<Vec<_>>()
+                };
+
+                // The parts are kept alive until they're in the list
+                let temporaries = self.temporaries.len();
+                let list = parts
+                    .into_iter()
+                    .map(|part| {
+                        let part = self.try_alloc(part)?.into();
+                        self.temporaries.push(part);
+                        Ok(part)
+                    })
+                    .collect::<Result<_, MemoryError>>()
+                    .and_then(|values| self.try_alloc(ObjectList::new(values)));
+                self.temporaries.truncate(temporaries);
+                list?.into()
+            }
+            StringMethod::Join => {
+                let list = args[0];
+                if !list.is_object() || list.as_object().type_() != ObjectType::List {
+                    return Err(TypeError::InvalidType {
+                        expected_type: "list".to_string(),
+                        actual_type: list.type_().to_string(),
+                    }
+                    .into());
+                }
+
+                let mut joined = String::new();
+                for (idx, &value) in unsafe { &(*list.as_object().list).values }.iter().enumerate()
+                {
+                    if idx > 0 {
+                        joined += string;
+                    }
+                    joined += string_arg(value)?;
+                }
+                self.try_alloc(joined)?.into()
+            }
+            StringMethod::Trim => self.try_alloc(string.trim())?.into(),
+            StringMethod::Upper => self.try_alloc(string.to_uppercase())?.into(),
+            StringMethod::Lower => self.try_alloc(string.to_lowercase())?.into(),
+            StringMethod::Replace => {
+                let from = string_arg(args[0])?;
+                let to = string_arg(args[1])?;
+                self.try_alloc(string.replace(from, to))?.into()
+            }
+            StringMethod::StartsWith => {
+                let prefix = string_arg(args[0])?;
+                string.starts_with(prefix).into()
+            }
+            StringMethod::EndsWith => {
+                let suffix = string_arg(args[0])?;
+                string.ends_with(suffix).into()
+            }
+            // Strings that aren't a finite number parse to `nil`
+            StringMethod::ToNumber => match string.trim().parse::<f64>() {
+                Ok(number) if number.is_finite() => number.into(),
+                _ => Value::NIL,
+            },
+        };
+
+        self.stack_top = unsafe { self.stack_top.sub(arg_count + 1) };
+        Ok(value)
+    }
+
+    /// Get the character at an index of a string
+    pub(super) fn string_index(
+        &mut self,
+        string: *mut ObjectString,
+        index: Value,
+    ) -> Result<Value, Error> {
+        let string = unsafe { (*string).value };
+        let idx = char_index(index)?;
+
+        match string.char_indices().nth(idx) {
+            Some((start, c)) => Ok(self.try_alloc(&string[start..start + c.len_utf8()])?.into()),
+            None => {
+                let length = string.chars().count();
+                Err(IndexError::OutOfBounds { wanted_index: idx, length }.into())
+            }
+        }
+    }
+}
+
+/// Convert a [`Value`] used to index a string's characters in to a `usize`,
+/// with the same rules as list indexes
+fn char_index(index: Value) -> Result<usize, TypeError> {
+    VM::list_index(index).map_err(|e| match e {
+        TypeError::InvalidIndexType { type_ } => TypeError::InvalidStringIndexType { type_ },
+        TypeError::InvalidIndex { index } => TypeError::InvalidStringIndex { index },
+        e => e,
+    })
+}
+
+/// The contents of an argument that has to be a string
+fn string_arg(value: Value) -> Result<&'static str, Error> {
+    if value.is_object() && value.as_object().type_() == ObjectType::String {
+        return Ok(unsafe { (*value.as_object().string).value });
+    }
+    Err(TypeError::InvalidType {
+        expected_type: "string".to_string(),
+        actual_type: value.type_().to_string(),
+    }
+    .into())
+}
+
+/// The byte offset of a character index, which may be the end of the string
+fn byte_offset(string: &str, idx: usize) -> usize {
+    string.char_indices().nth(idx).map_or(string.len(), |(offset, _)| offset)
+}
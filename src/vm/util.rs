@@ -1,4 +1,5 @@
 use std::hint;
+use std::time::Duration;
 
 #[cfg(target_family = "wasm")]
 use wasm_bindgen::prelude::*;
@@ -8,6 +9,8 @@ use wasm_bindgen::prelude::*;
 extern "C" {
     #[wasm_bindgen(js_namespace = Date, js_name = now)]
     fn date_now() -> f64;
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
 }
 
 #[cfg(target_family = "wasm")]
@@ -23,6 +26,45 @@ pub fn now() -> f64 {
         .as_secs_f64()
 }
 
+/// A moment measured with a monotonic clock, for timing how long something
+/// takes. Unlike [`now`] it isn't affected by changes to the system clock.
+#[derive(Clone, Copy, Debug)]
+pub struct Instant {
+    #[cfg(not(target_family = "wasm"))]
+    instant: std::time::Instant,
+    /// Milliseconds since the page or worker started
+    #[cfg(target_family = "wasm")]
+    millis: f64,
+}
+
+impl Instant {
+    #[cfg(not(target_family = "wasm"))]
+    pub fn now() -> Self {
+        Self { instant: std::time::Instant::now() }
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub fn now() -> Self {
+        Self { millis: performance_now() }
+    }
+
+    /// The time from `earlier` to this, or zero if `earlier` is later
+    #[cfg(not(target_family = "wasm"))]
+    pub fn duration_since(self, earlier: Self) -> Duration {
+        self.instant.saturating_duration_since(earlier.instant)
+    }
+
+    /// The time from `earlier` to this, or zero if `earlier` is later
+    #[cfg(target_family = "wasm")]
+    pub fn duration_since(self, earlier: Self) -> Duration {
+        Duration::try_from_secs_f64((self.millis - earlier.millis) / 1000.0).unwrap_or_default()
+    }
+
+    pub fn elapsed(self) -> Duration {
+        Self::now().duration_since(self)
+    }
+}
+
 pub const fn unreachable() -> ! {
     if cfg!(debug_assertions) { unreachable!() } else { unsafe { hint::unreachable_unchecked() } }
 }
@@ -7,7 +7,7 @@ use crate::vm::util;
 
 const _: () = assert!(mem::size_of::<Value>() == 8);
 
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
 pub struct Value(u64);
 
 impl Default for Value {
@@ -69,6 +69,15 @@ impl Not for Value {
 }
 
 impl Value {
+    /// Format the value as it's shown inside a list or map (e.g. strings are quoted)
+    pub fn repr(self) -> String {
+        if self.is_object() && self.as_object().type_() == ObjectType::String {
+            format!("{:?}", unsafe { (*self.as_object().string).value })
+        } else {
+            self.to_string()
+        }
+    }
+
     const SIGN_BIT: u64 = 0x8000000000000000;
     const QNAN: u64 = 0x7ffc000000000000;
 
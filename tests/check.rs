@@ -0,0 +1,46 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const SOURCE: &str = "fn f(a, b) {\n  return a;\n}\nprintln(f(1, 2));\n";
+
+fn check(args: &[&str], dir: &Path) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_locks"))
+        .arg("check")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("failed to run locks check");
+    (output.status.success(), String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn warnings_and_rules() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("check");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.locks"), SOURCE).unwrap();
+
+    let (success, stderr) = check(&["main.locks"], &dir);
+    assert!(success);
+    assert!(stderr.contains("unused-variable"), "{stderr}");
+    assert!(stderr.contains(r#"unused parameter: "b""#), "{stderr}");
+
+    let (success, _) = check(&["--deny-warnings", "main.locks"], &dir);
+    assert!(!success);
+
+    let (success, stderr) =
+        check(&["--deny-warnings", "--allow", "unused-variable", "main.locks"], &dir);
+    assert!(success);
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn errors() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("check_error");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.locks"), "return 1;\n").unwrap();
+
+    let (success, stderr) = check(&["."], &dir);
+    assert!(!success);
+    assert!(stderr.contains("\"return\" used outside function"), "{stderr}");
+}
@@ -0,0 +1,142 @@
+#![cfg(feature = "dap")]
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use pretty_assertions::assert_eq;
+use serde_json::{json, Value};
+
+const SOURCE: &str = "\
+fn add(a, b) {
+  let sum = a + b;
+  return sum;
+}
+let x = 1;
+let y = add(x, 2);
+println(y);
+";
+
+/// Send every request up front and collect every message the adapter sends
+/// back until it exits
+fn session(program: &Path, requests: &[Value]) -> Vec<Value> {
+    let mut input = Vec::new();
+    for (idx, request) in requests.iter().enumerate() {
+        let mut request = request.clone();
+        request["seq"] = (idx + 1).into();
+        request["type"] = "request".into();
+        let body = request.to_string();
+        write!(input, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_locks"))
+        .arg("dap")
+        .current_dir(program.parent().unwrap())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start the debug adapter");
+    child.stdin.take().unwrap().write_all(&input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let mut output = output.stdout.as_slice();
+    let mut messages = Vec::new();
+    while !output.is_empty() {
+        let header_end = output.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+        let header = std::str::from_utf8(&output[..header_end]).unwrap();
+        let len: usize = header.strip_prefix("Content-Length: ").unwrap().parse().unwrap();
+        let body = &output[header_end + 4..header_end + 4 + len];
+        messages.push(serde_json::from_slice(body).unwrap());
+        output = &output[header_end + 4 + len..];
+    }
+    messages
+}
+
+fn response(messages: &[Value], request_seq: usize) -> &Value {
+    messages
+        .iter()
+        .find(|message| message["type"] == "response" && message["request_seq"] == request_seq)
+        .unwrap_or_else(|| panic!("no response to request {request_seq}"))
+}
+
+fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+    messages.iter().filter(|message| message["event"] == event).collect()
+}
+
+#[test]
+fn breakpoints_and_steps() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("dap");
+    fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("main.locks");
+    fs::write(&program, SOURCE).unwrap();
+    let path = program.display().to_string();
+
+    let messages = session(
+        &program,
+        &[
+            json!({ "command": "initialize", "arguments": { "adapterID": "locks" } }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({
+                "command": "setBreakpoints",
+                "arguments": { "source": { "path": path }, "breakpoints": [{ "line": 2 }] },
+            }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 2 } }),
+            json!({ "command": "next", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ],
+    );
+
+    for seq in 1..=13 {
+        assert_eq!(response(&messages, seq)["success"], true, "request {seq} failed");
+    }
+
+    let stops = events(&messages, "stopped")
+        .iter()
+        .map(|event| event["body"]["reason"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(stops, vec!["breakpoint", "step", "step"]);
+
+    let frames = &response(&messages, 5)["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "add");
+    assert_eq!(frames[0]["line"], 2);
+    assert_eq!(frames[0]["source"]["path"], path);
+    assert_eq!(frames[1]["name"], "<script>");
+    assert_eq!(frames[1]["line"], 6);
+
+    let scopes = &response(&messages, 6)["body"]["scopes"];
+    assert_eq!(scopes[0]["variablesReference"], 2);
+
+    let locals = &response(&messages, 7)["body"]["variables"];
+    assert_eq!(
+        locals,
+        &json!([
+            { "name": "a", "value": "1", "type": "number", "variablesReference": 0 },
+            { "name": "b", "value": "2", "type": "number", "variablesReference": 0 },
+        ])
+    );
+
+    assert_eq!(response(&messages, 9)["body"]["stackFrames"][0]["line"], 3);
+
+    let globals = &response(&messages, 11)["body"]["variables"];
+    let names =
+        globals.as_array().unwrap().iter().map(|global| &global["name"]).collect::<Vec<_>>();
+    assert_eq!(names, vec!["add", "x"]);
+
+    let output = events(&messages, "output")
+        .iter()
+        .map(|event| event["body"]["output"].as_str().unwrap())
+        .collect::<String>();
+    assert_eq!(output, "3\n");
+
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+    assert_eq!(events(&messages, "terminated").len(), 1);
+}
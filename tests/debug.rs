@@ -0,0 +1,122 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use locks::vm::debug::{Breakpoints, DebugHook, Debugger, Location, Step, StopReason};
+use locks::vm::VM;
+use pretty_assertions::assert_eq;
+
+const SOURCE: &str = "\
+fn add(a, b) {
+  let sum = a + b;
+  return sum;
+}
+let x = 1;
+let y = add(x, 2);
+println(y);
+";
+
+#[derive(Debug, PartialEq)]
+struct Stop {
+    line: usize,
+    reason: StopReason,
+    frames: Vec<String>,
+    locals: Vec<(String, String)>,
+}
+
+/// Answers each stop with the next step, and records where it stopped
+struct Script {
+    steps: Vec<Step>,
+    breakpoints: Vec<usize>,
+    stops: Rc<RefCell<Vec<Stop>>>,
+}
+
+impl DebugHook for Script {
+    fn stopped(
+        &mut self,
+        vm: &VM,
+        breakpoints: &mut Breakpoints,
+        location: &Location,
+        reason: StopReason,
+    ) -> Step {
+        for line in self.breakpoints.drain(..) {
+            breakpoints.add(location.source.clone(), line);
+        }
+
+        let frames = vm.debug_frames();
+        self.stops.borrow_mut().push(Stop {
+            line: location.line,
+            reason,
+            frames: frames.iter().map(|frame| frame.name.clone()).collect(),
+            locals: frames[0]
+                .locals
+                .iter()
+                .map(|(name, value)| (name.clone(), value.repr()))
+                .collect(),
+        });
+
+        if self.steps.is_empty() {
+            Step::Continue
+        } else {
+            self.steps.remove(0)
+        }
+    }
+}
+
+fn debug(steps: Vec<Step>, breakpoints: Vec<usize>) -> Vec<Stop> {
+    let stops = Rc::new(RefCell::new(Vec::new()));
+    let script = Script { steps, breakpoints, stops: Rc::clone(&stops) };
+
+    let mut vm = VM::new();
+    vm.set_debugger(Some(Debugger::new(script, Step::In)));
+
+    let mut output = Vec::new();
+    vm.run(SOURCE, &mut output).expect("script failed");
+    assert_eq!(output, b"3\n");
+
+    stops.take()
+}
+
+fn stop(line: usize, reason: StopReason, frames: &[&str], locals: &[(&str, &str)]) -> Stop {
+    Stop {
+        line,
+        reason,
+        frames: frames.iter().map(|frame| frame.to_string()).collect(),
+        locals: locals.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+    }
+}
+
+#[test]
+fn step_in() {
+    let stops = debug(vec![Step::In; 10], vec![]);
+    let lines = stops.iter().map(|stop| stop.line).collect::<Vec<_>>();
+    assert_eq!(lines, vec![1, 5, 6, 2, 3, 7]);
+}
+
+#[test]
+fn step_over() {
+    let stops = debug(vec![Step::Over; 10], vec![]);
+    let lines = stops.iter().map(|stop| stop.line).collect::<Vec<_>>();
+    assert_eq!(lines, vec![1, 5, 6, 7]);
+}
+
+#[test]
+fn breakpoint_and_step_out() {
+    let stops = debug(vec![Step::Continue, Step::Out, Step::Continue], vec![2]);
+    assert_eq!(
+        stops,
+        vec![
+            stop(1, StopReason::Step, &[""], &[]),
+            stop(2, StopReason::Breakpoint, &["add", ""], &[("a", "1"), ("b", "2")]),
+            stop(6, StopReason::Step, &[""], &[]),
+        ]
+    );
+}
+
+#[test]
+fn locals_in_scope() {
+    let stops = debug(vec![Step::In, Step::Continue], vec![3]);
+    assert_eq!(
+        stops[2],
+        stop(3, StopReason::Breakpoint, &["add", ""], &[("a", "1"), ("b", "2"), ("sum", "3")])
+    );
+}
@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use pretty_assertions::assert_eq;
+
+const UNFORMATTED: &str = "let x=1;   // one\nfn f(a){return a;}\n";
+const FORMATTED: &str = "let x = 1; // one\nfn f(a) {\n  return a;\n}\n";
+
+fn fmt(args: &[&str], dir: &Path) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_locks"))
+        .arg("fmt")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("failed to run locks fmt");
+    (output.status.success(), String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn check_then_format() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fmt");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join("main.locks"), UNFORMATTED).unwrap();
+    fs::write(dir.join("nested/done.locks"), FORMATTED).unwrap();
+    fs::write(dir.join("nested/notes.txt"), UNFORMATTED).unwrap();
+
+    let (success, stdout) = fmt(&["--check", "."], &dir);
+    assert!(!success);
+    assert_eq!(stdout, format!("{}\n", Path::new(".").join("main.locks").display()));
+    assert_eq!(fs::read_to_string(dir.join("main.locks")).unwrap(), UNFORMATTED);
+
+    let (success, _) = fmt(&["."], &dir);
+    assert!(success);
+    assert_eq!(fs::read_to_string(dir.join("main.locks")).unwrap(), FORMATTED);
+    assert_eq!(fs::read_to_string(dir.join("nested/notes.txt")).unwrap(), UNFORMATTED);
+
+    let (success, stdout) = fmt(&["--check", "."], &dir);
+    assert!(success);
+    assert_eq!(stdout, "");
+}
+
+#[test]
+fn syntax_errors_are_not_formatted() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fmt_error");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("broken.locks"), "let x = ;\n").unwrap();
+
+    let (success, _) = fmt(&["broken.locks"], &dir);
+    assert!(!success);
+    assert_eq!(fs::read_to_string(dir.join("broken.locks")).unwrap(), "let x = ;\n");
+}
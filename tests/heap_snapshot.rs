@@ -0,0 +1,169 @@
+use std::{env, fs, process};
+
+use locks::vm::{HeapEdgeKind, HeapNode, HeapSnapshot, ObjectType, VM};
+use pretty_assertions::assert_eq;
+
+fn run(vm: &mut VM, source: &str) {
+    vm.run(source, &mut Vec::new()).unwrap();
+}
+
+/// The node a root or node references with a property called `name`
+fn property<'a>(snapshot: &'a HeapSnapshot, node: Option<&HeapNode>, name: &str) -> &'a HeapNode {
+    let edges = node.map_or(&snapshot.roots, |node| &node.edges);
+    let edge = edges
+        .iter()
+        .find(|edge| edge.kind == HeapEdgeKind::Property(name.to_string()))
+        .unwrap_or_else(|| panic!("no property called {name}"));
+    &snapshot.nodes[edge.to]
+}
+
+fn edge<'a>(snapshot: &'a HeapSnapshot, node: &HeapNode, kind: HeapEdgeKind) -> &'a HeapNode {
+    let edge = node.edges.iter().find(|edge| edge.kind == kind).unwrap();
+    &snapshot.nodes[edge.to]
+}
+
+#[test]
+fn snapshot_follows_references() {
+    let mut vm = VM::new();
+    let source = r#"
+        class Node {
+            let value;
+            fn init(value) {
+                this.value = value;
+            }
+        }
+
+        let node = Node("leaf");
+        let list = [node, 1];
+
+        fn counter() {
+            let count = 0;
+            fn increment() {
+                count = count + 1;
+                return count;
+            }
+            return increment;
+        }
+        let increment = counter();
+        increment();
+    "#;
+    run(&mut vm, source);
+    let snapshot = vm.heap_snapshot();
+
+    let node = property(&snapshot, None, "node");
+    assert_eq!((node.type_, node.name.as_str()), (ObjectType::Instance, "Node"));
+    let class = edge(&snapshot, node, HeapEdgeKind::Internal("class"));
+    assert_eq!((class.type_, class.name.as_str()), (ObjectType::Class, "Node"));
+    assert_eq!(property(&snapshot, Some(class), "init").type_, ObjectType::Closure);
+    let value = property(&snapshot, Some(node), "value");
+    assert_eq!((value.type_, value.name.as_str()), (ObjectType::String, "leaf"));
+
+    let list = property(&snapshot, None, "list");
+    assert_eq!(list.type_, ObjectType::List);
+    assert_eq!(edge(&snapshot, list, HeapEdgeKind::Element(0)), node);
+    // Numbers aren't objects
+    assert_eq!(list.edges.len(), 1);
+
+    let increment = property(&snapshot, None, "increment");
+    assert_eq!((increment.type_, increment.name.as_str()), (ObjectType::Closure, "increment"));
+    let upvalue = edge(&snapshot, increment, HeapEdgeKind::Element(0));
+    assert_eq!(upvalue.type_, ObjectType::Upvalue);
+    let function = edge(&snapshot, increment, HeapEdgeKind::Internal("function"));
+    assert_eq!((function.type_, function.name.as_str()), (ObjectType::Function, "increment"));
+
+    assert!(snapshot.nodes.iter().all(|node| node.self_size > 0));
+}
+
+#[test]
+#[cfg(not(feature = "gc-off"))]
+fn snapshot_only_has_reachable_objects() {
+    let mut vm = VM::new();
+    let source = r#"
+        class Garbage {}
+        let garbage = Garbage();
+        garbage = nil;
+    "#;
+    run(&mut vm, source);
+    let snapshot = vm.heap_snapshot();
+
+    assert!(snapshot
+        .nodes
+        .iter()
+        .any(|node| node.type_ == ObjectType::Class && node.name == "Garbage"));
+    assert!(!snapshot.nodes.iter().any(|node| node.type_ == ObjectType::Instance));
+}
+
+#[test]
+fn native_writes_snapshot() {
+    let dir = env::temp_dir().join(format!("locks-heap-snapshot-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = r#"
+        let numbers = [1, 2, 3];
+        heap_snapshot();
+        numbers = nil;
+        heap_snapshot();
+    "#;
+
+    let mut vm = VM::new();
+    vm.set_heap_snapshot_path(dir.join("snapshot.json"));
+    run(&mut vm, source);
+    assert_eq!(vm.heap_snapshots_written(), 2);
+    assert!(!dir.join("snapshot.json").exists());
+    assert!(dir.join("snapshot.2.json").exists());
+
+    let json = dir.join("snapshot.1.json");
+    let snapshot = serde_json::from_str::<serde_json::Value>(&fs::read_to_string(&json).unwrap());
+    let snapshot = snapshot.unwrap();
+    let nodes = snapshot["nodes"].as_array().unwrap();
+    let numbers = snapshot["roots"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|edge| edge["name"] == "numbers")
+        .unwrap();
+    let list = &nodes[numbers["to"].as_u64().unwrap() as usize];
+    assert_eq!(list["type"], "List");
+
+    let mut vm = VM::new();
+    vm.set_heap_snapshot_path(dir.join("snapshot.heapsnapshot"));
+    run(&mut vm, source);
+    let chrome = dir.join("snapshot.1.heapsnapshot");
+
+    let snapshot = serde_json::from_str::<serde_json::Value>(&fs::read_to_string(&chrome).unwrap());
+    let snapshot = snapshot.unwrap();
+    let meta = &snapshot["snapshot"]["meta"];
+    let node_fields = meta["node_fields"].as_array().unwrap().len();
+    let edge_fields = meta["edge_fields"].as_array().unwrap().len();
+    let nodes = snapshot["nodes"].as_array().unwrap();
+    let edges = snapshot["edges"].as_array().unwrap();
+    let strings = snapshot["strings"].as_array().unwrap();
+    assert_eq!(
+        nodes.len(),
+        snapshot["snapshot"]["node_count"].as_u64().unwrap() as usize * node_fields
+    );
+    assert_eq!(
+        edges.len(),
+        snapshot["snapshot"]["edge_count"].as_u64().unwrap() as usize * edge_fields
+    );
+
+    // Each node's edges follow the previous node's, and point to the start of
+    // a node
+    let edge_count = nodes.chunks(node_fields).map(|node| node[4].as_u64().unwrap()).sum::<u64>();
+    assert_eq!(edge_count as usize * edge_fields, edges.len());
+    for edge in edges.chunks(edge_fields) {
+        let to_node = edge[2].as_u64().unwrap() as usize;
+        assert_eq!(to_node % node_fields, 0);
+        assert!(to_node < nodes.len());
+    }
+    // The root comes first
+    assert_eq!(strings[nodes[1].as_u64().unwrap() as usize], "(roots)");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn native_does_nothing_without_path() {
+    let mut vm = VM::new();
+    run(&mut vm, "heap_snapshot();");
+    assert_eq!(vm.heap_snapshots_written(), 0);
+}
@@ -0,0 +1,214 @@
+use std::str;
+
+use locks::vm::{HostValue, ObjectType, RunOutcome, VM};
+use pretty_assertions::assert_eq;
+
+fn run(vm: &mut VM, source: &str) {
+    let mut output = Vec::new();
+    vm.run(source, &mut output).expect("script failed");
+}
+
+#[test]
+fn get_global() {
+    let mut vm = VM::new();
+    run(
+        &mut vm,
+        r#"
+        let name = "locks";
+        let config = { "debug": true, "retries": 3, "hosts": ["a", "b"] };
+        "#,
+    );
+
+    assert_eq!(vm.get_global("name"), Some(HostValue::from("locks")));
+    assert_eq!(
+        vm.get_global("config"),
+        Some(HostValue::Map(vec![
+            (HostValue::from("debug"), HostValue::Bool(true)),
+            (HostValue::from("retries"), HostValue::Number(3.0)),
+            (HostValue::from("hosts"), HostValue::from(vec!["a".into(), "b".into()])),
+        ]))
+    );
+    assert_eq!(vm.get_global("missing"), None);
+}
+
+#[test]
+fn get_global_self_referencing_list() {
+    let mut vm = VM::new();
+    run(&mut vm, "let list = [1]; list[0] = list;");
+
+    let Some(HostValue::List(values)) = vm.get_global("list") else {
+        panic!("expected a list");
+    };
+    assert!(matches!(values[..], [HostValue::Object(_)]));
+}
+
+#[test]
+fn set_global() {
+    let mut vm = VM::new();
+    vm.set_global("limit", 10.0).expect("set_global failed");
+    vm.set_global("names", vec![HostValue::from("a"), HostValue::Nil]).expect("set_global failed");
+
+    let mut output = Vec::new();
+    vm.run("println(limit); println(names);", &mut output).expect("script failed");
+    assert_eq!(str::from_utf8(&output).unwrap(), "10\n[\"a\", nil]\n");
+}
+
+#[test]
+fn call() {
+    let mut vm = VM::new();
+    run(
+        &mut vm,
+        r#"
+        fn greet(name) {
+          println("greeting " + name);
+          return "hello " + name;
+        }
+
+        class Counter {
+          let count;
+
+          fn init(start) {
+            this.count = start;
+          }
+        }
+        "#,
+    );
+
+    let mut output = Vec::new();
+    let greet = vm.get_global("greet").unwrap();
+    let value = vm.call(&greet, &["world".into()], &mut output).expect("call failed");
+    assert_eq!(value, HostValue::from("hello world"));
+    assert_eq!(str::from_utf8(&output).unwrap(), "greeting world\n");
+
+    let len = vm.get_global("len").unwrap();
+    let value = vm.call(&len, &["abc".into()], &mut output).expect("call failed");
+    assert_eq!(value, HostValue::Number(3.0));
+
+    let counter = vm.get_global("Counter").unwrap();
+    let value = vm.call(&counter, &[1.0.into()], &mut output).expect("call failed");
+    assert!(matches!(value, HostValue::Object(_)));
+}
+
+#[test]
+fn object_handles_keep_objects_alive() {
+    let mut vm = VM::new();
+    run(
+        &mut vm,
+        r#"
+        class Counter {
+          let count = 5;
+        }
+        let counter = Counter();
+        fn count(counter) {
+          return counter.count;
+        }
+        "#,
+    );
+    let is_alive = |vm: &mut VM| {
+        let snapshot = vm.heap_snapshot();
+        snapshot.nodes.iter().any(|node| node.type_ == ObjectType::Instance)
+    };
+
+    let counter = vm.get_global("counter").unwrap();
+    assert!(matches!(counter, HostValue::Object(_)));
+    assert_eq!(vm.get_global("counter"), Some(counter.clone()));
+    run(&mut vm, "counter = nil;");
+    assert!(is_alive(&mut vm));
+
+    let mut output = Vec::new();
+    let count = vm.get_global("count").unwrap();
+    let args = [counter];
+    let value = vm.call(&count, &args, &mut output).expect("call failed");
+    assert_eq!(value, HostValue::Number(5.0));
+
+    drop(args);
+    assert_eq!(is_alive(&mut vm), cfg!(feature = "gc-off"));
+}
+
+#[test]
+#[should_panic(expected = "object handle is from another VM")]
+fn object_handles_belong_to_their_vm() {
+    let mut vm = VM::new();
+    run(&mut vm, "fn f() {}");
+    let f = vm.get_global("f").unwrap();
+
+    VM::new().set_global("f", f).unwrap();
+}
+
+#[test]
+fn call_error() {
+    let mut vm = VM::new();
+    run(&mut vm, r#"fn fail() { throw TypeError("bad"); }"#);
+
+    let mut output = Vec::new();
+    let fail = vm.get_global("fail").unwrap();
+    let errors = vm.call(&fail, &[], &mut output).unwrap_err();
+    assert_eq!(errors[0].0.to_string(), "TypeError: bad");
+
+    let errors = vm.call(&fail, &[HostValue::Nil], &mut output).unwrap_err();
+    assert_eq!(errors[0].0.to_string(), "TypeError: fail() takes 0 arguments but 1 were given");
+
+    let errors = vm.call(&HostValue::Number(1.0), &[], &mut output).unwrap_err();
+    assert_eq!(errors[0].0.to_string(), "TypeError: \"number\" object is not callable");
+}
+
+#[test]
+fn start_and_resume() {
+    let mut vm = VM::new();
+    let mut output = Vec::new();
+
+    let source = "let i = 0; while (i < 100) { i = i + 1; } println(i);";
+    let outcome = vm.start(source, 50, &mut output).expect("script failed");
+    assert_eq!(outcome, RunOutcome::Suspended);
+    assert!(vm.is_suspended());
+    assert!(output.is_empty());
+
+    let mut slices = 1;
+    while vm.resume(50, &mut output).expect("script failed") == RunOutcome::Suspended {
+        slices += 1;
+    }
+    assert!(slices > 2);
+    assert!(!vm.is_suspended());
+    assert_eq!(str::from_utf8(&output).unwrap(), "100\n");
+}
+
+#[test]
+fn cancel() {
+    let mut vm = VM::new();
+    let mut output = Vec::new();
+
+    let outcome = vm.start("while (true) {}", 1000, &mut output).expect("script failed");
+    assert_eq!(outcome, RunOutcome::Suspended);
+
+    vm.cancel();
+    assert!(!vm.is_suspended());
+    assert_eq!(vm.resume(1000, &mut output), Ok(RunOutcome::Finished));
+
+    run(&mut vm, r#"println("still usable");"#);
+}
+
+#[test]
+fn cancel_closes_upvalues() {
+    let mut vm = VM::new();
+    let mut output = Vec::new();
+
+    let source = r#"
+        let get;
+        {
+            let value = "captured";
+            fn f() {
+                return value;
+            }
+            get = f;
+            while (true) {}
+        }
+    "#;
+    let outcome = vm.start(source, 1000, &mut output).expect("script failed");
+    assert_eq!(outcome, RunOutcome::Suspended);
+
+    vm.cancel();
+    let mut output = Vec::new();
+    let source = "{ let a = 1; let b = 2; let c = 3; println(get()); }";
+    vm.run(source, &mut output).expect("script failed");
+    assert_eq!(str::from_utf8(&output).unwrap(), "captured\n");
+}
@@ -1,7 +1,9 @@
-use std::io::Write;
-use std::{fs, str};
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use locks::vm::VM;
+use locks::snapshot::{self, Output};
+use locks::syntax::format;
+use locks::vm::{bytecode, Gc, VM};
 use pretty_assertions::assert_eq;
 use test_generator::test_resources;
 
@@ -15,20 +17,68 @@ fn lox(path: &str) {
     }
 
     let source = fs::read_to_string(path).expect("unable to read test file");
-    let mut exp_output = String::new();
-    for line in source.lines() {
-        const OUT_COMMENT: &str = "// out: ";
-        if let Some(idx) = line.find(OUT_COMMENT) {
-            exp_output += &line[idx + OUT_COMMENT.len()..];
-            exp_output += "\n";
-        }
+    let exp_output = Output::expected(&source);
+
+    let got_output = snapshot::run(&source, &[package_path(path)]);
+    if !got_output.matches(&exp_output) {
+        assert_eq!(exp_output, got_output);
+    }
+}
+
+/// Run the examples again after a round trip through the bytecode format
+#[test_resources("res/examples/**/*.locks")]
+fn lox_bytecode(path: &str) {
+    const MIRI_SKIP_PATHS: &[&str] =
+        &["res/examples/limit/loop_too_large.locks", "res/examples/limit/stack_overflow.locks"];
+    if cfg!(miri) && MIRI_SKIP_PATHS.contains(&path) {
+        return;
     }
 
-    let mut got_output = Vec::new();
-    if let Err(e) = VM::default().run(&source, &mut got_output) {
-        let (e, _) = e.first().expect("received empty error");
-        writeln!(&mut got_output, "{e}").expect("could not write to output");
+    let source = fs::read_to_string(path).expect("unable to read test file");
+    let exp_output = Output::expected(&source);
+
+    // Programs that don't compile are covered by the test above
+    let bytes = match bytecode::compile(&source, &mut Gc::default()) {
+        Ok(bytes) => bytes,
+        Err(_) => return,
+    };
+
+    let mut stdout = Vec::new();
+    let mut vm = VM::default();
+    vm.add_package_path(package_path(path));
+    let result = vm.run_bytecode(&bytes, &mut stdout);
+    let got_output = Output::new(&stdout, result);
+    if !got_output.matches(&exp_output) {
+        assert_eq!(exp_output, got_output);
     }
-    let got_output = str::from_utf8(&got_output).expect("invalid UTF-8 in output");
-    assert_eq!(exp_output, got_output);
+}
+
+/// Formatting the examples keeps their comments and doesn't change what they do
+#[test_resources("res/examples/**/*.locks")]
+fn lox_format(path: &str) {
+    const MIRI_SKIP_PATHS: &[&str] =
+        &["res/examples/limit/loop_too_large.locks", "res/examples/limit/stack_overflow.locks"];
+    if cfg!(miri) && MIRI_SKIP_PATHS.contains(&path) {
+        return;
+    }
+
+    let source = fs::read_to_string(path).expect("unable to read test file");
+    let exp_output = Output::expected(&source);
+
+    // Programs that don't parse are left alone by the formatter
+    let formatted = match format::format(&source) {
+        Ok(formatted) => formatted,
+        Err(_) => return,
+    };
+    assert_eq!(exp_output, Output::expected(&formatted));
+    assert_eq!(formatted, format::format(&formatted).expect("formatted code does not parse"));
+
+    let got_output = snapshot::run(&formatted, &[package_path(path)]);
+    if !got_output.matches(&exp_output) {
+        assert_eq!(exp_output, got_output);
+    }
+}
+
+fn package_path(path: &str) -> PathBuf {
+    Path::new(path).parent().expect("test file has no parent directory").to_path_buf()
 }
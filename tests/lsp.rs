@@ -0,0 +1,379 @@
+#![cfg(all(feature = "lsp", feature = "dap"))]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use pretty_assertions::assert_eq;
+use serde_json::{json, Value};
+
+const URI: &str = "file:///tmp/main.locks";
+
+/// A client that talks to `locks lsp` one message at a time
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    messages: Receiver<Value>,
+    id: u64,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_locks"))
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start the language server");
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut len = 0;
+            loop {
+                let mut header = String::new();
+                if stdout.read_line(&mut header).unwrap_or(0) == 0 {
+                    return;
+                }
+                match header.trim_end().strip_prefix("Content-Length: ") {
+                    Some(value) => len = value.parse().unwrap(),
+                    None if header.trim_end().is_empty() => break,
+                    None => {}
+                }
+            }
+            let mut body = vec![0; len];
+            stdout.read_exact(&mut body).unwrap();
+            if sender.send(serde_json::from_slice(&body).unwrap()).is_err() {
+                return;
+            }
+        });
+
+        let mut client = Self { child, stdin, messages, id: 0 };
+        client.request("initialize", json!({ "capabilities": {} }));
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let message = self.response(method, params);
+        assert_eq!(message["error"], Value::Null, "{method} failed");
+        message["result"].clone()
+    }
+
+    /// The error from a request that's expected to fail
+    fn request_error(&mut self, method: &str, params: Value) -> Value {
+        let message = self.response(method, params);
+        assert_eq!(message["result"], Value::Null, "{method} succeeded");
+        message["error"].clone()
+    }
+
+    fn response(&mut self, method: &str, params: Value) -> Value {
+        self.id += 1;
+        let id = self.id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == id {
+                return message;
+            }
+        }
+    }
+
+    /// The next diagnostics the server publishes
+    fn diagnostics(&mut self) -> Value {
+        loop {
+            let message = self.receive();
+            if message["method"] == "textDocument/publishDiagnostics" {
+                return message["params"].clone();
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Value {
+        self.messages
+            .recv_timeout(Duration::from_secs(10))
+            .expect("timed out waiting for the language server")
+    }
+
+    fn change(&mut self, version: i32, changes: Value) {
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": URI, "version": version },
+                "contentChanges": changes,
+            }),
+        );
+    }
+
+    fn hover(&mut self, line: u32, character: u32) -> Value {
+        let params = json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        });
+        self.request("textDocument/hover", params)["contents"]["value"].clone()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+fn open(client: &mut Client, text: &str) {
+    client.notify(
+        "textDocument/didOpen",
+        json!({
+            "textDocument": { "uri": URI, "languageId": "locks", "version": 1, "text": text },
+        }),
+    );
+}
+
+#[test]
+fn incremental_changes() {
+    let mut client = Client::start();
+    open(&mut client, "let a = 1;\nprintln(a);\n");
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics["version"], 1);
+    assert_eq!(diagnostics["diagnostics"], json!([]));
+
+    // Rename `a` to `bee` in both places, then break the program
+    client.change(
+        2,
+        json!([
+            {
+                "range": { "start": { "line": 1, "character": 8 }, "end": { "line": 1, "character": 9 } },
+                "text": "bee",
+            },
+            {
+                "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 5 } },
+                "text": "bee",
+            },
+        ]),
+    );
+    assert_eq!(client.diagnostics()["version"], 2);
+    assert_eq!(client.hover(1, 9), "```locks\nlet bee\n```");
+
+    client.change(
+        3,
+        json!([{
+            "range": { "start": { "line": 1, "character": 12 }, "end": { "line": 1, "character": 13 } },
+            "text": "",
+        }]),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics["version"], 3);
+    assert_eq!(diagnostics["diagnostics"][0]["message"], "SyntaxError: unexpected end of file");
+
+    // A change without a range replaces the whole document
+    client.change(4, json!([{ "text": "fn f() {}\n" }]));
+    assert_eq!(client.diagnostics()["version"], 4);
+    assert_eq!(client.hover(0, 3), "```locks\nfn f()\n```");
+
+    // Empty change lists are ignored
+    client.change(5, json!([]));
+    assert_eq!(client.diagnostics()["version"], 5);
+}
+
+#[test]
+fn save_and_close() {
+    let mut client = Client::start();
+    open(&mut client, "let a = 1;\n");
+    client.diagnostics();
+
+    client.notify(
+        "textDocument/didSave",
+        json!({ "textDocument": { "uri": URI }, "text": "let a = ;\n" }),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics["diagnostics"][0]["message"], "SyntaxError: unexpected \";\"");
+
+    client.notify("textDocument/didClose", json!({ "textDocument": { "uri": URI } }));
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics["diagnostics"], json!([]));
+    assert_eq!(client.hover(0, 4), Value::Null);
+}
+
+#[test]
+fn large_documents_are_debounced() {
+    let mut client = Client::start();
+    let text = "let a = 1;\n".repeat(4 * 1024);
+    open(&mut client, &text);
+    assert_eq!(client.diagnostics()["version"], 1);
+
+    // Only the last of several quick changes is diagnosed
+    for version in 2..=4 {
+        client.change(
+            version,
+            json!([{
+                "range": { "start": { "line": 0, "character": 8 }, "end": { "line": 0, "character": 9 } },
+                "text": version.to_string(),
+            }]),
+        );
+    }
+    assert_eq!(client.diagnostics()["version"], 4);
+}
+
+#[test]
+fn semantic_tokens() {
+    let mut client = Client::start();
+    open(&mut client, "class A {\n  static fn s(x) => x;\n}\nprintln(A.s(1));\n");
+    client.diagnostics();
+
+    let params = json!({ "textDocument": { "uri": URI } });
+    let data = client.request("textDocument/semanticTokens/full", params)["data"].clone();
+    #[rustfmt::skip]
+    assert_eq!(data, json!([
+        0, 6, 1, 1, 1,  // class A (declaration)
+        1, 12, 1, 3, 3, // static fn s (declaration, static)
+        0, 2, 1, 6, 1,  // parameter x (declaration)
+        0, 6, 1, 6, 0,  // x
+        2, 0, 7, 2, 4,  // println (defaultLibrary)
+        0, 8, 1, 1, 0,  // A
+        0, 2, 1, 3, 2,  // .s (static)
+    ]));
+
+    let params = json!({
+        "textDocument": { "uri": URI },
+        "range": { "start": { "line": 3, "character": 0 }, "end": { "line": 3, "character": 9 } },
+    });
+    let data = client.request("textDocument/semanticTokens/range", params)["data"].clone();
+    assert_eq!(data, json!([3, 0, 7, 2, 4, 0, 8, 1, 1, 0]));
+}
+
+#[test]
+fn rename() {
+    let mut client = Client::start();
+    open(&mut client, "class A {\n  let x;\n  fn get() => this.x;\n}\nlet a = A();\na.x = 1;\n");
+    client.diagnostics();
+
+    let position =
+        json!({ "textDocument": { "uri": URI }, "position": { "line": 5, "character": 2 } });
+    let range = client.request("textDocument/prepareRename", position.clone());
+    assert_eq!(
+        range,
+        json!({ "start": { "line": 5, "character": 2 }, "end": { "line": 5, "character": 3 } })
+    );
+
+    let mut params = position;
+    params["newName"] = json!("y");
+    let edits = client.request("textDocument/rename", params)["changes"][URI].clone();
+    let lines = edits
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|edit| (edit["range"]["start"]["line"].clone(), edit["newText"].clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(lines, [(json!(1), json!("y")), (json!(2), json!("y")), (json!(5), json!("y"))]);
+
+    // Natives and invalid names are rejected
+    client.change(2, json!([{ "text": "println(1);\n" }]));
+    client.diagnostics();
+    let error = client.request_error(
+        "textDocument/prepareRename",
+        json!({ "textDocument": { "uri": URI }, "position": { "line": 0, "character": 1 } }),
+    );
+    assert_eq!(error["message"], "natives can't be renamed");
+
+    open(&mut client, "let a = 1;\n");
+    client.diagnostics();
+    let params = json!({
+        "textDocument": { "uri": URI },
+        "position": { "line": 0, "character": 4 },
+        "newName": "class",
+    });
+    let error = client.request_error("textDocument/rename", params);
+    assert_eq!(error["message"], "\"class\" is not a valid identifier");
+}
+
+#[test]
+fn rename_package_global() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/res/examples/import");
+    let uri = format!("file://{dir}/main.locks");
+    let math = format!("file://{dir}/math.locks");
+
+    let mut client = Client::start();
+    client.notify(
+        "textDocument/didOpen",
+        json!({
+            "textDocument": {
+                "uri": uri,
+                "languageId": "locks",
+                "version": 1,
+                "text": "import math;\nprintln(math:add(1, math:add(2, 3)));\n",
+            },
+        }),
+    );
+    client.diagnostics();
+
+    let params = json!({
+        "textDocument": { "uri": uri },
+        "position": { "line": 1, "character": 10 },
+        "newName": "sum",
+    });
+    let changes = client.request("textDocument/rename", params)["changes"].clone();
+    let edit = |line: u32, character: u32| {
+        json!({
+            "range": {
+                "start": { "line": line, "character": character },
+                "end": { "line": line, "character": character + 3 },
+            },
+            "newText": "sum",
+        })
+    };
+    assert_eq!(changes[&uri], json!([edit(1, 13), edit(1, 25)]));
+    assert_eq!(changes[&math], json!([edit(2, 3)]));
+}
+
+#[test]
+fn document_symbols() {
+    let mut client = Client::start();
+    open(
+        &mut client,
+        "class A {\n  static let s = 1;\n  let x;\n  fn get() => this.x;\n}\nfn f() {}\nlet a = A();\n",
+    );
+    client.diagnostics();
+
+    let params = json!({ "textDocument": { "uri": URI } });
+    let symbols = client.request("textDocument/documentSymbol", params);
+    let outline = |symbols: &Value| {
+        symbols
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|symbol| (symbol["name"].clone(), symbol["detail"].clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        outline(&symbols),
+        [
+            (json!("A"), json!("class A")),
+            (json!("f"), json!("fn f()")),
+            (json!("a"), json!("let a")),
+        ]
+    );
+    assert_eq!(
+        outline(&symbols[0]["children"]),
+        [
+            (json!("s"), json!("static let A.s")),
+            (json!("x"), json!("let A.x")),
+            (json!("get"), json!("fn A.get()")),
+        ]
+    );
+    assert_eq!(symbols[0]["kind"], 5);
+    assert_eq!(symbols[0]["range"]["end"], json!({ "line": 4, "character": 1 }));
+    assert_eq!(symbols[0]["selectionRange"]["start"], json!({ "line": 0, "character": 6 }));
+}
@@ -0,0 +1,277 @@
+use std::str;
+
+use locks::error::Error;
+use locks::vm::{Value, VmConfig, VmCtx, VM};
+use pretty_assertions::assert_eq;
+
+const LIMIT: usize = 256 * 1024;
+
+fn limited() -> VM {
+    VM::with_config(VmConfig { max_heap_bytes: Some(LIMIT), ..VmConfig::default() })
+}
+
+/// Run a script and return what it printed, or its first error
+fn run(vm: &mut VM, source: &str) -> Result<String, String> {
+    let mut output = Vec::new();
+    match vm.run(source, &mut output) {
+        Ok(()) => Ok(str::from_utf8(&output).unwrap().to_string()),
+        Err(errors) => Err(errors[0].0.to_string()),
+    }
+}
+
+#[test]
+fn heap_is_counted_per_vm() {
+    let mut a = VM::new();
+    let mut b = VM::new();
+    run(&mut a, "").unwrap();
+    run(&mut b, "").unwrap();
+    let before = b.gc.allocated_bytes();
+
+    run(&mut a, "let list = []; list[10000] = 1;").unwrap();
+    assert!(a.gc.allocated_bytes() > 10000 * 8);
+    assert_eq!(b.gc.allocated_bytes(), before);
+}
+
+#[test]
+#[cfg(not(feature = "gc-off"))]
+fn garbage_is_collected_under_limit() {
+    let mut vm = limited();
+    let source = r#"
+        for (let i = 0; i < 20000; i = i + 1) {
+            let garbage = [i, i, i, i, i, i, i, i];
+        }
+        println("done");
+    "#;
+    assert_eq!(run(&mut vm, source).unwrap(), "done\n");
+    assert!(vm.gc.allocated_bytes() <= LIMIT);
+}
+
+#[test]
+fn limit_raises_memory_error() {
+    let mut vm = limited();
+    let source = r#"
+        let s = "a";
+        while (true) { s = s + s; }
+    "#;
+    assert_eq!(
+        run(&mut vm, source).unwrap_err(),
+        format!("MemoryError: heap limit of {LIMIT} bytes exceeded")
+    );
+}
+
+#[test]
+fn limit_is_checked_before_allocating() {
+    for source in [
+        "let s = \"a\"; while (true) { s = s + s; }",
+        "let s = \"a\"; while (true) { s = s.replace(\"a\", \"aa\"); }",
+        "let list = []; list[100000] = 1;",
+        "let map = {}; for (let i = 0; true; i = i + 1) { map[i] = i; }",
+    ] {
+        let mut vm = limited();
+        assert_eq!(
+            run(&mut vm, source).unwrap_err(),
+            format!("MemoryError: heap limit of {LIMIT} bytes exceeded")
+        );
+        assert!(vm.gc.allocated_bytes() <= LIMIT, "{source}");
+    }
+}
+
+#[test]
+#[cfg(not(feature = "gc-off"))]
+fn memory_error_is_catchable() {
+    let mut vm = limited();
+    let source = r#"
+        let s = "a";
+        try {
+            while (true) { s = s + s; }
+        } catch (e) {
+            println(instanceof(e, MemoryError));
+            println(e.message);
+        }
+        s = nil;
+        println("a" + "b");
+    "#;
+    assert_eq!(
+        run(&mut vm, source).unwrap(),
+        format!("true\nheap limit of {LIMIT} bytes exceeded\nab\n")
+    );
+}
+
+#[test]
+fn growing_a_list_counts_against_limit() {
+    let mut vm = limited();
+    assert_eq!(
+        run(&mut vm, "let list = []; list[1000000] = 1;").unwrap_err(),
+        format!("MemoryError: heap limit of {LIMIT} bytes exceeded")
+    );
+
+    let mut vm = VM::new();
+    run(&mut vm, "let list = []; list[1000] = 1;").unwrap();
+}
+
+#[test]
+fn growing_a_map_counts_against_limit() {
+    let mut vm = limited();
+    let source = r#"
+        let map = {};
+        let key = "k";
+        while (true) {
+            map[key] = true;
+            key = key + "k";
+        }
+    "#;
+    assert_eq!(
+        run(&mut vm, source).unwrap_err(),
+        format!("MemoryError: heap limit of {LIMIT} bytes exceeded")
+    );
+}
+
+#[test]
+#[cfg(not(feature = "gc-off"))]
+fn gc_pauses_are_recorded() {
+    let mut vm = VM::with_config(VmConfig { initial_threshold: 64 * 1024, ..VmConfig::default() });
+    let source = r#"
+        let kept = [];
+        for (let i = 0; i < 20000; i = i + 1) {
+            let garbage = [i, i, i, i];
+            kept[i] = garbage;
+            kept[i] = nil;
+        }
+    "#;
+    run(&mut vm, source).unwrap();
+
+    let pauses = vm.gc.pause_stats();
+    assert!(pauses.count > 0);
+    assert!(pauses.max <= pauses.total);
+    assert!(pauses.mean() <= pauses.max);
+}
+
+#[test]
+#[cfg(not(feature = "gc-off"))]
+fn gc_stats() {
+    use locks::vm::ObjectType;
+
+    let mut vm = VM::with_config(VmConfig { initial_threshold: 64 * 1024, ..VmConfig::default() });
+    let source = r#"
+        class Node {
+            let value;
+            fn init(value) {
+                this.value = value;
+            }
+        }
+
+        let nodes = [];
+        for (let i = 0; i < 100; i = i + 1) {
+            nodes[i] = Node([i]);
+        }
+        for (let i = 0; i < 10000; i = i + 1) {
+            let garbage = [i, i, i, i];
+        }
+    "#;
+    run(&mut vm, source).unwrap();
+
+    let stats = vm.gc_stats();
+    assert!(stats.collections > 0);
+    assert!(stats.objects_freed > 0);
+    assert!(stats.bytes_freed > 0);
+    assert_eq!(stats.allocated_bytes, vm.gc.allocated_bytes());
+    assert_eq!(stats.pauses, vm.gc.pause_stats());
+
+    let count = |type_| stats.objects.iter().find(|&&(t, _)| t == type_).map(|&(_, count)| count);
+    assert_eq!(count(ObjectType::Instance), Some(100));
+    assert!(count(ObjectType::List) >= Some(101));
+    assert!(count(ObjectType::String) > Some(0));
+    assert_eq!(count(ObjectType::BoundMethod), None);
+}
+
+#[test]
+#[cfg(all(feature = "gc-incremental", not(feature = "gc-off")))]
+fn incremental_collections_run_in_steps() {
+    let mut vm = VM::with_config(VmConfig { initial_threshold: 64 * 1024, ..VmConfig::default() });
+    let source = r#"
+        let kept = [];
+        for (let i = 0; i < 5000; i = i + 1) {
+            kept[i] = [i];
+        }
+        for (let i = 0; i < 20000; i = i + 1) {
+            let garbage = [i, i, i, i];
+        }
+    "#;
+    run(&mut vm, source).unwrap();
+
+    let stats = vm.gc_stats();
+    assert!(stats.collections > 0);
+    assert!(stats.pauses.count > stats.collections);
+}
+
+#[test]
+#[cfg(all(feature = "gc-incremental", not(feature = "gc-off")))]
+fn objects_allocated_while_sweeping_survive() {
+    let mut vm = VM::with_config(VmConfig { initial_threshold: 64 * 1024, ..VmConfig::default() });
+    let source = r#"
+        let old = [];
+        for (let i = 0; i < 5000; i = i + 1) {
+            old[i] = [i];
+        }
+        let fresh = [];
+        for (let i = 0; i < 20000; i = i + 1) {
+            old[i % 5000] = nil;
+            fresh[i] = [i, i];
+        }
+        let matches = 0;
+        for (let i = 0; i < 20000; i = i + 1) {
+            if (fresh[i][0] + fresh[i][1] == i * 2) {
+                matches = matches + 1;
+            }
+        }
+        println(matches);
+    "#;
+    assert_eq!(run(&mut vm, source).unwrap(), "20000\n");
+
+    let stats = vm.gc_stats();
+    assert!(stats.collections > 0);
+    assert_eq!(stats.allocated_bytes, vm.gc.allocated_bytes());
+}
+
+#[test]
+fn string_methods_raise_memory_error() {
+    let mut vm = limited();
+    let source = r#"
+        let s = "a";
+        while (true) { s = s.replace("a", "aa"); }
+    "#;
+    assert_eq!(
+        run(&mut vm, source).unwrap_err(),
+        format!("MemoryError: heap limit of {LIMIT} bytes exceeded")
+    );
+}
+
+#[test]
+fn host_values_raise_memory_error() {
+    let mut vm = limited();
+    run(&mut vm, "").unwrap();
+
+    let errors = vm.set_global("big", "a".repeat(LIMIT)).unwrap_err();
+    assert_eq!(
+        errors[0].0.to_string(),
+        format!("MemoryError: heap limit of {LIMIT} bytes exceeded")
+    );
+}
+
+fn big(ctx: &mut VmCtx, _: &[Value]) -> Result<Value, Error> {
+    ctx.alloc_string(&"a".repeat(LIMIT))
+}
+
+#[test]
+fn natives_raise_memory_error() {
+    let mut vm = limited();
+    vm.register_native("big", 0, big);
+    let source = r#"
+        try {
+            big();
+        } catch (e) {
+            println(instanceof(e, MemoryError));
+        }
+    "#;
+    assert_eq!(run(&mut vm, source).unwrap(), "true\n");
+}
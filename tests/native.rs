@@ -0,0 +1,93 @@
+use std::str;
+
+use locks::error::{Error, TypeError};
+use locks::vm::{Value, VmCtx, VM};
+use pretty_assertions::assert_eq;
+
+fn run(vm: &mut VM, source: &str) -> String {
+    let mut output = Vec::new();
+    if let Err(e) = vm.run(source, &mut output) {
+        let (e, _) = e.first().expect("received empty error");
+        output.extend(format!("{e}\n").bytes());
+    }
+    String::from_utf8(output).expect("invalid UTF-8 in output")
+}
+
+fn add(_: &mut VmCtx, args: &[Value]) -> Result<Value, Error> {
+    if !args[0].is_number() || !args[1].is_number() {
+        return Err(TypeError::InvalidType {
+            expected_type: "number".to_string(),
+            actual_type: "value".to_string(),
+        }
+        .into());
+    }
+    Ok((args[0].as_number() + args[1].as_number()).into())
+}
+
+fn greet(ctx: &mut VmCtx, args: &[Value]) -> Result<Value, Error> {
+    let name = ctx.as_str(args[0]).unwrap_or("stranger").to_string();
+    ctx.alloc_string(&format!("hello {name}"))
+}
+
+fn split(ctx: &mut VmCtx, args: &[Value]) -> Result<Value, Error> {
+    let string = ctx.as_str(args[0]).unwrap_or_default().to_string();
+    let parts = string.split(' ').map(|part| ctx.alloc_string(part)).collect::<Result<_, _>>()?;
+    ctx.alloc_list(parts)
+}
+
+#[test]
+fn call_host_native() {
+    let mut vm = VM::new();
+    vm.register_native("add", 2, add);
+    vm.register_native("greet", 1, greet);
+    vm.register_native("split", 1, split);
+
+    let output = run(
+        &mut vm,
+        r#"
+        println(add(1, 2));
+        println(greet("world"));
+        println(split("a b c"));
+        println(typeof(add));
+        println(add);
+        "#,
+    );
+    assert_eq!(output, "3\nhello world\n[\"a\", \"b\", \"c\"]\nfunction\n<native add>\n");
+}
+
+#[test]
+fn host_native_arity_mismatch() {
+    let mut vm = VM::new();
+    vm.register_native("add", 2, add);
+
+    let output = run(&mut vm, "add(1);");
+    assert_eq!(output, "TypeError: add() takes 2 arguments but 1 were given\n");
+}
+
+#[test]
+fn host_native_error_is_catchable() {
+    let mut vm = VM::new();
+    vm.register_native("add", 2, add);
+
+    let output = run(
+        &mut vm,
+        r#"
+        try {
+          add("a", 1);
+        } catch (e) {
+          println(typeof(e));
+        }
+        "#,
+    );
+    assert_eq!(output, "instance\n");
+}
+
+#[test]
+fn register_native_after_run() {
+    let mut vm = VM::new();
+    run(&mut vm, "");
+    vm.register_native("greet", 1, greet);
+
+    let output = run(&mut vm, r#"println(greet("again"));"#);
+    assert_eq!(output, "hello again\n");
+}
@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::{env, fs, process};
+
+use locks::vm::debug::SourceName;
+use locks::vm::profile::{FunctionProfile, LineProfile, Profile, Profiler};
+use locks::vm::VM;
+use pretty_assertions::assert_eq;
+
+const SOURCE: &str = r#"fn fib(n) {
+  if (n < 2) return n;
+  return fib(n - 1) + fib(n - 2);
+}
+
+fn fails() {
+  throw Error("unwound");
+}
+
+try {
+  fails();
+} catch (e) {}
+
+println(fib(10));
+"#;
+
+fn profile(source: &str) -> (Profile, String) {
+    let mut vm = VM::new();
+    vm.set_profiler(Some(Profiler::new()));
+    let mut output = Vec::new();
+    vm.run(source, &mut output).unwrap();
+    let profile = vm.take_profiler().unwrap().finish(&vm);
+    (profile, String::from_utf8(output).unwrap())
+}
+
+fn function<'a>(profile: &'a Profile, name: &str) -> (usize, &'a FunctionProfile) {
+    profile.functions.iter().enumerate().find(|(_, function)| function.name == name).unwrap()
+}
+
+/// The line in the script
+fn line(profile: &Profile, line: usize) -> &LineProfile {
+    profile
+        .lines
+        .iter()
+        .find(|profile| {
+            profile.location.as_ref().is_some_and(|location| {
+                location.source == SourceName::Script && location.line == line
+            })
+        })
+        .unwrap()
+}
+
+#[test]
+fn functions_are_counted_and_timed() {
+    let (profile, output) = profile(SOURCE);
+    assert_eq!(output, "55\n");
+
+    let (_, fib) = function(&profile, "fib");
+    assert_eq!(fib.calls, 177);
+    assert_eq!(fib.location.as_ref().unwrap().line, 2);
+    assert!(fib.self_time <= fib.total_time);
+    // Recursive calls are only timed once
+    assert!(fib.total_time <= profile.total_time);
+
+    let (_, fails) = function(&profile, "fails");
+    assert_eq!(fails.calls, 1);
+
+    let script = profile
+        .functions
+        .iter()
+        .find(|function| {
+            function.name.is_empty()
+                && function
+                    .location
+                    .as_ref()
+                    .is_some_and(|location| location.source == SourceName::Script)
+        })
+        .unwrap();
+    assert_eq!(script.calls, 1);
+    assert!(script.total_time >= fib.total_time);
+}
+
+#[test]
+fn lines_count_calls_made_from_them() {
+    let (profile, _) = profile(SOURCE);
+
+    assert_eq!(line(&profile, 2).calls, 0);
+    assert_eq!(line(&profile, 3).calls, 176);
+    assert_eq!(line(&profile, 11).calls, 1);
+    assert_eq!(line(&profile, 14).calls, 1);
+
+    let call = line(&profile, 14);
+    assert!(call.self_time <= call.total_time);
+    assert!(call.total_time >= function(&profile, "fib").1.total_time);
+}
+
+#[test]
+fn stacks_start_with_the_outermost_function() {
+    let (profile, _) = profile(SOURCE);
+    let (fib, _) = function(&profile, "fib");
+
+    let fib_stacks = profile.stacks.iter().filter(|(stack, _)| stack.contains(&fib)).count();
+    assert!(fib_stacks > 1);
+    for (stack, _) in &profile.stacks {
+        assert!(profile.functions[stack[0]].name.is_empty());
+        let fib_calls = stack.iter().skip_while(|&&idx| idx != fib);
+        assert!(fib_calls.into_iter().all(|&idx| idx == fib));
+    }
+}
+
+#[test]
+fn profile_command_writes_folded_stacks() {
+    let dir = env::temp_dir().join(format!("locks-profile-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("fib.locks");
+    fs::write(&path, SOURCE).unwrap();
+    let folded = dir.join("fib.folded");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_locks"))
+        .arg("profile")
+        .arg("--folded")
+        .arg(&folded)
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "55\n");
+
+    let summary = String::from_utf8(output.stderr).unwrap();
+    let fib = format!("fib ({}:2)", path.display());
+    assert!(summary.starts_with("total time: "), "{summary}");
+    assert!(
+        summary.lines().any(|line| line.ends_with(&fib) && line.contains(" 177 ")),
+        "{summary}"
+    );
+    assert!(summary.lines().any(|line| line.ends_with(&format!("{}:3", path.display()))));
+
+    let folded = fs::read_to_string(&folded).unwrap();
+    let script = format!("<script> ({}:1)", path.display());
+    for line in folded.lines() {
+        let (stack, micros) = line.rsplit_once(' ').unwrap();
+        // The core library runs before the script
+        assert!(stack.starts_with(&script) || stack.starts_with("<script> (<lib>:1)"), "{line}");
+        micros.parse::<u64>().unwrap();
+    }
+    assert!(folded.contains(&format!("{script};{fib};{fib} ")));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn profile_command_reports_errors() {
+    let path = PathBuf::from("res/examples/exception/uncaught_rethrow_runtime_error.locks");
+    let output =
+        Command::new(env!("CARGO_BIN_EXE_locks")).arg("profile").arg(&path).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("total time: "), "{stderr}");
+    assert!(stderr.contains("program exited with errors"), "{stderr}");
+}
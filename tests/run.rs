@@ -0,0 +1,43 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn run(args: &[&str], dir: &Path) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_locks"))
+        .arg("run")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("failed to run locks run");
+    (output.status.success(), String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn errors_are_reported_in_their_file() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("run_errors");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.locks"), "println(1);\nprintln(x);\n").unwrap();
+    fs::write(dir.join("boom.locks"), "package boom;\n\nfn boom() {\n  return 1 + nil;\n}\n")
+        .unwrap();
+    fs::write(dir.join("import.locks"), "import boom;\n\nboom:boom();\n").unwrap();
+
+    let (success, stderr) = run(&["main.locks"], &dir);
+    assert!(!success);
+    assert!(stderr.contains("main.locks:2:9"), "{stderr}");
+
+    let (success, stderr) = run(&["import.locks"], &dir);
+    assert!(!success);
+    assert!(stderr.contains("boom.locks:4:10"), "{stderr}");
+}
+
+#[test]
+fn errors_are_reported_when_heap_snapshot_fails() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("run_heap_snapshot");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.locks"), "println(x);\n").unwrap();
+
+    let (success, stderr) = run(&["--heap-snapshot", "missing/out.json", "main.locks"], &dir);
+    assert!(!success);
+    assert!(stderr.contains("main.locks:1:9"), "{stderr}");
+    assert!(stderr.contains("could not write heap snapshot"), "{stderr}");
+}
@@ -0,0 +1,76 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use pretty_assertions::assert_eq;
+
+fn snapshot(args: &[&str], dir: &Path) -> (bool, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_locks"))
+        .arg("snapshot")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("failed to run locks snapshot");
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+fn dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    dir
+}
+
+#[test]
+fn compare_and_update() {
+    let dir = dir("snapshot");
+    fs::write(dir.join("pass.locks"), "println(1); // out: 1\nprintln(nil + 1); // err: TypeError: unsupported operand type(s) for +: \"nil\" and \"number\"\n").unwrap();
+    let source = "println(\"a\"); // out: b\nprintln(\"c\");\n";
+    fs::write(dir.join("nested/fail.locks"), source).unwrap();
+
+    let (success, stdout, stderr) = snapshot(&["**/*.locks"], &dir);
+    assert!(!success);
+    assert!(stdout.contains("snapshot nested/fail.locks ... FAILED"), "{stdout}");
+    assert!(stdout.contains("snapshot pass.locks ... ok"), "{stdout}");
+    assert!(stdout.contains("expected stdout:\nb\n"), "{stdout}");
+    assert!(stdout.contains("actual stdout:\na\nc\n"), "{stdout}");
+    assert!(stderr.contains("1 of 2 snapshot(s) failed"), "{stderr}");
+
+    let (success, stdout, _) = snapshot(&["--update", "nested"], &dir);
+    assert!(success);
+    assert!(stdout.contains("snapshot nested/fail.locks ... updated"), "{stdout}");
+    assert_eq!(
+        fs::read_to_string(dir.join("nested/fail.locks")).unwrap(),
+        "println(\"a\"); // out: a\nprintln(\"c\");\n// out: c\n"
+    );
+
+    let (success, _, _) = snapshot(&["**/*.locks"], &dir);
+    assert!(success);
+}
+
+#[test]
+fn expected_errors() {
+    let dir = dir("snapshot_errors");
+
+    // A script that's expected to fail has to fail
+    fs::write(dir.join("main.locks"), "println(1); // out: 1\n// err: Error: oops\n").unwrap();
+    let (success, stdout, _) = snapshot(&["main.locks"], &dir);
+    assert!(!success);
+    assert!(stdout.contains("expected stderr:\nError: oops\n"), "{stdout}");
+
+    fs::write(
+        dir.join("main.locks"),
+        "println(1); // out: 1\nthrow \"oops\"; // err: Error: oops\n",
+    )
+    .unwrap();
+    let (success, _, _) = snapshot(&["main.locks"], &dir);
+    assert!(success);
+
+    let (success, _, stderr) = snapshot(&["missing/*.locks"], &dir);
+    assert!(!success);
+    assert!(stderr.contains("no files match: missing/*.locks"), "{stderr}");
+}
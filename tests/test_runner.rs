@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SOURCE: &str = r#"let count = 0;
+
+fn test_pass() {
+  count = count + 1;
+  assert_eq(count, 1);
+}
+
+// Each test has its own VM, so `count` starts at 0 again
+fn test_isolated() {
+  count = count + 1;
+  assert_eq(count, 1);
+}
+
+fn test_fail() {
+  println("before");
+  assert_eq("a", "b");
+}
+
+fn not_a_test() {
+  assert(false);
+}
+"#;
+
+fn test(args: &[&str], dir: &Path) -> (bool, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_locks"))
+        .arg("test")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("failed to run locks test");
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+fn dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn pass_and_fail() {
+    let dir = dir("test_runner");
+    fs::write(dir.join("main.locks"), SOURCE).unwrap();
+
+    let (success, stdout, stderr) = test(&["."], &dir);
+    assert!(!success);
+    assert!(stdout.contains("test ./main.locks::test_pass ... ok"), "{stdout}");
+    assert!(stdout.contains("test ./main.locks::test_isolated ... ok"), "{stdout}");
+    assert!(stdout.contains("test ./main.locks::test_fail ... FAILED"), "{stdout}");
+    assert!(!stdout.contains("not_a_test"), "{stdout}");
+    assert!(stdout.contains("before\n"), "{stdout}");
+    assert!(stdout.contains(r#"expected "b" but got "a""#), "{stdout}");
+    assert!(stdout.contains("./main.locks:16:3"), "{stdout}");
+    assert!(stdout.contains("test result: FAILED. 2 passed; 1 failed"), "{stdout}");
+    assert!(stderr.contains("1 test(s) failed and 0 file(s) could not be loaded"), "{stderr}");
+
+    fs::write(dir.join("main.locks"), SOURCE.replace(r#""b""#, r#""a""#)).unwrap();
+    let (success, stdout, _) = test(&["main.locks"], &dir);
+    assert!(success);
+    assert!(stdout.contains("test result: ok. 3 passed; 0 failed"), "{stdout}");
+}
+
+#[test]
+fn junit() {
+    let dir = dir("test_runner_junit");
+    fs::write(dir.join("main.locks"), SOURCE).unwrap();
+    fs::write(dir.join("broken.locks"), "fn test_broken( {}\n").unwrap();
+
+    let (success, stdout, _) = test(&["--junit", "report.xml", "."], &dir);
+    assert!(!success);
+    assert!(stdout.contains("./broken.locks ... ERROR"), "{stdout}");
+
+    let xml = fs::read_to_string(dir.join("report.xml")).unwrap();
+    assert!(
+        xml.contains(r#"<testsuites name="locks" tests="4" failures="1" errors="1">"#),
+        "{xml}"
+    );
+    assert!(
+        xml.contains(r#"<testsuite name="./main.locks" tests="3" failures="1" errors="0""#),
+        "{xml}"
+    );
+    assert!(
+        xml.contains(
+            r#"<testcase name="test_pass" classname="./main.locks" file="./main.locks" line="3""#
+        ),
+        "{xml}"
+    );
+    assert!(
+        xml.contains(r#"<failure message="AssertionError: expected &quot;b&quot; but got &quot;a&quot;" type="AssertionError">"#),
+        "{xml}"
+    );
+    assert!(xml.contains("<system-out>before\n</system-out>"), "{xml}");
+    assert!(
+        xml.contains(
+            r#"<error message="SyntaxError: unexpected &quot;{&quot;" type="SyntaxError">"#
+        ),
+        "{xml}"
+    );
+}
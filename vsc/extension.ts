@@ -9,6 +9,8 @@ import {
   window,
   env,
   Uri,
+  debug,
+  DebugAdapterExecutable,
 } from "vscode";
 import {
   LanguageClient,
@@ -143,6 +145,13 @@ export function activate(context: ExtensionContext) {
 
   tasks.registerTaskProvider("locks", new LocksTaskProvider());
 
+  context.subscriptions.push(
+    debug.registerDebugAdapterDescriptorFactory("locks", {
+      createDebugAdapterDescriptor: () =>
+        new DebugAdapterExecutable(locksBinPath, ["dap"]),
+    })
+  );
+
   lc.start();
 }
 